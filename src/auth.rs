@@ -0,0 +1,400 @@
+//! Authentication with origin servers.
+//!
+//! Credentials are given per host with `ClientBuilder::credentials`, or by a
+//! `Provider` set with `ClientBuilder::credential_provider`. They are only added
+//! to requests without an `Authorization` header.
+//!
+//! Basic and Bearer credentials are sent with every request. Digest credentials
+//! (RFC 7616, with the `digest` feature) answer the `WWW-Authenticate` challenge of
+//! a `401` response, the request being sent again once.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+
+use ::http::{HeaderMap, HeaderValue, Method};
+use hyper::Uri;
+use typed_headers::Token68;
+
+/// Credentials for a server.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Credentials(Kind);
+
+#[derive(Clone, PartialEq, Eq)]
+enum Kind {
+    Header(typed_headers::Credentials),
+    #[cfg(feature = "digest")]
+    Digest { username: String, password: String },
+}
+
+impl Credentials {
+    /// Basic credentials (RFC 7617), the user name can't contain a colon.
+    pub fn basic(username: &str, password: &str) -> io::Result<Self> {
+        typed_headers::Credentials::basic(username, password)
+            .map(Self::from)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))
+    }
+
+    /// A Bearer token (RFC 6750).
+    pub fn bearer(token: &str) -> io::Result<Self> {
+        Token68::new(token)
+            .map(|token| Self::from(typed_headers::Credentials::bearer(token)))
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid bearer token"))
+    }
+
+    /// Digest credentials (RFC 7616), sent in answer to a challenge with the MD5 or
+    /// SHA-256 algorithm and `qop=auth`.
+    #[cfg(feature = "digest")]
+    pub fn digest(username: impl Into<String>, password: impl Into<String>) -> Self {
+        Credentials(Kind::Digest {
+            username: username.into(),
+            password: password.into(),
+        })
+    }
+
+    /// The `Authorization` header sent up front, `None` for credentials answering a challenge.
+    pub(crate) fn preemptive(&self) -> Option<HeaderValue> {
+        match self.0 {
+            Kind::Header(ref credentials) => HeaderValue::from_str(&credentials.to_string()).ok(),
+            #[cfg(feature = "digest")]
+            Kind::Digest { .. } => None,
+        }
+    }
+}
+
+/// Credentials of any scheme, sent with every request.
+impl From<typed_headers::Credentials> for Credentials {
+    fn from(credentials: typed_headers::Credentials) -> Self {
+        Credentials(Kind::Header(credentials))
+    }
+}
+
+/// Secrets are left out.
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Kind::Header(ref credentials) => f.debug_tuple("Credentials").field(credentials.scheme()).finish(),
+            #[cfg(feature = "digest")]
+            Kind::Digest { ref username, .. } => f.debug_struct("Credentials").field("digest", username).finish(),
+        }
+    }
+}
+
+/// Gives the credentials for requests, for instance tokens that expire.
+pub trait Provider: Send + Sync + 'static {
+    /// The credentials for a request to `uri`, `None` to send it without.
+    fn credentials(&self, uri: &Uri) -> Option<Credentials>;
+
+    /// Called when the server answered `401` to `credentials`.
+    ///
+    /// When `credentials` then returns other credentials, the request is sent again
+    /// once with them. Does nothing by default.
+    fn rejected(&self, _uri: &Uri, _credentials: &Credentials) {}
+}
+
+impl<F> Provider for F
+    where F: Fn(&Uri) -> Option<Credentials> + Send + Sync + 'static
+{
+    fn credentials(&self, uri: &Uri) -> Option<Credentials> {
+        self(uri)
+    }
+}
+
+/// The credentials of a client.
+#[derive(Clone, Default)]
+pub(crate) struct Auth {
+    /// By lowercased host, with the port when it is not the default one.
+    pub(crate) hosts: HashMap<String, Credentials>,
+    pub(crate) provider: Option<Arc<dyn Provider>>,
+}
+
+impl Auth {
+    /// The credentials for a request to `uri`, the ones given for its host first.
+    pub(crate) fn credentials(&self, uri: &Uri) -> Option<Credentials> {
+        self.fixed(uri).or_else(|| self.provider.as_ref()?.credentials(uri))
+    }
+
+    /// The `Authorization` header of a request sent again after a `401` response with `headers`,
+    /// `None` when there are no other credentials to send.
+    #[cfg_attr(not(feature = "digest"), allow(unused_variables))]
+    pub(crate) fn answer(&self, credentials: &Credentials, method: &Method, uri: &Uri, headers: &HeaderMap) -> Option<HeaderValue> {
+        match credentials.0 {
+            Kind::Header(_) => {
+                // the credentials given for a host don't change
+                if self.fixed(uri).is_some() {
+                    return None;
+                }
+
+                let provider = self.provider.as_ref()?;
+                provider.rejected(uri, credentials);
+                provider.credentials(uri)
+                    .filter(|renewed| renewed != credentials)?
+                    .preemptive()
+            },
+            #[cfg(feature = "digest")]
+            Kind::Digest { ref username, ref password } => digest::answer(username, password, method, uri, headers),
+        }
+    }
+
+    fn fixed(&self, uri: &Uri) -> Option<Credentials> {
+        if self.hosts.is_empty() {
+            return None;
+        }
+
+        let host = crate::host(uri).to_ascii_lowercase();
+        let name = host.rsplit_once(':').map(|(name, _)| name).filter(|_| uri.port_u16().is_some());
+
+        self.hosts.get(&host)
+            .or_else(|| self.hosts.get(name?))
+            .cloned()
+    }
+}
+
+#[cfg(feature = "digest")]
+mod digest {
+    use ::http::{HeaderMap, HeaderValue, Method};
+    use ::http::header::WWW_AUTHENTICATE;
+    use hyper::Uri;
+    use md5::{Digest, Md5};
+    use sha2::Sha256;
+
+    /// A challenge of a `WWW-Authenticate` header (RFC 7235, section 4.1).
+    struct Challenge {
+        scheme: String,
+        params: Vec<(String, String)>,
+    }
+
+    impl Challenge {
+        fn param(&self, name: &str) -> Option<&str> {
+            self.params.iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                .map(|(_, value)| &value[..])
+        }
+    }
+
+    /// The `Authorization` header answering the strongest Digest challenge of `headers`.
+    pub(super) fn answer(username: &str, password: &str, method: &Method, uri: &Uri, headers: &HeaderMap) -> Option<HeaderValue> {
+        answer_with(username, password, method, uri, headers, &format!("{:016x}", rand::random::<u64>()))
+    }
+
+    fn answer_with(username: &str, password: &str, method: &Method, uri: &Uri, headers: &HeaderMap, cnonce: &str) -> Option<HeaderValue> {
+        let mut candidates: Vec<_> = challenges(headers).into_iter()
+            .filter(|challenge| challenge.scheme.eq_ignore_ascii_case("digest"))
+            .filter(|challenge| challenge.param("qop")
+                .map(|qop| qop.split(',').any(|qop| qop.trim().eq_ignore_ascii_case("auth")))
+                .unwrap_or(true))
+            .filter_map(|challenge| Some((algorithm(challenge.param("algorithm").unwrap_or("MD5"))?, challenge)))
+            .collect();
+
+        // SHA-256 is preferred, otherwise the first challenge is answered
+        candidates.sort_by_key(|&((sha256, _), _)| !sha256);
+        let ((sha256, session), challenge) = candidates.into_iter().next()?;
+
+        let hash = |data: String| if sha256 {
+            format!("{:x}", Sha256::digest(data.as_bytes()))
+        } else {
+            format!("{:x}", Md5::digest(data.as_bytes()))
+        };
+
+        let realm = challenge.param("realm").unwrap_or("");
+        let nonce = challenge.param("nonce")?;
+        let digest_uri = uri.path_and_query().map(|path| path.as_str()).unwrap_or("/");
+        let qop = challenge.param("qop").is_some();
+
+        let mut ha1 = hash(format!("{}:{}:{}", username, realm, password));
+        if session {
+            ha1 = hash(format!("{}:{}:{}", ha1, nonce, cnonce));
+        }
+
+        let ha2 = hash(format!("{}:{}", method.as_str(), digest_uri));
+
+        let response = if qop {
+            hash(format!("{}:{}:00000001:{}:auth:{}", ha1, nonce, cnonce, ha2))
+        } else {
+            // RFC 2069 compatibility
+            hash(format!("{}:{}:{}", ha1, nonce, ha2))
+        };
+
+        let mut header = format!("Digest username=\"{}\", realm=\"{}\", nonce=\"{}\", uri=\"{}\"",
+            quote(username), quote(realm), quote(nonce), quote(digest_uri));
+
+        if let Some(algorithm) = challenge.param("algorithm") {
+            header.push_str(&format!(", algorithm={}", algorithm));
+        }
+
+        if qop {
+            header.push_str(&format!(", qop=auth, nc=00000001, cnonce=\"{}\"", cnonce));
+        }
+
+        header.push_str(&format!(", response=\"{}\"", response));
+
+        if let Some(opaque) = challenge.param("opaque") {
+            header.push_str(&format!(", opaque=\"{}\"", quote(opaque)));
+        }
+
+        HeaderValue::from_str(&header).ok()
+    }
+
+    /// Whether the algorithm is SHA-256 and whether it is a session variant.
+    fn algorithm(name: &str) -> Option<(bool, bool)> {
+        match &name.to_ascii_uppercase()[..] {
+            "MD5" => Some((false, false)),
+            "MD5-SESS" => Some((false, true)),
+            "SHA-256" => Some((true, false)),
+            "SHA-256-SESS" => Some((true, true)),
+            _ => None,
+        }
+    }
+
+    /// The challenges of all `WWW-Authenticate` headers.
+    fn challenges(headers: &HeaderMap) -> Vec<Challenge> {
+        let mut challenges: Vec<Challenge> = Vec::new();
+
+        for value in headers.get_all(WWW_AUTHENTICATE) {
+            let mut rest = match value.to_str() {
+                Ok(value) => value,
+                Err(_) => continue,
+            };
+
+            loop {
+                rest = rest.trim_start_matches([' ', '\t', ',']);
+                if rest.is_empty() {
+                    break;
+                }
+
+                let end = rest.find([' ', '\t', ',', '=']).unwrap_or(rest.len());
+                let token = &rest[.. end];
+                rest = rest[end ..].trim_start_matches([' ', '\t']);
+
+                if !rest.starts_with('=') {
+                    challenges.push(Challenge {
+                        scheme: token.to_string(),
+                        params: Vec::new(),
+                    });
+                    continue;
+                }
+
+                rest = rest[1 ..].trim_start_matches([' ', '\t']);
+
+                let value = if rest.starts_with('"') {
+                    let (value, after) = unquote(rest);
+                    rest = after;
+                    value
+                } else {
+                    let end = rest.find(',').unwrap_or(rest.len());
+                    let value = rest[.. end].trim().to_string();
+                    rest = &rest[end ..];
+                    value
+                };
+
+                // the padding of a token68 is not a parameter
+                if let Some(challenge) = challenges.last_mut().filter(|_| !token.is_empty()) {
+                    challenge.params.push((token.to_string(), value));
+                }
+            }
+        }
+
+        challenges
+    }
+
+    /// The content of the quoted string `s` starts with, and what follows it.
+    fn unquote(s: &str) -> (String, &str) {
+        let mut value = String::new();
+        let mut chars = s.char_indices().skip(1);
+
+        while let Some((idx, c)) = chars.next() {
+            match c {
+                '\\' => value.extend(chars.next().map(|(_, c)| c)),
+                '"' => return (value, &s[idx + 1 ..]),
+                c => value.push(c),
+            }
+        }
+
+        (value, "")
+    }
+
+    fn quote(s: &str) -> String {
+        s.replace('\\', "\\\\").replace('"', "\\\"")
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use ::http::header::WWW_AUTHENTICATE;
+
+        use super::*;
+
+        const CNONCE: &str = "f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ";
+
+        fn challenge(values: &[&str]) -> HeaderMap {
+            let mut headers = HeaderMap::new();
+
+            for value in values {
+                headers.append(WWW_AUTHENTICATE, HeaderValue::from_str(value).unwrap());
+            }
+
+            headers
+        }
+
+        /// The parameters of the `Authorization` header answering `headers`.
+        fn answer_params(headers: &HeaderMap) -> Vec<(String, String)> {
+            let uri = "http://www.example.org/dir/index.html".parse().unwrap();
+            let answer = answer_with("Mufasa", "Circle of Life", &Method::GET, &uri, headers, CNONCE).unwrap();
+
+            // an answer has the syntax of a challenge
+            let mut challenges = challenges(&challenge(&[answer.to_str().unwrap()]));
+
+            assert_eq!(challenges.len(), 1);
+            assert_eq!(challenges[0].scheme, "Digest");
+            challenges.remove(0).params
+        }
+
+        fn param<'a>(params: &'a [(String, String)], name: &str) -> &'a str {
+            &params.iter().find(|(key, _)| key == name).unwrap().1
+        }
+
+        /// The example of RFC 7616, section 3.9.1.
+        const RFC_7616: [&str; 2] = [
+            r#"Digest realm="http-auth@example.org", qop="auth, auth-int", algorithm=SHA-256, nonce="7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v", opaque="FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS""#,
+            r#"Digest realm="http-auth@example.org", qop="auth, auth-int", algorithm=MD5, nonce="7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v", opaque="FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS""#,
+        ];
+
+        #[test]
+        fn answers_the_rfc_7616_example() {
+            let params = answer_params(&challenge(&RFC_7616));
+
+            assert_eq!(param(&params, "username"), "Mufasa");
+            assert_eq!(param(&params, "realm"), "http-auth@example.org");
+            assert_eq!(param(&params, "uri"), "/dir/index.html");
+            assert_eq!(param(&params, "algorithm"), "SHA-256");
+            assert_eq!(param(&params, "qop"), "auth");
+            assert_eq!(param(&params, "nc"), "00000001");
+            assert_eq!(param(&params, "cnonce"), CNONCE);
+            assert_eq!(param(&params, "opaque"), "FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS");
+            assert_eq!(param(&params, "response"), "753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1");
+
+            let params = answer_params(&challenge(&RFC_7616[1 ..]));
+            assert_eq!(param(&params, "algorithm"), "MD5");
+            assert_eq!(param(&params, "response"), "8ca523f5e9506fed4657c9700eebdbec");
+        }
+
+        #[test]
+        fn prefers_sha_256_over_earlier_challenges() {
+            let params = answer_params(&challenge(&[&format!("Basic realm=\"a\", {}", RFC_7616[1]), RFC_7616[0]]));
+
+            assert_eq!(param(&params, "algorithm"), "SHA-256");
+        }
+
+        #[test]
+        fn ignores_challenges_it_cant_answer() {
+            let uri = "http://www.example.org/".parse().unwrap();
+            let answer = |values: &[&str]| answer_with("Mufasa", "Circle of Life", &Method::GET, &uri, &challenge(values), CNONCE);
+
+            assert!(answer(&[r#"Basic realm="a""#]).is_none());
+            assert!(answer(&[r#"Digest realm="a", nonce="b", algorithm=SHA-512-256"#]).is_none());
+            assert!(answer(&[r#"Digest realm="a", nonce="b", qop="auth-int""#]).is_none());
+            assert!(answer(&[r#"Digest realm="a""#]).is_none());
+            assert!(answer(&[r#"Digest realm="a", nonce="b""#]).is_some());
+        }
+    }
+}
@@ -1,20 +1,65 @@
 use futures::stream::Stream;
 use std::task::{Poll, Context};
 use std::pin::Pin;
-use std::io;
+use std::{io, mem, str};
+use std::future::Future;
+use std::time::Duration;
 use futures::io::AsyncRead;
 use futures::compat::*;
+use crate::encoding::ContentDecoder;
+use crate::pool::{Conn, Key, Pool};
+use crate::timeout::{Deadline, Sleep, TimeoutPhase};
 
 
 static EMPTY: &[u8] = &[];
 
+/// Longest chunk-size line or trailer line accepted by the chunked decoder.
+const MAX_CHUNKED_LINE: usize = 4096;
+
+/// Maximum total size of the trailer section of a chunked body.
+const MAX_TRAILERS_SIZE: usize = 16 * 1024;
+
+/// How the end of a response body is determined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Length {
+    /// `Content-Length` bytes, or no body at all for `Exact(0)`.
+    Exact(usize),
+    /// `Transfer-Encoding: chunked`.
+    Chunked,
+    /// Everything until the server closes the connection.
+    CloseDelimited,
+}
+
+enum Reader {
+    Io(Box<dyn AsyncRead + Send + 'static>),
+    Conn(Compat01As03<Conn>, Option<(Pool, Key)>),
+}
+
+impl Reader {
+    fn poll_read(&mut self, ctx: &mut Context, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        match self {
+            Reader::Io(reader) => unsafe {Pin::new_unchecked(&mut **reader)}.poll_read(ctx, buf),
+            Reader::Conn(conn, _) => Pin::new(conn).poll_read(ctx, buf),
+        }
+    }
+}
+
+enum Decoder {
+    Length(usize),
+    Chunked(ChunkedDecoder),
+    Eof,
+}
+
 pub struct Body {
     drained: bool,
     rest: Option<Vec<u8>>,
     buf: Vec<u8>,
-    counter: usize,
-    content_length: Option<usize>,
-    reader: Box<dyn AsyncRead + Send + 'static>,
+    decoder: Decoder,
+    reader: Reader,
+    idle_timeout: Option<Duration>,
+    idle: Option<Sleep>,
+    deadline: Option<Sleep>,
+    content: Option<ContentDecoder>,
 }
 
 impl Body {
@@ -23,25 +68,149 @@ impl Body {
             drained: true,
             rest: None,
             buf: Vec::new(),
-            counter: 0,
-            content_length: None,
-            reader: Box::new(EMPTY)
+            decoder: Decoder::Length(0),
+            reader: Reader::Io(Box::new(EMPTY)),
+            idle_timeout: None,
+            idle: None,
+            deadline: None,
+            content: None,
         }
     }
-    
+
     pub fn new(reader: impl tokio_io::AsyncRead + Send + 'static, rest: Option<Vec<u8>>, content_length: Option<usize>) -> Self {
-        let mut buf = Vec::with_capacity(4096);
-        unsafe {buf.set_len(4096)};
+        let length = match content_length {
+            Some(len) => Length::Exact(len),
+            None => Length::CloseDelimited,
+        };
+
+        Self::with_reader(Reader::Io(Box::new(reader.compat())), rest, length)
+    }
+
+    /// A body read from a client connection.
+    ///
+    /// When `pool` is set, the connection is returned to it once the body was
+    /// read to its end, and dropped if the body is abandoned before that.
+    pub(crate) fn from_conn(conn: Conn, rest: Option<Vec<u8>>, length: Length, pool: Option<(Pool, Key)>) -> Self {
+        let pool = pool.filter(|_| length != Length::CloseDelimited);
+        let mut body = Self::with_reader(Reader::Conn(Compat01As03::new(conn), pool), rest, length);
+
+        if body.is_complete() {
+            body.release();
+        }
+
+        body
+    }
+
+    fn with_reader(reader: Reader, rest: Option<Vec<u8>>, length: Length) -> Self {
+        let rest = rest.filter(|rest| !rest.is_empty());
+
+        let decoder = match length {
+            Length::Exact(len) => Decoder::Length(len),
+            Length::Chunked => Decoder::Chunked(ChunkedDecoder::new()),
+            Length::CloseDelimited => Decoder::Eof,
+        };
 
         Body {
             drained: false,
             rest,
-            buf,
-            counter: 0,
-            content_length,
-            reader: Box::new(reader.compat())
+            buf: vec![0; 4096],
+            decoder,
+            reader,
+            idle_timeout: None,
+            idle: None,
+            deadline: None,
+            content: None,
+        }
+    }
+
+    /// Fails reading when no data arrives for `idle`, or once `deadline` passed.
+    ///
+    /// The idle time counts from now, the body is created once the head was received.
+    pub(crate) fn timeouts(self, idle: Option<Duration>, deadline: Option<Deadline>) -> Self {
+        Body {
+            idle_timeout: idle,
+            idle: idle_delay(idle),
+            deadline: deadline.map(Deadline::delay),
+            .. self
         }
     }
+
+    /// Decodes the body according to its `Content-Encoding`.
+    pub(crate) fn decoded(self, decoder: ContentDecoder) -> Self {
+        Body {
+            content: Some(decoder),
+            .. self
+        }
+    }
+
+    /// Polls the timeouts while waiting for the connection.
+    fn poll_timeouts(&mut self, ctx: &mut Context) -> Option<io::Error> {
+        if let Some(ref mut deadline) = self.deadline {
+            if let Poll::Ready(err) = Pin::new(deadline).poll(ctx) {
+                return Some(err.into());
+            }
+        }
+
+        if let Some(ref mut idle) = self.idle {
+            if let Poll::Ready(err) = Pin::new(idle).poll(ctx) {
+                return Some(err.into());
+            }
+        }
+
+        None
+    }
+
+    /// Trailer fields received after the last chunk of a chunked body.
+    ///
+    /// Returns `None` until the body has been read to its end, or when the body is not chunked.
+    pub fn trailers(&self) -> Option<&[(String, String)]> {
+        match self.decoder {
+            Decoder::Chunked(ref chunked) if chunked.is_done() => Some(&chunked.trailers),
+            _ => None,
+        }
+    }
+
+    fn is_complete(&self) -> bool {
+        match self.decoder {
+            Decoder::Length(left) => left == 0 && self.rest.is_none(),
+            Decoder::Chunked(ref chunked) => chunked.is_done(),
+            Decoder::Eof => false,
+        }
+    }
+
+    /// Gives the connection back to the pool, unless the server sent more than the body.
+    fn release(&mut self) {
+        let leftover = match self.decoder {
+            Decoder::Chunked(ref chunked) => !chunked.pending.is_empty(),
+            _ => false,
+        };
+
+        if let Reader::Conn(conn, Some((pool, key))) = mem::replace(&mut self.reader, Reader::Io(Box::new(EMPTY))) {
+            if !leftover {
+                pool.checkin(key, conn.into_inner());
+            }
+        }
+    }
+
+    fn finish(&mut self) -> Poll<Option<io::Result<Vec<u8>>>> {
+        self.drained = true;
+        self.release();
+        Poll::Ready(None)
+    }
+
+    fn fail(&mut self, err: io::Error) -> Poll<Option<io::Result<Vec<u8>>>> {
+        self.drained = true;
+        self.reader = Reader::Io(Box::new(EMPTY));
+        Poll::Ready(Some(Err(err)))
+    }
+}
+
+/// A body holding the given bytes, such as the one of a response made up by a middleware.
+impl From<Vec<u8>> for Body {
+    fn from(data: Vec<u8>) -> Self {
+        let len = data.len();
+        Self::with_reader(Reader::Io(Box::new(EMPTY)), Some(data), Length::Exact(len))
+    }
 }
 
 impl Stream for Body {
@@ -50,35 +219,475 @@ impl Stream for Body {
     fn poll_next(mut self: Pin<&mut Self>, ctx: &mut Context) -> Poll<Option<Self::Item>> {
         let this = &mut *self;
 
-        if this.drained {
-            return Poll::Ready(None);
+        loop {
+            let res = this.poll_raw(ctx);
+
+            let decoder = match this.content {
+                Some(ref mut decoder) => decoder,
+                None => return res,
+            };
+
+            let decoded = match res {
+                Poll::Ready(Some(Ok(chunk))) => decoder.decode(&chunk),
+                Poll::Ready(None) => {
+                    let decoded = decoder.finish();
+                    this.content = None;
+
+                    match decoded {
+                        Ok(ref chunk) if chunk.is_empty() => return Poll::Ready(None),
+                        decoded => return Poll::Ready(Some(decoded)),
+                    }
+                },
+                res => return res,
+            };
+
+            match decoded {
+                Ok(ref chunk) if chunk.is_empty() => continue,
+                Ok(chunk) => return Poll::Ready(Some(Ok(chunk))),
+                Err(err) => {
+                    this.content = None;
+                    return this.fail(err);
+                },
+            }
         }
+    }
+}
+
+impl Body {
+    /// Reads the next piece of the body as sent by the server.
+    fn poll_raw(&mut self, ctx: &mut Context) -> Poll<Option<io::Result<Vec<u8>>>> {
+        let this = self;
 
-        if let Some(vec) = this.rest.take() {
-            Poll::Ready(Some(Ok(vec)))
-        } else {
-            match unsafe {Pin::new_unchecked(&mut *this.reader)}.poll_read(ctx, &mut this.buf[0 ..]) {
-                Poll::Pending => Poll::Pending,
-                Poll::Ready(Ok(mut n)) => {
-                    this.counter += n;
+        loop {
+            if this.drained {
+                return Poll::Ready(None);
+            }
 
-                    if let Some(max) = this.content_length {
-                        if this.counter > max {
-                            n -= this.counter - max;
+            if let Some(mut vec) = this.rest.take() {
+                match this.decoder {
+                    Decoder::Length(ref mut left) => {
+                        if vec.len() > *left {
+                            // the server sent more than it announced, the connection can't be reused
+                            vec.truncate(*left);
+
+                            if let Reader::Conn(_, ref mut pool) = this.reader {
+                                pool.take();
+                            }
                         }
+
+                        *left -= vec.len();
+
+                        if !vec.is_empty() {
+                            return Poll::Ready(Some(Ok(vec)));
+                        }
+                    },
+                    Decoder::Chunked(ref mut chunked) => chunked.pending = vec,
+                    Decoder::Eof => return Poll::Ready(Some(Ok(vec))),
+                }
+            }
+
+            if let Decoder::Chunked(ref mut chunked) = this.decoder {
+                let mut out = Vec::new();
+
+                if let Err(err) = chunked.decode(&mut out) {
+                    return this.fail(err);
+                }
+
+                if !out.is_empty() {
+                    return Poll::Ready(Some(Ok(out)));
+                }
+            }
+
+            if this.is_complete() {
+                return this.finish();
+            }
+
+            let n = match this.reader.poll_read(ctx, &mut this.buf[0 ..]) {
+                Poll::Pending => match this.poll_timeouts(ctx) {
+                    Some(err) => return this.fail(err),
+                    None => return Poll::Pending,
+                },
+                Poll::Ready(Ok(n)) => {
+                    this.idle = idle_delay(this.idle_timeout);
+                    n
+                },
+                Poll::Ready(Err(err)) => return this.fail(err),
+            };
+
+            match this.decoder {
+                Decoder::Length(ref mut left) => {
+                    if n == 0 {
+                        return this.fail(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed before the end of the body"));
                     }
 
-                    if n > 0 {
-                        Poll::Ready(Some(Ok(this.buf[0 .. n].to_vec())))
-                    } else {
-                        Poll::Ready(None)
+                    let read = n;
+                    let n = n.min(*left);
+                    *left -= n;
+
+                    let chunk = this.buf[0 .. n].to_vec();
+
+                    if read > n {
+                        if let Reader::Conn(_, ref mut pool) = this.reader {
+                            pool.take();
+                        }
                     }
+
+                    if *left == 0 {
+                        this.release();
+                    }
+
+                    return Poll::Ready(Some(Ok(chunk)));
+                },
+                Decoder::Chunked(ref mut chunked) => {
+                    if n == 0 {
+                        return this.fail(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed before the last chunk"));
+                    }
+
+                    chunked.pending.extend_from_slice(&this.buf[0 .. n]);
                 },
-                Poll::Ready(Err(err)) => {
-                    this.drained = true;
-                    Poll::Ready(Some(Err(err)))
+                Decoder::Eof => {
+                    if n == 0 {
+                        return this.finish();
+                    }
+
+                    return Poll::Ready(Some(Ok(this.buf[0 .. n].to_vec())));
                 }
             }
         }
     }
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ChunkedState {
+    Size,
+    Data(usize),
+    DataEnd,
+    Trailers,
+    Done,
+}
+
+/// Strips `Transfer-Encoding: chunked` framing (RFC 7230, section 4.1).
+struct ChunkedDecoder {
+    state: ChunkedState,
+    pending: Vec<u8>,
+    trailers: Vec<(String, String)>,
+    trailers_size: usize,
+}
+
+impl ChunkedDecoder {
+    fn new() -> Self {
+        ChunkedDecoder {
+            state: ChunkedState::Size,
+            pending: Vec::new(),
+            trailers: Vec::new(),
+            trailers_size: 0,
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        self.state == ChunkedState::Done
+    }
+
+    /// Decodes as much of `pending` as possible, appending chunk data to `out`.
+    fn decode(&mut self, out: &mut Vec<u8>) -> io::Result<()> {
+        let mut pos = 0;
+
+        let res = loop {
+            match self.state {
+                ChunkedState::Size => match take_line(&self.pending[pos ..]) {
+                    Ok(Some((line, used))) => {
+                        let size = match parse_chunk_size(line) {
+                            Ok(size) => size,
+                            Err(err) => break Err(err),
+                        };
+                        pos += used;
+
+                        self.state = if size == 0 {
+                            ChunkedState::Trailers
+                        } else {
+                            ChunkedState::Data(size)
+                        };
+                    },
+                    Ok(None) => break Ok(()),
+                    Err(err) => break Err(err),
+                },
+                ChunkedState::Data(left) => {
+                    let n = left.min(self.pending.len() - pos);
+                    if n == 0 {
+                        break Ok(());
+                    }
+
+                    out.extend_from_slice(&self.pending[pos .. pos + n]);
+                    pos += n;
+
+                    self.state = if n == left {
+                        ChunkedState::DataEnd
+                    } else {
+                        ChunkedState::Data(left - n)
+                    };
+                },
+                ChunkedState::DataEnd => match take_line(&self.pending[pos ..]) {
+                    Ok(Some((line, used))) => {
+                        if !line.is_empty() {
+                            break Err(invalid_data("chunk data is longer than its declared size"));
+                        }
+
+                        pos += used;
+                        self.state = ChunkedState::Size;
+                    },
+                    Ok(None) => break Ok(()),
+                    Err(err) => break Err(err),
+                },
+                ChunkedState::Trailers => match take_line(&self.pending[pos ..]) {
+                    Ok(Some((line, used))) => {
+                        if self.trailers_size + used > MAX_TRAILERS_SIZE {
+                            break Err(invalid_data("chunked trailers are too large"));
+                        }
+
+                        if line.is_empty() {
+                            self.state = ChunkedState::Done;
+                        } else {
+                            match parse_trailer(line) {
+                                Ok(trailer) => self.trailers.push(trailer),
+                                Err(err) => break Err(err),
+                            }
+                        }
+
+                        pos += used;
+                        self.trailers_size += used;
+                    },
+                    Ok(None) => break Ok(()),
+                    Err(err) => break Err(err),
+                },
+                ChunkedState::Done => break Ok(()),
+            }
+        };
+
+        self.pending.drain(.. pos);
+
+        match res {
+            // hand out the data decoded so far, the error is hit again on the next call
+            Err(_) if !out.is_empty() => Ok(()),
+            res => res,
+        }
+    }
+}
+
+/// Returns the next line without its terminator, and the number of bytes it occupied.
+///
+/// Accepts a bare LF as line terminator, as recommended by RFC 7230, section 3.5.
+fn take_line(input: &[u8]) -> io::Result<Option<(&[u8], usize)>> {
+    match input.iter().position(|&b| b == b'\n') {
+        Some(idx) if idx > MAX_CHUNKED_LINE => Err(invalid_data("chunked line is too long")),
+        Some(idx) => {
+            let line = &input[.. idx];
+            let line = if line.last() == Some(&b'\r') { &line[.. idx - 1] } else { line };
+
+            Ok(Some((line, idx + 1)))
+        },
+        None if input.len() > MAX_CHUNKED_LINE => Err(invalid_data("chunked line is too long")),
+        None => Ok(None),
+    }
+}
+
+fn parse_chunk_size(line: &[u8]) -> io::Result<usize> {
+    // chunk-size [ chunk-ext ], where chunk-ext = *( BWS ";" BWS chunk-ext-name [ "=" chunk-ext-val ] )
+    let size = match line.iter().position(|&b| b == b';') {
+        Some(idx) => &line[.. idx],
+        None => line,
+    };
+
+    let size = trim(size);
+
+    if size.is_empty() || !size.iter().all(u8::is_ascii_hexdigit) {
+        return Err(invalid_data("invalid chunk size"));
+    }
+
+    let size = str::from_utf8(size).map_err(|_| invalid_data("invalid chunk size"))?;
+
+    usize::from_str_radix(size, 16).map_err(|_| invalid_data("chunk size is too large"))
+}
+
+fn parse_trailer(line: &[u8]) -> io::Result<(String, String)> {
+    let idx = line.iter().position(|&b| b == b':')
+        .ok_or_else(|| invalid_data("invalid chunked trailer"))?;
+
+    let name = &line[.. idx];
+    if name.is_empty() || name.iter().any(|&b| b == b' ' || b == b'\t') {
+        return Err(invalid_data("invalid chunked trailer"));
+    }
+
+    Ok((
+        String::from_utf8_lossy(name).to_lowercase(),
+        String::from_utf8_lossy(trim(&line[idx + 1 ..])).to_string(),
+    ))
+}
+
+fn trim(mut val: &[u8]) -> &[u8] {
+    while let Some((&b' ', rest)) | Some((&b'\t', rest)) = val.split_first() {
+        val = rest;
+    }
+
+    while let Some((&b' ', rest)) | Some((&b'\t', rest)) = val.split_last() {
+        val = rest;
+    }
+
+    val
+}
+
+/// The timer failing a body once no data arrived for `timeout`.
+fn idle_delay(timeout: Option<Duration>) -> Option<Sleep> {
+    Deadline::after(timeout, TimeoutPhase::BodyIdle).map(Deadline::delay)
+}
+
+fn invalid_data(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+    use futures::stream::StreamExt;
+
+    use super::*;
+
+    /// Decodes `input` fed in pieces of `step` bytes, giving the data and the decoder.
+    fn decode(input: &[u8], step: usize) -> io::Result<(Vec<u8>, ChunkedDecoder)> {
+        let mut decoder = ChunkedDecoder::new();
+        let mut data = Vec::new();
+
+        for piece in input.chunks(step).chain(Some(&[][..])) {
+            let mut out = Vec::new();
+            decoder.pending.extend_from_slice(piece);
+            decoder.decode(&mut out)?;
+            data.extend(out);
+        }
+
+        Ok((data, decoder))
+    }
+
+    type Trailers = Vec<(String, String)>;
+
+    /// Reads a whole chunked body, the data decoded and its trailers.
+    fn read(input: &'static [u8]) -> io::Result<(Vec<u8>, Trailers)> {
+        let mut body = Body::with_reader(Reader::Io(Box::new(input)), None, Length::Chunked);
+        let mut data = Vec::new();
+
+        while let Some(chunk) = block_on(body.next()) {
+            data.extend_from_slice(&chunk?);
+        }
+
+        Ok((data, body.trailers().expect("the body was read to its end").to_vec()))
+    }
+
+    #[test]
+    fn decodes_chunks() {
+        let input = b"5\r\nhello\r\n1;ext\r\n \r\nA ; name=\"va;lue\"\r\n0123456789\r\n0\r\n\r\n";
+
+        for step in 1 ..= input.len() {
+            let (data, decoder) = decode(input, step).unwrap();
+            assert!(decoder.is_done());
+            assert_eq!(data, b"hello 0123456789");
+        }
+    }
+
+    #[test]
+    fn accepts_bare_line_feeds() {
+        let (data, decoder) = decode(b"3\nabc\n0\n\n", 64).unwrap();
+        assert!(decoder.is_done());
+        assert_eq!(data, b"abc");
+    }
+
+    #[test]
+    fn rejects_invalid_sizes() {
+        for input in &[&b"zz\r\n"[..], b"\r\n", b"-1\r\n", b"0x5\r\n", b"5 5\r\n", b"ffffffffffffffffffff\r\n"] {
+            let err = decode(input, 64).err().expect("invalid chunk size");
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn rejects_chunks_longer_than_their_size() {
+        let err = decode(b"3\r\nabcd\r\n0\r\n\r\n", 64).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_long_lines() {
+        // a size line with an endless extension, with and without its end seen
+        let mut line = b"5;".to_vec();
+        line.resize(MAX_CHUNKED_LINE + 1, b'x');
+
+        assert!(decode(&line, 512).is_err());
+
+        line.extend_from_slice(b"\r\n");
+        assert!(decode(&line, 512).is_err());
+
+        // the longest line accepted
+        let mut line = b"5;".to_vec();
+        line.resize(MAX_CHUNKED_LINE, b'x');
+        line.extend_from_slice(b"\nhello\r\n0\r\n\r\n");
+        assert_eq!(decode(&line, 512).unwrap().0, b"hello");
+    }
+
+    #[test]
+    fn keeps_trailers() {
+        let (data, trailers) = read(b"5\r\nhello\r\n0\r\nExpires: never\r\nX-Checksum:\t abc \r\n\r\n").unwrap();
+
+        assert_eq!(data, b"hello");
+        assert_eq!(trailers, vec![
+            ("expires".to_string(), "never".to_string()),
+            ("x-checksum".to_string(), "abc".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn rejects_invalid_trailers() {
+        assert!(decode(b"0\r\nno colon\r\n\r\n", 64).is_err());
+        assert!(decode(b"0\r\nbad name: x\r\n\r\n", 64).is_err());
+    }
+
+    #[test]
+    fn limits_the_size_of_trailers() {
+        let mut input = b"0\r\n".to_vec();
+        let trailer = format!("x-pad: {}\r\n", "a".repeat(1000));
+
+        while input.len() + trailer.len() <= MAX_TRAILERS_SIZE {
+            input.extend_from_slice(trailer.as_bytes());
+        }
+
+        let mut fits = input.clone();
+        fits.extend_from_slice(b"\r\n");
+        assert!(decode(&fits, 4096).unwrap().1.is_done());
+
+        input.extend_from_slice(trailer.as_bytes());
+        input.extend_from_slice(b"\r\n");
+        let err = decode(&input, 4096).err().expect("trailers are too large");
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn hands_out_data_before_an_error() {
+        let mut decoder = ChunkedDecoder::new();
+        let mut out = Vec::new();
+        decoder.pending = b"3\r\nabc\r\nzz\r\n".to_vec();
+
+        decoder.decode(&mut out).unwrap();
+        assert_eq!(out, b"abc");
+        assert!(decoder.decode(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn fails_on_eof_in_a_chunk() {
+        let err = read(b"5\r\nhel").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn fails_on_eof_before_the_last_chunk() {
+        for input in &[&b"5\r\nhello\r\n"[..], b"5\r\nhello", b"5\r\nhello\r\n0\r\n", b"5\r\nhello\r\n0\r\nx: y\r\n", b""] {
+            let err = read(input).expect_err("the body is incomplete");
+            assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        }
+    }
+}
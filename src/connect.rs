@@ -1,12 +1,13 @@
+use std::any::Any;
 use std::error::Error as StdError;
 use std::{fmt, mem};
+use std::time::Duration;
 
 use bytes::{BufMut, Bytes, BytesMut};
 use futures_legacy::future::Future;
 use http::{uri, Uri};
 use tokio_io::{AsyncRead, AsyncWrite};
 pub use crate::http::HttpConnector;
-pub use hyper::client::connect::{Connected};
 
 type Result<T> = std::result::Result<T, Error>;
 
@@ -39,9 +40,24 @@ pub trait Connect: Send + Sync {
 #[derive(Clone, Debug)]
 pub struct Destination {
     pub(super) uri: Uri,
+    pub(super) connect_timeout: Option<Duration>,
+    pub(super) alpn: Alpn,
+}
+
+/// The protocol a connection is expected to speak.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Alpn {
+    Http1,
+    H2,
 }
 
 impl Destination {
+    /// Get the timeout for each attempt to connect to one of the resolved addresses.
+    #[inline]
+    pub fn connect_timeout(&self) -> Option<Duration> {
+        self.connect_timeout
+    }
+
     /// Get the protocol scheme.
     #[inline]
     pub fn scheme(&self) -> &str {
@@ -210,13 +226,82 @@ impl Destination {
         }
     }
 
-    /*
-    /// Returns whether this connection must negotiate HTTP/2 via ALPN.
+    /// Returns whether this connection must speak HTTP/2, negotiated via ALPN for
+    /// `https` or with prior knowledge for `http`.
     pub fn must_h2(&self) -> bool {
         match self.alpn {
             Alpn::Http1 => false,
             Alpn::H2 => true,
         }
     }
-    */
+}
+
+/// Extra information about a connection, returned by connectors along with the transport.
+pub struct Connected {
+    alpn: Alpn,
+    is_proxied: bool,
+    extra: Option<Box<dyn Any + Send + Sync>>,
+}
+
+impl Connected {
+    /// A connection with nothing special about it.
+    pub fn new() -> Connected {
+        Connected {
+            alpn: Alpn::Http1,
+            is_proxied: false,
+            extra: None,
+        }
+    }
+
+    /// Sets whether the connection goes to an HTTP proxy, which gets requests with absolute URIs.
+    pub fn proxy(self, is_proxied: bool) -> Connected {
+        Connected {
+            is_proxied,
+            .. self
+        }
+    }
+
+    /// Attaches any value to the connection, such as `HttpInfo`.
+    pub fn extra<T: Any + Send + Sync>(self, extra: T) -> Connected {
+        Connected {
+            extra: Some(Box::new(extra)),
+            .. self
+        }
+    }
+
+    /// Sets that HTTP/2 was negotiated via ALPN.
+    pub fn negotiated_h2(self) -> Connected {
+        Connected {
+            alpn: Alpn::H2,
+            .. self
+        }
+    }
+
+    pub fn is_proxied(&self) -> bool {
+        self.is_proxied
+    }
+
+    pub fn is_negotiated_h2(&self) -> bool {
+        self.alpn == Alpn::H2
+    }
+
+    /// The value attached with `extra`, if it is a `T`.
+    pub fn extra_ref<T: Any>(&self) -> Option<&T> {
+        self.extra.as_ref()?.downcast_ref()
+    }
+}
+
+impl Default for Connected {
+    fn default() -> Connected {
+        Connected::new()
+    }
+}
+
+impl fmt::Debug for Connected {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Connected")
+            .field("alpn", &self.alpn)
+            .field("is_proxied", &self.is_proxied)
+            .finish()
+    }
 }
\ No newline at end of file
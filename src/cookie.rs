@@ -0,0 +1,783 @@
+//! Cookie storage (RFC 6265).
+//!
+//! A `Jar` set with `ClientBuilder::cookie_store` keeps the cookies of every
+//! response and sends them back on the requests they match. The same jar can be
+//! shared between clients, and saved and loaded as JSON or in the Netscape
+//! `cookies.txt` format.
+
+use std::cmp::Reverse;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use hyper::Uri;
+use serde_json::{json, Value};
+
+/// The `SameSite` attribute of a cookie.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl SameSite {
+    fn as_str(self) -> &'static str {
+        match self {
+            SameSite::Strict => "Strict",
+            SameSite::Lax => "Lax",
+            SameSite::None => "None",
+        }
+    }
+}
+
+impl FromStr for SameSite {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<SameSite, ()> {
+        if s.eq_ignore_ascii_case("strict") {
+            Ok(SameSite::Strict)
+        } else if s.eq_ignore_ascii_case("lax") {
+            Ok(SameSite::Lax)
+        } else if s.eq_ignore_ascii_case("none") {
+            Ok(SameSite::None)
+        } else {
+            Err(())
+        }
+    }
+}
+
+/// A cookie kept in a `Jar`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cookie {
+    name: String,
+    value: String,
+    domain: String,
+    host_only: bool,
+    path: String,
+    expires: Option<SystemTime>,
+    secure: bool,
+    http_only: bool,
+    same_site: Option<SameSite>,
+}
+
+impl Cookie {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    /// The host the cookie was set by, or the domain it was set for.
+    pub fn domain(&self) -> &str {
+        &self.domain
+    }
+
+    /// Whether the cookie is only sent to the host that set it, and not to its subdomains.
+    pub fn is_host_only(&self) -> bool {
+        self.host_only
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// When the cookie expires, `None` for a session cookie.
+    pub fn expires(&self) -> Option<SystemTime> {
+        self.expires
+    }
+
+    /// Whether the cookie is only sent over `https`.
+    pub fn is_secure(&self) -> bool {
+        self.secure
+    }
+
+    pub fn is_http_only(&self) -> bool {
+        self.http_only
+    }
+
+    pub fn same_site(&self) -> Option<SameSite> {
+        self.same_site
+    }
+
+    fn is_expired(&self, now: SystemTime) -> bool {
+        self.expires.map(|expires| expires <= now).unwrap_or(false)
+    }
+
+    fn matches(&self, uri: &Uri, host: &str) -> bool {
+        let domain = if self.host_only {
+            host == self.domain
+        } else {
+            domain_match(host, &self.domain)
+        };
+
+        domain && path_match(uri.path(), &self.path) && (!self.secure || uri.scheme_str() == Some("https"))
+    }
+}
+
+/// A thread safe cookie store.
+#[derive(Default)]
+pub struct Jar {
+    // in creation order, which breaks ties when sending cookies
+    cookies: Mutex<Vec<Cookie>>,
+}
+
+impl Jar {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stores the cookie of a `Set-Cookie` header received from `uri`.
+    ///
+    /// Returns whether the cookie was accepted. An already expired cookie is
+    /// accepted and removes the cookie it replaces.
+    pub fn set_cookie(&self, uri: &Uri, set_cookie: &str) -> bool {
+        let cookie = match parse(uri, set_cookie, SystemTime::now()) {
+            Some(cookie) => cookie,
+            None => return false,
+        };
+
+        self.insert(cookie);
+        true
+    }
+
+    /// Value of the `Cookie` header for a request to `uri`, `None` when no cookie matches.
+    ///
+    /// Cookies with longer paths come first.
+    pub fn cookies(&self, uri: &Uri) -> Option<String> {
+        let host = host(uri)?;
+        let now = SystemTime::now();
+
+        let cookies = self.lock();
+        let mut matching: Vec<_> = cookies.iter()
+            .filter(|cookie| !cookie.is_expired(now) && cookie.matches(uri, &host))
+            .collect();
+
+        if matching.is_empty() {
+            return None;
+        }
+
+        matching.sort_by_key(|cookie| Reverse(cookie.path.len()));
+
+        let pairs: Vec<_> = matching.iter()
+            .map(|cookie| format!("{}={}", cookie.name, cookie.value))
+            .collect();
+
+        Some(pairs.join("; "))
+    }
+
+    /// A copy of every cookie that has not expired.
+    pub fn iter(&self) -> impl Iterator<Item = Cookie> {
+        let now = SystemTime::now();
+        let cookies: Vec<_> = self.lock().iter()
+            .filter(|cookie| !cookie.is_expired(now))
+            .cloned()
+            .collect();
+
+        cookies.into_iter()
+    }
+
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    /// Writes the cookies as a JSON array, session cookies included.
+    pub fn save_json(&self, writer: impl Write) -> io::Result<()> {
+        let cookies: Vec<Value> = self.iter()
+            .map(|cookie| json!({
+                "name": cookie.name,
+                "value": cookie.value,
+                "domain": cookie.domain,
+                "host_only": cookie.host_only,
+                "path": cookie.path,
+                "expires": cookie.expires.map(unix_time),
+                "secure": cookie.secure,
+                "http_only": cookie.http_only,
+                "same_site": cookie.same_site.map(SameSite::as_str),
+            }))
+            .collect();
+
+        serde_json::to_writer_pretty(writer, &cookies).map_err(io::Error::from)
+    }
+
+    /// Reads cookies written by `save_json`, expired cookies are skipped.
+    pub fn load_json(reader: impl Read) -> io::Result<Jar> {
+        let values: Vec<Value> = serde_json::from_reader(reader)?;
+        let now = SystemTime::now();
+        let jar = Jar::new();
+
+        for value in values {
+            let string = |key: &str| value.get(key).and_then(Value::as_str).map(str::to_string);
+            let flag = |key: &str| value.get(key).and_then(Value::as_bool).unwrap_or(false);
+
+            let cookie = Cookie {
+                name: string("name").ok_or_else(|| invalid_data("cookie without a name"))?,
+                value: string("value").unwrap_or_default(),
+                domain: string("domain").ok_or_else(|| invalid_data("cookie without a domain"))?,
+                host_only: flag("host_only"),
+                path: string("path").unwrap_or_else(|| "/".to_string()),
+                expires: value.get("expires").and_then(Value::as_u64).map(|secs| after(UNIX_EPOCH, secs)),
+                secure: flag("secure"),
+                http_only: flag("http_only"),
+                same_site: string("same_site").and_then(|same_site| same_site.parse().ok()),
+            };
+
+            if !cookie.is_expired(now) {
+                jar.insert(cookie);
+            }
+        }
+
+        Ok(jar)
+    }
+
+    /// Writes the cookies in the Netscape `cookies.txt` format used by curl and browsers.
+    ///
+    /// Session cookies are written with an expiry of `0`, and `HttpOnly` cookies with
+    /// the `#HttpOnly_` prefix.
+    pub fn save_netscape(&self, mut writer: impl Write) -> io::Result<()> {
+        writer.write_all(b"# Netscape HTTP Cookie File\n")?;
+
+        for cookie in self.iter() {
+            writeln!(writer, "{}{}{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                if cookie.http_only { "#HttpOnly_" } else { "" },
+                if cookie.host_only { "" } else { "." },
+                cookie.domain,
+                if cookie.host_only { "FALSE" } else { "TRUE" },
+                cookie.path,
+                if cookie.secure { "TRUE" } else { "FALSE" },
+                cookie.expires.map(unix_time).unwrap_or(0),
+                cookie.name,
+                cookie.value)?;
+        }
+
+        Ok(())
+    }
+
+    /// Reads cookies in the Netscape `cookies.txt` format, expired cookies are skipped.
+    pub fn load_netscape(reader: impl Read) -> io::Result<Jar> {
+        let now = SystemTime::now();
+        let jar = Jar::new();
+
+        for line in BufReader::new(reader).lines() {
+            let line = line?;
+            let (http_only, line) = match line.strip_prefix("#HttpOnly_") {
+                Some(line) => (true, line),
+                None => (false, &line[..]),
+            };
+
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let fields: Vec<_> = line.split('\t').collect();
+            if fields.len() < 6 {
+                return Err(invalid_data(format!("invalid cookie line {:?}", line)));
+            }
+
+            let expires: u64 = fields[4].trim().parse()
+                .map_err(|_| invalid_data(format!("invalid cookie expiry {:?}", fields[4])))?;
+
+            let cookie = Cookie {
+                name: fields[5].to_string(),
+                value: fields.get(6).copied().unwrap_or("").to_string(),
+                domain: fields[0].trim_start_matches('.').to_ascii_lowercase(),
+                host_only: !fields[1].eq_ignore_ascii_case("TRUE"),
+                path: fields[2].to_string(),
+                expires: Some(expires).filter(|&secs| secs != 0).map(|secs| after(UNIX_EPOCH, secs)),
+                secure: fields[3].eq_ignore_ascii_case("TRUE"),
+                http_only,
+                same_site: None,
+            };
+
+            if !cookie.is_expired(now) {
+                jar.insert(cookie);
+            }
+        }
+
+        Ok(jar)
+    }
+
+    /// Adds the cookie or replaces the one with the same name, domain and path,
+    /// keeping its place in the creation order.
+    fn insert(&self, cookie: Cookie) {
+        let now = SystemTime::now();
+        let mut cookies = self.lock();
+
+        let existing = cookies.iter()
+            .position(|c| c.name == cookie.name && c.domain == cookie.domain && c.path == cookie.path);
+
+        match existing {
+            Some(idx) if cookie.is_expired(now) => { cookies.remove(idx); },
+            Some(idx) => cookies[idx] = cookie,
+            None if cookie.is_expired(now) => (),
+            None => cookies.push(cookie),
+        }
+
+        cookies.retain(|cookie| !cookie.is_expired(now));
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<Cookie>> {
+        // the cookies are consistent at any time, a panic elsewhere doesn't corrupt them
+        self.cookies.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl fmt::Debug for Jar {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.lock().iter()).finish()
+    }
+}
+
+/// Parses a `Set-Cookie` header received from `uri` (RFC 6265, sections 5.2 and 5.3).
+fn parse(uri: &Uri, set_cookie: &str, now: SystemTime) -> Option<Cookie> {
+    let host = host(uri)?;
+    let https = uri.scheme_str() == Some("https");
+
+    let mut attributes = set_cookie.split(';');
+    let (name, value) = split_pair(attributes.next()?)?;
+    if name.is_empty() {
+        return None;
+    }
+
+    let mut max_age = None;
+    let mut expires = None;
+    let mut domain = None;
+    let mut path = None;
+    let mut secure = false;
+    let mut http_only = false;
+    let mut same_site = None;
+
+    for attribute in attributes {
+        let (key, value) = split_pair(attribute).unwrap_or((attribute.trim(), ""));
+
+        if key.eq_ignore_ascii_case("expires") {
+            expires = parse_date(value).or(expires);
+        } else if key.eq_ignore_ascii_case("max-age") {
+            max_age = value.parse::<i64>().ok().or(max_age);
+        } else if key.eq_ignore_ascii_case("domain") && !value.is_empty() {
+            domain = Some(value.trim_start_matches('.').to_ascii_lowercase());
+        } else if key.eq_ignore_ascii_case("path") {
+            path = Some(value).filter(|path| path.starts_with('/')).map(str::to_string);
+        } else if key.eq_ignore_ascii_case("secure") {
+            secure = true;
+        } else if key.eq_ignore_ascii_case("httponly") {
+            http_only = true;
+        } else if key.eq_ignore_ascii_case("samesite") {
+            same_site = value.parse().ok();
+        }
+    }
+
+    let expires = match max_age {
+        Some(secs) if secs <= 0 => Some(UNIX_EPOCH),
+        Some(secs) => Some(after(now, secs as u64)),
+        None => expires,
+    };
+
+    let (domain, host_only) = match domain {
+        // a public suffix only stands for the host itself
+        Some(domain) if psl::suffix_str(&domain) == Some(&domain[..]) => {
+            if domain != host {
+                return None;
+            }
+            (host, true)
+        },
+        Some(domain) if domain_match(&host, &domain) => (domain, false),
+        Some(_) => return None,
+        None => (host, true),
+    };
+
+    let path = path.unwrap_or_else(|| default_path(uri.path()));
+
+    // a cookie only sent over `https` can't be set over plain `http`,
+    // and the prefixes of cookie names promise some attributes
+    let prefix_ok = if name.starts_with("__Host-") {
+        secure && host_only && path == "/"
+    } else {
+        !name.starts_with("__Secure-") || secure
+    };
+
+    if (secure && !https) || !prefix_ok || (same_site == Some(SameSite::None) && !secure) {
+        return None;
+    }
+
+    Some(Cookie {
+        name: name.to_string(),
+        value: value.to_string(),
+        domain,
+        host_only,
+        path,
+        expires,
+        secure,
+        http_only,
+        same_site,
+    })
+}
+
+fn split_pair(pair: &str) -> Option<(&str, &str)> {
+    let idx = pair.find('=')?;
+    Some((pair[.. idx].trim(), pair[idx + 1 ..].trim()))
+}
+
+/// Parses the date of an `Expires` attribute (RFC 6265, section 5.1.1).
+///
+/// The tokens of a date are told apart by their shape, in any order, which accepts
+/// the IMF-fixdate as well as the Netscape `Wdy, DD-Mon-YY HH:MM:SS GMT` and asctime forms.
+fn parse_date(date: &str) -> Option<SystemTime> {
+    const MONTHS: [&str; 12] = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+
+    let is_delimiter = |c: char| matches!(c, '\t' | ' ' ..= '/' | ';' ..= '@' | '[' ..= '`' | '{' ..= '~');
+
+    let mut time = None;
+    let mut day = None;
+    let mut month = None;
+    let mut year = None;
+
+    for token in date.split(is_delimiter).filter(|token| !token.is_empty()) {
+        if time.is_none() {
+            if let Some(parsed) = parse_time(token) {
+                time = Some(parsed);
+                continue;
+            }
+        }
+
+        if day.is_none() {
+            if let Some((value, _)) = leading_digits(token, 1, 2) {
+                day = Some(value);
+                continue;
+            }
+        }
+
+        if month.is_none() {
+            let prefix = token.get(.. 3).map(str::to_ascii_lowercase);
+            if let Some(idx) = MONTHS.iter().position(|&month| prefix.as_deref() == Some(month)) {
+                month = Some(idx as u32 + 1);
+                continue;
+            }
+        }
+
+        if year.is_none() {
+            if let Some((value, _)) = leading_digits(token, 2, 4) {
+                year = Some(value);
+            }
+        }
+    }
+
+    let (hour, minute, second) = time?;
+    let (day, month, mut year) = (day?, month?, year?);
+
+    match year {
+        70 ..= 99 => year += 1900,
+        0 ..= 69 => year += 2000,
+        _ => (),
+    }
+
+    let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let days_in_month = match month {
+        2 if leap => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    };
+
+    if !(1 ..= days_in_month).contains(&day) || year < 1601 || hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+
+    // days since the epoch of the civil date, counting years from March
+    let (y, m) = if month <= 2 { (i64::from(year) - 1, month + 9) } else { (i64::from(year), month - 3) };
+    let era = y.div_euclid(400);
+    let year_of_era = y - era * 400;
+    let day_of_year = i64::from((153 * m + 2) / 5 + day - 1);
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+
+    let secs = days * 86_400 + i64::from(hour * 3600 + minute * 60 + second);
+
+    // a date before the epoch has passed as surely as the epoch itself
+    Some(after(UNIX_EPOCH, secs.max(0) as u64))
+}
+
+/// The `hh:mm:ss` time of a cookie date, each field of one or two digits.
+fn parse_time(token: &str) -> Option<(u32, u32, u32)> {
+    let (hour, rest) = leading_digits(token, 1, 2)?;
+    let (minute, rest) = leading_digits(rest.strip_prefix(':')?, 1, 2)?;
+    let (second, _) = leading_digits(rest.strip_prefix(':')?, 1, 2)?;
+
+    Some((hour, minute, second))
+}
+
+/// The value of the `min` to `max` digits starting `token`, and what follows them.
+fn leading_digits(token: &str, min: usize, max: usize) -> Option<(u32, &str)> {
+    let len = token.bytes().take_while(u8::is_ascii_digit).count();
+    if len < min || len > max {
+        return None;
+    }
+
+    Some((token[.. len].parse().ok()?, &token[len ..]))
+}
+
+/// The lowercased host of `uri`, without the brackets of an IPv6 address.
+fn host(uri: &Uri) -> Option<String> {
+    let host = uri.host()?.trim_start_matches('[').trim_end_matches(']');
+    Some(host.to_ascii_lowercase())
+}
+
+/// Domain matching of RFC 6265, section 5.1.3.
+fn domain_match(host: &str, domain: &str) -> bool {
+    host == domain || (
+        host.ends_with(domain)
+            && host[.. host.len() - domain.len()].ends_with('.')
+            && host.parse::<IpAddr>().is_err()
+    )
+}
+
+/// Path matching of RFC 6265, section 5.1.4.
+fn path_match(request_path: &str, cookie_path: &str) -> bool {
+    let request_path = if request_path.is_empty() { "/" } else { request_path };
+
+    request_path == cookie_path || (
+        request_path.starts_with(cookie_path)
+            && (cookie_path.ends_with('/') || request_path[cookie_path.len() ..].starts_with('/'))
+    )
+}
+
+/// The default path of a cookie, the directory of the request path.
+fn default_path(request_path: &str) -> String {
+    match request_path.rfind('/') {
+        Some(0) | None => "/".to_string(),
+        Some(idx) => request_path[.. idx].to_string(),
+    }
+}
+
+/// `secs` seconds after `time`, or the end of year 9999 when that can't be represented.
+fn after(time: SystemTime, secs: u64) -> SystemTime {
+    let far_future = UNIX_EPOCH + Duration::from_secs(253_402_300_799);
+
+    time.checked_add(Duration::from_secs(secs))
+        .map(|time| time.min(far_future))
+        .unwrap_or(far_future)
+}
+
+fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0)
+}
+
+fn invalid_data(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn uri(uri: &str) -> Uri {
+        uri.parse().unwrap()
+    }
+
+    /// A jar holding the cookies set by `uri`.
+    fn jar_of(uri: &str, set_cookies: &[&str]) -> Jar {
+        let jar = Jar::new();
+
+        for set_cookie in set_cookies {
+            assert!(jar.set_cookie(&self::uri(uri), set_cookie), "{} refused", set_cookie);
+        }
+
+        jar
+    }
+
+    fn cookies(jar: &Jar, uri: &str) -> Option<String> {
+        jar.cookies(&self::uri(uri))
+    }
+
+    #[test]
+    fn matches_domains() {
+        let jar = jar_of("http://www.example.com/", &["host=1", "domain=2; Domain=.Example.com"]);
+
+        assert_eq!(cookies(&jar, "http://www.example.com/").as_deref(), Some("host=1; domain=2"));
+        assert_eq!(cookies(&jar, "http://example.com/").as_deref(), Some("domain=2"));
+        assert_eq!(cookies(&jar, "http://a.www.example.com/").as_deref(), Some("domain=2"));
+        assert_eq!(cookies(&jar, "http://notexample.com/"), None);
+
+        // a public suffix or another site isn't a domain the host can set cookies for
+        let other = Jar::new();
+        assert!(!other.set_cookie(&uri("http://www.example.com/"), "a=1; Domain=com"));
+        assert!(!other.set_cookie(&uri("http://www.example.com/"), "a=1; Domain=example.org"));
+        assert!(!other.set_cookie(&uri("http://www.example.com/"), "a=1; Domain=a.www.example.com"));
+        assert!(other.iter().next().is_none());
+
+        // IP addresses only match themselves
+        let ip = jar_of("http://127.0.0.1/", &["a=1"]);
+        assert_eq!(cookies(&ip, "http://127.0.0.1/").as_deref(), Some("a=1"));
+        assert!(!ip.set_cookie(&uri("http://127.0.0.1/"), "b=2; Domain=0.0.1"));
+    }
+
+    #[test]
+    fn matches_paths() {
+        let jar = jar_of("http://example.com/docs/index.html", &["default=1", "root=2; Path=/", "docs=3; Path=/docs/"]);
+
+        assert_eq!(cookies(&jar, "http://example.com/docs/a").as_deref(), Some("docs=3; default=1; root=2"));
+        assert_eq!(cookies(&jar, "http://example.com/docs").as_deref(), Some("default=1; root=2"));
+        assert_eq!(cookies(&jar, "http://example.com/docsx").as_deref(), Some("root=2"));
+        assert_eq!(cookies(&jar, "http://example.com").as_deref(), Some("root=2"));
+
+        assert_eq!(default_path("/"), "/");
+        assert_eq!(default_path("/a"), "/");
+        assert_eq!(default_path("/a/b"), "/a");
+    }
+
+    #[test]
+    fn sends_secure_cookies_over_https_only() {
+        let jar = jar_of("https://example.com/", &["secure=1; Secure", "plain=2"]);
+
+        assert_eq!(cookies(&jar, "https://example.com/").as_deref(), Some("secure=1; plain=2"));
+        assert_eq!(cookies(&jar, "http://example.com/").as_deref(), Some("plain=2"));
+
+        // nor can they be set over plain http
+        assert!(!jar.set_cookie(&uri("http://example.com/"), "secure=3; Secure"));
+        assert!(!jar.set_cookie(&uri("http://example.com/"), "__Secure-a=1"));
+        assert!(!jar.set_cookie(&uri("https://example.com/"), "__Host-a=1; Secure; Path=/docs"));
+        assert!(jar.set_cookie(&uri("https://example.com/"), "__Host-a=1; Secure; Path=/"));
+    }
+
+    #[test]
+    fn expires_cookies() {
+        let now = SystemTime::now();
+        let set = |set_cookie| parse(&uri("http://example.com/"), set_cookie, now).unwrap().expires;
+
+        assert_eq!(set("a=1"), None);
+        assert_eq!(set("a=1; Max-Age=60"), Some(now + Duration::from_secs(60)));
+        assert_eq!(set("a=1; Max-Age=-1"), Some(UNIX_EPOCH));
+        assert_eq!(set("a=1; Expires=someday"), None);
+        assert_eq!(set("a=1; Expires=Sun, 06 Nov 1994 08:49:37 GMT"), Some(UNIX_EPOCH + Duration::from_secs(784_111_777)));
+        // Max-Age wins over Expires
+        assert_eq!(set("a=1; Max-Age=60; Expires=Sun, 06 Nov 1994 08:49:37 GMT"), Some(now + Duration::from_secs(60)));
+
+        let far = set(&format!("a=1; Max-Age={}", i64::MAX)).unwrap();
+        assert!(far > now + Duration::from_secs(1_000 * 365 * 24 * 3600));
+
+        let jar = jar_of("http://example.com/", &["a=1", "b=2"]);
+        assert!(jar.set_cookie(&uri("http://example.com/"), "a=1; Max-Age=0"));
+        assert_eq!(cookies(&jar, "http://example.com/").as_deref(), Some("b=2"));
+    }
+
+    #[test]
+    fn parses_cookie_dates() {
+        let date = |date| parse_date(date).map(unix_time);
+
+        for variant in &[
+            "Wed, 21 Oct 2015 07:28:00 GMT",
+            "Wed, 21-Oct-2015 07:28:00 GMT",
+            "Wednesday, 21-Oct-15 07:28:00 GMT",
+            "Wed Oct 21 07:28:00 2015",
+            "21 october 2015 7:28:0",
+            "2015 OCT 21 07:28:00 UTC",
+            "Wed,21-Oct-2015 07:28:00 GMT+0000",
+        ] {
+            assert_eq!(date(variant), Some(1_445_412_480), "{}", variant);
+        }
+
+        // two digit years
+        assert_eq!(date("Thu, 01-Jan-70 00:00:00 GMT"), Some(0));
+        assert_eq!(date("Tue, 01-Jan-69 00:00:00 GMT"), Some(3_124_224_000));
+        assert_eq!(date("Tue, 29 Feb 2000 12:00:00 GMT"), Some(951_825_600));
+        // dates before the epoch have passed as well
+        assert_eq!(date("Mon, 01 Jan 1900 00:00:00 GMT"), Some(0));
+
+        for invalid in &[
+            "",
+            "someday",
+            "Wed, 21 Oct 2015",
+            "Wed, 21 2015 07:28:00 GMT",
+            "Wed, 32 Oct 2015 07:28:00 GMT",
+            "Sat, 29 Feb 2015 07:28:00 GMT",
+            "Wed, 21 Oct 2015 24:00:00 GMT",
+            "Wed, 21 Oct 2015 07:60:00 GMT",
+            "Wed, 21 Oct 1600 07:28:00 GMT",
+            "Wed, 21 Oct 2015 07:28:000 GMT",
+        ] {
+            assert_eq!(date(invalid), None, "{}", invalid);
+        }
+
+        // a cookie whose date can be parsed is no session cookie
+        let expires = parse(&uri("http://example.com/"), "a=1; Expires=Wed, 21-Oct-2037 07:28:00 GMT", SystemTime::now()).unwrap().expires;
+        assert_eq!(expires.map(unix_time), Some(2_139_722_880));
+    }
+
+    #[test]
+    fn saves_and_loads_json() {
+        let jar = jar_of("https://www.example.com/a/b", &[
+            "session=1; HttpOnly; SameSite=Strict",
+            "persistent=2; Max-Age=3600; Domain=example.com; Path=/; Secure",
+        ]);
+
+        let mut json = Vec::new();
+        jar.save_json(&mut json).unwrap();
+        let loaded = Jar::load_json(&json[..]).unwrap();
+
+        let expires = |cookie: &Cookie| cookie.expires.map(unix_time);
+        let saved: Vec<_> = jar.iter().map(|cookie| (expires(&cookie), Cookie { expires: None, .. cookie })).collect();
+        let loaded: Vec<_> = loaded.iter().map(|cookie| (expires(&cookie), Cookie { expires: None, .. cookie })).collect();
+        assert_eq!(saved, loaded);
+    }
+
+    #[test]
+    fn loads_json() {
+        let json = format!(r#"[
+            {{"name": "a", "value": "1", "domain": "example.com", "expires": {}}},
+            {{"name": "expired", "value": "2", "domain": "example.com", "expires": 1}},
+            {{"name": "far", "value": "3", "domain": "example.com", "path": "/far", "expires": {}}}
+        ]"#, unix_time(SystemTime::now()) + 60, u64::MAX);
+
+        let jar = Jar::load_json(json.as_bytes()).unwrap();
+        assert_eq!(cookies(&jar, "http://www.example.com/far").as_deref(), Some("far=3; a=1"));
+
+        assert!(Jar::load_json(&br#"[{"value": "1", "domain": "example.com"}]"#[..]).is_err());
+        assert!(Jar::load_json(&b"{"[..]).is_err());
+    }
+
+    #[test]
+    fn saves_and_loads_netscape() {
+        let jar = jar_of("https://www.example.com/a/b", &[
+            "session=1; HttpOnly",
+            "persistent=2; Max-Age=3600; Domain=example.com; Path=/; Secure",
+        ]);
+
+        let mut txt = Vec::new();
+        jar.save_netscape(&mut txt).unwrap();
+
+        let expires = unix_time(SystemTime::now() + Duration::from_secs(3600));
+        let txt = String::from_utf8(txt).unwrap();
+        let lines: Vec<_> = txt.lines().collect();
+        assert_eq!(lines[0], "# Netscape HTTP Cookie File");
+        assert_eq!(lines[1], "#HttpOnly_www.example.com\tFALSE\t/a\tFALSE\t0\tsession\t1");
+        assert!(lines[2].starts_with(".example.com\tTRUE\t/\tTRUE\t"), "{}", lines[2]);
+        assert!(lines[2].ends_with("\tpersistent\t2"), "{}", lines[2]);
+
+        let loaded = Jar::load_netscape(txt.as_bytes()).unwrap();
+        let loaded: Vec<_> = loaded.iter().collect();
+        assert_eq!(loaded.len(), 2);
+        assert_eq!((loaded[0].name(), loaded[0].is_http_only(), loaded[0].expires()), ("session", true, None));
+        assert_eq!((loaded[1].domain(), loaded[1].is_host_only(), loaded[1].is_secure()), ("example.com", false, true));
+        assert!(loaded[1].expires().map(unix_time).unwrap() - expires <= 1);
+    }
+
+    #[test]
+    fn loads_netscape() {
+        let txt = format!("# comment\n\n\
+            example.com\tFALSE\t/\tFALSE\t1\texpired\t1\n\
+            .example.com\tTRUE\t/\tFALSE\t{}\tfar\t2\n\
+            example.com\tFALSE\t/\tFALSE\t0\tempty\n", u64::MAX);
+
+        let jar = Jar::load_netscape(txt.as_bytes()).unwrap();
+        assert_eq!(cookies(&jar, "http://example.com/").as_deref(), Some("far=2; empty="));
+        assert_eq!(cookies(&jar, "http://www.example.com/").as_deref(), Some("far=2"));
+
+        assert!(Jar::load_netscape(&b"example.com\tFALSE\t/\n"[..]).is_err());
+        assert!(Jar::load_netscape(&b"example.com\tFALSE\t/\tFALSE\tsoon\ta\t1\n"[..]).is_err());
+    }
+}
@@ -0,0 +1,328 @@
+//! Decoding of compressed response bodies.
+//!
+//! Each codec is compiled in with the cargo feature of the same name.
+
+#![cfg_attr(not(any(feature = "gzip", feature = "deflate", feature = "brotli")), allow(unused_imports, unused_variables, unreachable_code))]
+
+#[cfg(feature = "deflate")]
+use std::cmp;
+use std::io;
+#[cfg(any(feature = "gzip", feature = "brotli"))]
+use std::io::Write;
+use std::mem;
+
+#[cfg(feature = "gzip")]
+use flate2::write::GzDecoder;
+#[cfg(feature = "deflate")]
+use flate2::{Decompress, FlushDecompress, Status};
+
+/// The content codings the client accepts and decodes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Codings {
+    pub(crate) gzip: bool,
+    pub(crate) deflate: bool,
+    pub(crate) brotli: bool,
+}
+
+impl Default for Codings {
+    fn default() -> Self {
+        Codings {
+            gzip: cfg!(feature = "gzip"),
+            deflate: cfg!(feature = "deflate"),
+            brotli: cfg!(feature = "brotli"),
+        }
+    }
+}
+
+impl Codings {
+    /// Value of the `Accept-Encoding` request header, `None` when nothing is decoded.
+    pub(crate) fn accept_encoding(&self) -> Option<String> {
+        let codings: Vec<_> = [(self.gzip, "gzip"), (self.deflate, "deflate"), (self.brotli, "br")].iter()
+            .filter(|(enabled, _)| *enabled)
+            .map(|(_, name)| *name)
+            .collect();
+
+        if codings.is_empty() {
+            None
+        } else {
+            Some(codings.join(", "))
+        }
+    }
+
+    /// A decoder for the `Content-Encoding` of a response.
+    ///
+    /// Returns `None` for unknown or disabled codings, and when more than one coding was applied.
+    pub(crate) fn decoder(&self, content_encoding: &str) -> Option<ContentDecoder> {
+        let mut codings = content_encoding.split(',')
+            .map(str::trim)
+            .filter(|coding| !coding.is_empty() && !coding.eq_ignore_ascii_case("identity"));
+
+        let coding = codings.next()?;
+        if codings.next().is_some() {
+            return None;
+        }
+
+        let inner = match coding.to_ascii_lowercase().as_str() {
+            #[cfg(feature = "gzip")]
+            "gzip" | "x-gzip" if self.gzip => Inner::Gzip(GzDecoder::new(Vec::new())),
+            #[cfg(feature = "deflate")]
+            "deflate" if self.deflate => Inner::Deflate(Vec::new()),
+            #[cfg(feature = "brotli")]
+            "br" if self.brotli => Inner::Brotli(Box::new(brotli_decompressor::DecompressorWriter::new(Vec::new(), 4096))),
+            _ => return None,
+        };
+
+        Some(ContentDecoder { inner })
+    }
+}
+
+/// Decodes a response body as it is received.
+pub(crate) struct ContentDecoder {
+    inner: Inner,
+}
+
+enum Inner {
+    #[cfg(feature = "gzip")]
+    Gzip(GzDecoder<Vec<u8>>),
+    /// Waits for the first two bytes, which tell a zlib stream from a raw deflate stream.
+    #[cfg(feature = "deflate")]
+    Deflate(Vec<u8>),
+    #[cfg(feature = "deflate")]
+    Inflate(Inflate),
+    #[cfg(feature = "brotli")]
+    Brotli(Box<brotli_decompressor::DecompressorWriter<Vec<u8>>>),
+}
+
+impl ContentDecoder {
+    /// Decodes the next piece of the body, the output may be empty.
+    pub(crate) fn decode(&mut self, input: &[u8]) -> io::Result<Vec<u8>> {
+        match self.inner {
+            #[cfg(feature = "gzip")]
+            Inner::Gzip(ref mut decoder) => {
+                decoder.write_all(input)?;
+                Ok(mem::take(decoder.get_mut()))
+            },
+            #[cfg(feature = "deflate")]
+            Inner::Deflate(ref mut start) => {
+                start.extend_from_slice(input);
+                if start.len() < 2 {
+                    return Ok(Vec::new());
+                }
+
+                let start = mem::take(start);
+                // RFC 1950: CM is 8, and the header is a multiple of 31
+                let zlib = start[0] & 0x0f == 8 && (u16::from(start[0]) << 8 | u16::from(start[1])) % 31 == 0;
+
+                self.inner = Inner::Inflate(Inflate::new(zlib));
+
+                self.decode(&start)
+            },
+            #[cfg(feature = "deflate")]
+            Inner::Inflate(ref mut inflate) => inflate.decode(input),
+            #[cfg(feature = "brotli")]
+            Inner::Brotli(ref mut decoder) => {
+                decoder.write_all(input)?;
+                Ok(mem::take(decoder.get_mut()))
+            },
+        }
+    }
+
+    /// Flushes the end of the body, failing if the encoded stream is incomplete.
+    pub(crate) fn finish(&mut self) -> io::Result<Vec<u8>> {
+        match self.inner {
+            #[cfg(feature = "gzip")]
+            Inner::Gzip(ref mut decoder) => {
+                decoder.try_finish()?;
+                Ok(mem::take(decoder.get_mut()))
+            },
+            #[cfg(feature = "deflate")]
+            Inner::Deflate(ref start) if start.is_empty() => Ok(Vec::new()),
+            #[cfg(feature = "deflate")]
+            Inner::Deflate(_) => Err(io::Error::new(io::ErrorKind::UnexpectedEof, "deflate stream ended early")),
+            #[cfg(feature = "deflate")]
+            Inner::Inflate(ref inflate) if inflate.done => Ok(Vec::new()),
+            #[cfg(feature = "deflate")]
+            Inner::Inflate(_) => Err(io::Error::new(io::ErrorKind::UnexpectedEof, "deflate stream ended early")),
+            #[cfg(feature = "brotli")]
+            Inner::Brotli(ref mut decoder) => {
+                decoder.close()?;
+                Ok(mem::take(decoder.get_mut()))
+            },
+        }
+    }
+}
+
+/// A zlib or raw deflate stream, which unlike the decoders of `flate2::write` tells
+/// whether it reached its end.
+#[cfg(feature = "deflate")]
+struct Inflate {
+    decompress: Decompress,
+    done: bool,
+}
+
+#[cfg(feature = "deflate")]
+impl Inflate {
+    fn new(zlib: bool) -> Self {
+        Inflate {
+            decompress: Decompress::new(zlib),
+            done: false,
+        }
+    }
+
+    fn decode(&mut self, mut input: &[u8]) -> io::Result<Vec<u8>> {
+        let mut output = Vec::new();
+
+        // anything after the end of the stream is ignored
+        while !self.done {
+            output.reserve(cmp::max(input.len() * 4, 8192));
+
+            let (total_in, total_out) = (self.decompress.total_in(), self.decompress.total_out());
+            let status = self.decompress.decompress_vec(input, &mut output, FlushDecompress::None)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+            input = &input[(self.decompress.total_in() - total_in) as usize ..];
+            self.done = status == Status::StreamEnd;
+
+            // done once all the input is taken and the output has room left
+            let progress = self.decompress.total_in() != total_in || self.decompress.total_out() != total_out;
+            if !progress || (input.is_empty() && output.len() < output.capacity()) {
+                break;
+            }
+        }
+
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(any(feature = "gzip", feature = "deflate"))]
+    use std::io::Write;
+
+    #[cfg(feature = "gzip")]
+    use flate2::write::GzEncoder;
+    #[cfg(feature = "deflate")]
+    use flate2::write::{DeflateEncoder, ZlibEncoder};
+    #[cfg(any(feature = "gzip", feature = "deflate"))]
+    use flate2::Compression;
+
+    use super::*;
+
+    #[cfg(any(feature = "gzip", feature = "deflate"))]
+    fn text() -> Vec<u8> {
+        (0 .. 1000).flat_map(|n| format!("line {} of the body\n", n).into_bytes()).collect()
+    }
+
+    /// Decodes `encoded` fed in pieces of `piece` bytes.
+    #[cfg(any(feature = "gzip", feature = "deflate", feature = "brotli"))]
+    fn decode(content_encoding: &str, encoded: &[u8], piece: usize) -> io::Result<Vec<u8>> {
+        let mut decoder = Codings::default().decoder(content_encoding).expect("a decoder");
+        let mut decoded = Vec::new();
+
+        for chunk in encoded.chunks(piece) {
+            decoded.extend(decoder.decode(chunk)?);
+        }
+
+        decoded.extend(decoder.finish()?);
+        Ok(decoded)
+    }
+
+    /// Decodes `encoded` in pieces of many sizes, expecting `expected` each time.
+    #[cfg(any(feature = "gzip", feature = "deflate", feature = "brotli"))]
+    fn assert_decodes(content_encoding: &str, encoded: &[u8], expected: &[u8]) {
+        for &piece in &[1, 2, 7, 4096, encoded.len().max(1)] {
+            assert_eq!(decode(content_encoding, encoded, piece).unwrap(), expected, "{} in pieces of {}", content_encoding, piece);
+        }
+    }
+
+    #[cfg(feature = "gzip")]
+    fn gzip() -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&text()).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[cfg(feature = "deflate")]
+    fn zlib() -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&text()).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[cfg(feature = "deflate")]
+    fn raw_deflate() -> Vec<u8> {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&text()).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    /// The 10x10y test vector of the reference implementation.
+    #[cfg(feature = "brotli")]
+    const BROTLI: [u8; 12] = [0x1b, 0x13, 0x00, 0x00, 0xa4, 0xb0, 0xb2, 0xea, 0x81, 0x47, 0x02, 0x8a];
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn decodes_gzip() {
+        assert_decodes("gzip", &gzip(), &text());
+        assert_decodes("X-GZip", &gzip(), &text());
+    }
+
+    #[cfg(feature = "deflate")]
+    #[test]
+    fn decodes_zlib_and_raw_deflate() {
+        assert_decodes("deflate", &zlib(), &text());
+        assert_decodes("deflate", &raw_deflate(), &text());
+        assert_decodes("deflate", &[], &[]);
+    }
+
+    #[cfg(feature = "brotli")]
+    #[test]
+    fn decodes_brotli() {
+        assert_decodes("br", &BROTLI, b"XXXXXXXXXXYYYYYYYYYY");
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn fails_on_corrupt_gzip() {
+        let encoded = gzip();
+
+        assert!(decode("gzip", b"not a gzip stream at all", 4096).is_err());
+        assert!(decode("gzip", &encoded[.. encoded.len() / 2], 4096).is_err());
+    }
+
+    #[cfg(feature = "deflate")]
+    #[test]
+    fn fails_on_corrupt_deflate() {
+        assert!(decode("deflate", &[0xff, 0xff, 0xff, 0xff], 4096).is_err());
+        assert!(decode("deflate", &[0x78], 4096).is_err());
+
+        for encoded in &[zlib(), raw_deflate()] {
+            assert!(decode("deflate", &encoded[.. encoded.len() / 2], 4096).is_err());
+            assert!(decode("deflate", &encoded[.. encoded.len() - 1], 1).is_err());
+        }
+    }
+
+    #[cfg(feature = "brotli")]
+    #[test]
+    fn fails_on_corrupt_brotli() {
+        assert!(decode("br", b"not a brotli stream at all", 4096).is_err());
+        assert!(decode("br", &BROTLI[.. 6], 4096).is_err());
+    }
+
+    #[test]
+    fn accepts_enabled_codings_only() {
+        let none = Codings { gzip: false, deflate: false, brotli: false };
+        assert_eq!(none.accept_encoding(), None);
+        assert!(none.decoder("gzip").is_none());
+
+        let all = Codings { gzip: true, deflate: true, brotli: true };
+        assert_eq!(all.accept_encoding().as_deref(), Some("gzip, deflate, br"));
+        assert!(all.decoder("compress").is_none());
+        assert!(all.decoder("identity").is_none());
+        // stacked codings aren't decoded
+        assert!(all.decoder("gzip, br").is_none());
+
+        #[cfg(feature = "gzip")]
+        assert!(all.decoder(" identity, gzip ").is_some());
+    }
+}
@@ -0,0 +1,254 @@
+use std::error::Error as StdError;
+use std::fmt;
+use std::io;
+
+use ::http::StatusCode;
+use crate::httparse::ParseError;
+use crate::redirect::RedirectError;
+use crate::timeout::TimeoutPhase;
+
+/// The error type returned by `Client::request`.
+///
+/// Connectors only return `io::Error`, so they wrap an `Error` into one with
+/// `io::Error::from(Error)`. The client unwraps it again, which keeps the original
+/// kind however many connectors are stacked.
+#[derive(Debug)]
+pub enum Error {
+    /// The URI can't be requested: the scheme or the host is missing, or the scheme is not supported.
+    InvalidUrl(io::Error),
+    /// The host name could not be resolved.
+    Dns(io::Error),
+    /// No TCP connection could be established to any of the resolved addresses.
+    Connect(io::Error),
+    /// The TLS handshake with the server or the proxy failed.
+    Tls(native_tls::Error),
+    /// The proxy did not open a `CONNECT` tunnel.
+    ///
+    /// Holds the status code of the proxy response, if the proxy answered at all.
+    Tunnel(Option<StatusCode>, io::Error),
+    /// The response head is malformed.
+    Parse(ParseError),
+    /// A redirect was refused by the redirect policy.
+    Redirect(RedirectError),
+    /// A configured timeout elapsed in the given phase of the request.
+    Timeout(TimeoutPhase, io::Error),
+    /// The request body stream failed, or the response body could not be read.
+    Body(io::Error),
+    /// Writing the request or reading the response head failed.
+    Io(io::Error),
+}
+
+impl Error {
+    /// Whether the error happened before anything was sent to the server.
+    pub fn is_connect(&self) -> bool {
+        matches!(*self, Error::InvalidUrl(_) | Error::Dns(_) | Error::Connect(_) | Error::Tls(_) | Error::Tunnel(..))
+    }
+
+    /// Whether the error is a timeout.
+    pub fn is_timeout(&self) -> bool {
+        matches!(*self, Error::Timeout(..))
+    }
+
+    /// The phase of the request that timed out.
+    pub fn timeout_phase(&self) -> Option<TimeoutPhase> {
+        match *self {
+            Error::Timeout(phase, _) => Some(phase),
+            _ => None,
+        }
+    }
+
+    /// The status code the proxy answered a `CONNECT` request with.
+    pub fn tunnel_status(&self) -> Option<StatusCode> {
+        match *self {
+            Error::Tunnel(status, _) => status,
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::InvalidUrl(ref err) => write!(f, "invalid url: {}", err),
+            Error::Dns(ref err) => write!(f, "dns error: {}", err),
+            Error::Connect(ref err) => write!(f, "connect error: {}", err),
+            Error::Tls(ref err) => write!(f, "tls handshake error: {}", err),
+            Error::Tunnel(Some(status), ref err) => write!(f, "proxy tunnel error ({}): {}", status, err),
+            Error::Tunnel(None, ref err) => write!(f, "proxy tunnel error: {}", err),
+            Error::Parse(ref err) => write!(f, "response parse error: {}", err),
+            Error::Redirect(ref err) => write!(f, "redirect error: {}", err),
+            Error::Timeout(_, ref err) => write!(f, "timeout: {}", err),
+            Error::Body(ref err) => write!(f, "body error: {}", err),
+            Error::Io(ref err) => write!(f, "io error: {}", err),
+        }
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match *self {
+            Error::InvalidUrl(ref err)
+            | Error::Dns(ref err)
+            | Error::Connect(ref err)
+            | Error::Tunnel(_, ref err)
+            | Error::Timeout(_, ref err)
+            | Error::Body(ref err)
+            | Error::Io(ref err) => Some(err),
+            Error::Tls(ref err) => Some(err),
+            Error::Parse(ref err) => Some(err),
+            Error::Redirect(ref err) => Some(err),
+        }
+    }
+}
+
+/// Recovers an `Error` carried inside an `io::Error`, or wraps the `io::Error` as is.
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        let nested = err.get_ref()
+            .map(|inner| inner.is::<Error>() || inner.is::<io::Error>())
+            .unwrap_or(false);
+
+        if nested {
+            let inner = err.into_inner().expect("checked to hold an error");
+
+            return match inner.downcast::<Error>() {
+                Ok(err) => *err,
+                Err(inner) => Error::from(*inner.downcast::<io::Error>().expect("checked to hold an io::Error")),
+            };
+        }
+
+        Error::Io(err)
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(err: ParseError) -> Error {
+        Error::Parse(err)
+    }
+}
+
+impl From<RedirectError> for Error {
+    fn from(err: RedirectError) -> Error {
+        Error::Redirect(err)
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(err: Error) -> io::Error {
+        let kind = match err {
+            Error::InvalidUrl(_) => io::ErrorKind::InvalidInput,
+            Error::Parse(_) => io::ErrorKind::InvalidData,
+            Error::Timeout(..) => io::ErrorKind::TimedOut,
+            Error::Io(ref err) | Error::Body(ref err) | Error::Connect(ref err) => err.kind(),
+            _ => io::ErrorKind::Other,
+        };
+
+        io::Error::new(kind, err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn io(kind: io::ErrorKind) -> io::Error {
+        io::Error::new(kind, "inner")
+    }
+
+    /// `err` wrapped into an `io::Error`, as by a connector, and unwrapped by the client.
+    fn round_trip(err: Error) -> (io::ErrorKind, Error) {
+        let wrapped = io::Error::from(err);
+        (wrapped.kind(), Error::from(wrapped))
+    }
+
+    #[test]
+    fn keeps_the_error_through_io_errors() {
+        let (kind, err) = round_trip(Error::Timeout(TimeoutPhase::Head, io(io::ErrorKind::TimedOut)));
+        assert_eq!(kind, io::ErrorKind::TimedOut);
+        assert_eq!(err.timeout_phase(), Some(TimeoutPhase::Head));
+
+        let (kind, err) = round_trip(Error::InvalidUrl(io(io::ErrorKind::InvalidInput)));
+        assert_eq!(kind, io::ErrorKind::InvalidInput);
+        assert!(matches!(err, Error::InvalidUrl(_)));
+
+        let (kind, err) = round_trip(Error::Parse(ParseError::HeadTooLarge(10)));
+        assert_eq!(kind, io::ErrorKind::InvalidData);
+        assert!(matches!(err, Error::Parse(ParseError::HeadTooLarge(10))));
+
+        let (kind, err) = round_trip(Error::Redirect(RedirectError::TooManyHops(3)));
+        assert_eq!(kind, io::ErrorKind::Other);
+        assert!(matches!(err, Error::Redirect(RedirectError::TooManyHops(3))));
+
+        let (kind, err) = round_trip(Error::Tunnel(Some(StatusCode::PROXY_AUTHENTICATION_REQUIRED), io(io::ErrorKind::Other)));
+        assert_eq!(kind, io::ErrorKind::Other);
+        assert_eq!(err.tunnel_status(), Some(StatusCode::PROXY_AUTHENTICATION_REQUIRED));
+
+        // the kind of the inner error is kept for the errors of the connection
+        for &(wrap, kind) in &[
+            (Error::Connect as fn(io::Error) -> Error, io::ErrorKind::ConnectionRefused),
+            (Error::Body, io::ErrorKind::UnexpectedEof),
+            (Error::Io, io::ErrorKind::BrokenPipe),
+        ] {
+            let (wrapped, err) = round_trip(wrap(io(kind)));
+            assert_eq!(wrapped, kind);
+            assert_eq!(io::Error::from(err).kind(), kind);
+        }
+
+        let (_, err) = round_trip(Error::Dns(io(io::ErrorKind::NotFound)));
+        match err {
+            Error::Dns(inner) => assert_eq!(inner.kind(), io::ErrorKind::NotFound),
+            err => panic!("expected a DNS error, got {}", err),
+        }
+    }
+
+    #[test]
+    fn unwraps_errors_nested_in_several_io_errors() {
+        let nested = io::Error::new(io::ErrorKind::Other, io::Error::from(Error::Timeout(TimeoutPhase::Connect, io(io::ErrorKind::TimedOut))));
+        assert_eq!(Error::from(nested).timeout_phase(), Some(TimeoutPhase::Connect));
+
+        // an io::Error inside another one is unwrapped as well
+        let nested = io::Error::new(io::ErrorKind::Other, io(io::ErrorKind::ConnectionReset));
+        match Error::from(nested) {
+            Error::Io(err) => assert_eq!(err.kind(), io::ErrorKind::ConnectionReset),
+            err => panic!("expected an io error, got {}", err),
+        }
+
+        // any other error stays inside its io::Error
+        let other = io::Error::new(io::ErrorKind::Other, ParseError::TooManyHeaders(1));
+        match Error::from(other) {
+            Error::Io(err) => assert!(err.get_ref().unwrap().is::<ParseError>()),
+            err => panic!("expected an io error, got {}", err),
+        }
+    }
+
+    #[test]
+    fn classifies_errors() {
+        let timeout = Error::Timeout(TimeoutPhase::Total, io(io::ErrorKind::TimedOut));
+        assert!(timeout.is_timeout());
+        assert!(!timeout.is_connect());
+        assert_eq!(timeout.tunnel_status(), None);
+
+        for err in [
+            Error::InvalidUrl(io(io::ErrorKind::InvalidInput)),
+            Error::Dns(io(io::ErrorKind::NotFound)),
+            Error::Connect(io(io::ErrorKind::ConnectionRefused)),
+            Error::Tunnel(None, io(io::ErrorKind::UnexpectedEof)),
+        ] {
+            assert!(err.is_connect(), "{}", err);
+            assert!(!err.is_timeout(), "{}", err);
+            assert_eq!(err.timeout_phase(), None);
+        }
+
+        for err in [
+            Error::Parse(ParseError::WrongStatusReason),
+            Error::Redirect(RedirectError::TooManyHops(1)),
+            Error::Body(io(io::ErrorKind::Other)),
+            Error::Io(io(io::ErrorKind::TimedOut)),
+        ] {
+            assert!(!err.is_connect(), "{}", err);
+            assert!(!err.is_timeout(), "{}", err);
+        }
+
+        assert_eq!(Error::Tunnel(None, io(io::ErrorKind::Other)).tunnel_status(), None);
+    }
+}
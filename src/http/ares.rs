@@ -1,17 +1,35 @@
-use c_ares_resolver::{FutureResolver, CAresFuture};
+use c_ares_resolver::{FutureResolver, Options};
 use super::dns::{Resolve, Name};
-use std::net::IpAddr;
+use std::cmp;
+use std::net::{IpAddr, SocketAddr};
 use std::vec::IntoIter;
-use std::io::{Error, ErrorKind};
+use std::io::{self, Error};
 use std::mem;
 use std::sync::{Arc, Mutex};
 use std::collections::HashMap;
-use std::time::Instant;
-use futures_legacy::{Poll, Async, Future as LegacyFuture};
+use std::time::{Duration, Instant};
+use futures_legacy::{future, Poll, Async, Future as LegacyFuture};
+
+/// The address families looked up for a name.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum LookupFamily {
+    /// Only A records.
+    V4Only,
+    /// Only AAAA records.
+    V6Only,
+    /// A and AAAA records, queried in parallel. IPv6 addresses come first, the
+    /// connector falls back to the IPv4 ones.
+    #[default]
+    Both,
+}
+
+/// Addresses with the TTL of their record, in seconds.
+type Answer = Vec<(IpAddr, u32)>;
+type Lookup = Box<dyn LegacyFuture<Item = Answer, Error = c_ares::Error> + Send>;
 
 pub enum CAresResolverFuture {
-    FromResolver(CAresFuture<c_ares::AResults>, String, ResolverCache),
-    FromCache(Vec<IpAddr>),
+    FromResolver(Lookup, String, LookupFamily, ResolverCache),
+    FromCache(Result<Vec<IpAddr>, c_ares::Error>),
     Done,
 }
 
@@ -21,26 +39,29 @@ impl LegacyFuture for CAresResolverFuture {
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
         let state = match mem::replace(self, CAresResolverFuture::Done) {
-            CAresResolverFuture::FromResolver(mut fut, name, cache) => {
+            CAresResolverFuture::FromResolver(mut fut, name, family, cache) => {
                 match fut.poll() {
-                    Ok(Async::Ready(vals)) => {
-                        let items: Vec<_> = vals.into_iter().map(|res| IpAddr::V4(res.ipv4())).collect();
+                    Ok(Async::Ready(answer)) => {
+                        let ttl = answer.iter().map(|&(_, ttl)| ttl).min().unwrap_or(0);
+                        let items: Vec<_> = answer.into_iter().map(|(addr, _)| addr).collect();
 
-                        cache.add(name, items.clone());
+                        cache.add(name, family, items.clone(), Duration::from_secs(ttl.into()));
 
                         return Ok(Async::Ready(items.into_iter()));
                     },
 
                     Err(err) => {
-                        return Err(Error::new(ErrorKind::Other, Box::new(err)))
+                        cache.add_failure(name, family, err);
+
+                        return Err(Error::other(err))
                     },
 
-                    _ => CAresResolverFuture::FromResolver(fut, name, cache),
+                    _ => CAresResolverFuture::FromResolver(fut, name, family, cache),
                 }
             },
 
-            CAresResolverFuture::FromCache(vec) => {
-                return Ok(Async::Ready(vec.into_iter()))
+            CAresResolverFuture::FromCache(res) => {
+                return res.map(|vec| Async::Ready(vec.into_iter())).map_err(Error::other)
             },
 
             CAresResolverFuture::Done => {
@@ -48,33 +69,263 @@ impl LegacyFuture for CAresResolverFuture {
             }
         };
 
-        mem::replace(self, state);
+        *self = state;
 
         Ok(Async::NotReady)
     }
 }
 
-#[derive(Clone, Default)]
+/// The answers of a `CAresResolverImpl` or a `DohResolver`, shared by all the clones of the cache.
+///
+/// Addresses are kept for the smallest TTL of their records, within `min_ttl` and
+/// `max_ttl`. Names that don't exist or have no addresses, and servers failing to
+/// answer, are remembered for `negative_ttl`. Once there are `max_entries` names, the
+/// least recently used one makes room for a new one. Names are kept apart for each
+/// `LookupFamily`, resolvers looking up different families can share a cache.
+#[derive(Clone)]
 pub struct ResolverCache {
-    cache: Arc<Mutex<HashMap<String, (Vec<IpAddr>, Instant)>>>,
+    min_ttl: Duration,
+    max_ttl: Duration,
+    negative_ttl: Duration,
+    max_entries: usize,
+    inner: Arc<Mutex<CacheInner>>,
+}
+
+#[derive(Default)]
+struct CacheInner {
+    entries: HashMap<(String, LookupFamily), Entry>,
+    /// Increases with every use of an entry, to find the least recently used one.
+    clock: u64,
+    hits: u64,
+    misses: u64,
+}
+
+struct Entry {
+    answer: Result<Vec<IpAddr>, c_ares::Error>,
+    expires: Instant,
+    used: u64,
+}
+
+/// The use of a `ResolverCache` so far.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Lookups answered by the cache, negative answers included.
+    pub hits: u64,
+    /// Lookups sent to the name servers.
+    pub misses: u64,
+    /// Names in the cache, once for each family looked up. Some of them may have expired.
+    pub entries: usize,
 }
 
 impl ResolverCache {
-    pub fn lookup(&self, name: String) -> Option<Vec<IpAddr>> {
-        let lock = self.cache.lock().ok()?;
-        let (vec, ttl) = lock.get(&name)?;
+    /// A cache of 1024 names, keeping addresses for at most a day and failures for 5 seconds.
+    pub fn new() -> Self {
+        ResolverCache {
+            min_ttl: Duration::from_secs(0),
+            max_ttl: Duration::from_secs(24 * 60 * 60),
+            negative_ttl: Duration::from_secs(5),
+            max_entries: 1024,
+            inner: Default::default(),
+        }
+    }
+
+    /// Keeps addresses for at least `ttl`, even when their records say otherwise. Default is no minimum.
+    pub fn min_ttl(self, ttl: Duration) -> Self {
+        ResolverCache {
+            min_ttl: ttl,
+            .. self
+        }
+    }
+
+    /// Keeps addresses for at most `ttl`. Default is a day.
+    pub fn max_ttl(self, ttl: Duration) -> Self {
+        ResolverCache {
+            max_ttl: ttl,
+            .. self
+        }
+    }
+
+    /// Keeps NXDOMAIN, NODATA and SERVFAIL answers for `ttl`. Default is 5 seconds.
+    pub fn negative_ttl(self, ttl: Duration) -> Self {
+        ResolverCache {
+            negative_ttl: ttl,
+            .. self
+        }
+    }
+
+    /// Sets the number of names kept, `0` disables the cache. Default is 1024.
+    pub fn max_entries(self, max_entries: usize) -> Self {
+        ResolverCache {
+            max_entries,
+            .. self
+        }
+    }
+
+    /// Forgets all the names, the statistics are kept.
+    pub fn flush(&self) {
+        if let Ok(mut inner) = self.inner.lock() {
+            inner.entries.clear();
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        match self.inner.lock() {
+            Ok(inner) => CacheStats {
+                hits: inner.hits,
+                misses: inner.misses,
+                entries: inner.entries.len(),
+            },
+            Err(_) => CacheStats::default(),
+        }
+    }
+
+    /// The answer for `name` looked up in `family` if it has not expired, counted as a hit or a miss.
+    pub(super) fn lookup(&self, name: &str, family: LookupFamily) -> Option<Result<Vec<IpAddr>, c_ares::Error>> {
+        let mut inner = self.inner.lock().ok()?;
+        let inner = &mut *inner;
+        let now = Instant::now();
+        let key = (name.to_string(), family);
+
+        match inner.entries.get_mut(&key) {
+            Some(entry) if entry.expires > now => {
+                inner.clock += 1;
+                inner.hits += 1;
+                entry.used = inner.clock;
+
+                Some(entry.answer.clone())
+            },
+            Some(_) => {
+                inner.entries.remove(&key);
+                inner.misses += 1;
+                None
+            },
+            None => {
+                inner.misses += 1;
+                None
+            },
+        }
+    }
+
+    pub(super) fn add(&self, name: String, family: LookupFamily, addrs: Vec<IpAddr>, ttl: Duration) {
+        let ttl = cmp::min(cmp::max(ttl, self.min_ttl), self.max_ttl);
+        self.insert((name, family), Ok(addrs), ttl);
+    }
+
+    pub(super) fn add_failure(&self, name: String, family: LookupFamily, err: c_ares::Error) {
+        match err {
+            c_ares::Error::ENOTFOUND | c_ares::Error::ENODATA | c_ares::Error::ESERVFAIL => {
+                self.insert((name, family), Err(err), self.negative_ttl);
+            },
+            // timeouts, refused queries and the like say nothing about the name
+            _ => {},
+        }
+    }
+
+    fn insert(&self, key: (String, LookupFamily), answer: Result<Vec<IpAddr>, c_ares::Error>, ttl: Duration) {
+        if self.max_entries == 0 || ttl == Duration::from_secs(0) {
+            return;
+        }
+
+        let mut inner = match self.inner.lock() {
+            Ok(inner) => inner,
+            Err(_) => return,
+        };
+
+        let now = Instant::now();
 
-        if ttl < &Instant::now() {
-            Some(vec.clone())
-        } else {
-            None
+        if !inner.entries.contains_key(&key) && inner.entries.len() >= self.max_entries {
+            inner.entries.retain(|_, entry| entry.expires > now);
+
+            if inner.entries.len() >= self.max_entries {
+                let lru = inner.entries.iter()
+                    .min_by_key(|(_, entry)| entry.used)
+                    .map(|(key, _)| key.clone());
+
+                if let Some(lru) = lru {
+                    inner.entries.remove(&lru);
+                }
+            }
         }
-    } 
 
-    pub fn add(&self, name: String, addrs: Vec<IpAddr>) {
-        let mut lock = self.cache.lock().unwrap();
+        inner.clock += 1;
+        let used = inner.clock;
 
-        lock.insert(name, (addrs, Instant::now()));
+        inner.entries.insert(key, Entry {
+            answer,
+            expires: now + ttl,
+            used,
+        });
+    }
+}
+
+impl Default for ResolverCache {
+    fn default() -> Self {
+        ResolverCache::new()
+    }
+}
+
+/// How a `CAresResolverImpl` finds names, the settings left out come from `/etc/resolv.conf`.
+#[derive(Clone, Debug, Default)]
+pub struct ResolverOptions {
+    servers: Vec<SocketAddr>,
+    timeout: Option<Duration>,
+    tries: Option<u32>,
+    search: Option<Vec<String>>,
+    ndots: Option<u32>,
+    rotate: Option<bool>,
+}
+
+impl ResolverOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the name servers to ask, each one with its port.
+    pub fn servers(self, servers: Vec<SocketAddr>) -> Self {
+        Self {
+            servers,
+            .. self
+        }
+    }
+
+    /// Sets how long to wait for a server to answer before asking the next one.
+    pub fn timeout(self, timeout: Duration) -> Self {
+        Self {
+            timeout: Some(timeout),
+            .. self
+        }
+    }
+
+    /// Sets how many times each server is asked before giving up.
+    pub fn tries(self, tries: u32) -> Self {
+        Self {
+            tries: Some(tries),
+            .. self
+        }
+    }
+
+    /// Sets the domains appended to names with less than `ndots` dots.
+    pub fn search(self, domains: Vec<String>) -> Self {
+        Self {
+            search: Some(domains),
+            .. self
+        }
+    }
+
+    /// Sets the number of dots from which a name is looked up as is before trying the search domains.
+    pub fn ndots(self, ndots: u32) -> Self {
+        Self {
+            ndots: Some(ndots),
+            .. self
+        }
+    }
+
+    /// Sets whether the servers are asked in turn instead of always starting with the first one.
+    pub fn rotate(self, rotate: bool) -> Self {
+        Self {
+            rotate: Some(rotate),
+            .. self
+        }
     }
 }
 
@@ -83,15 +334,112 @@ pub type CAresResolver = Arc<CAresResolverImpl>;
 pub struct CAresResolverImpl {
     cache: ResolverCache,
     resolver: FutureResolver,
+    family: LookupFamily,
 }
 
 impl CAresResolverImpl {
-    pub fn new() -> Self {
-        CAresResolverImpl {
-            resolver: FutureResolver::new().unwrap(),
+    /// A resolver configured by `/etc/resolv.conf`.
+    pub fn new() -> io::Result<Self> {
+        CAresResolverImpl::with_options(ResolverOptions::default())
+    }
+
+    pub fn with_options(options: ResolverOptions) -> io::Result<Self> {
+        let mut opts = Options::new();
+
+        if let Some(timeout) = options.timeout {
+            opts.set_timeout(cmp::min(timeout.as_millis(), u32::MAX.into()) as u32);
+        }
+
+        if let Some(tries) = options.tries {
+            opts.set_tries(tries);
+        }
+
+        if let Some(ref search) = options.search {
+            opts.set_domains(&search.iter().map(String::as_str).collect::<Vec<_>>());
+        }
+
+        if let Some(ndots) = options.ndots {
+            opts.set_ndots(ndots);
+        }
+
+        match options.rotate {
+            Some(true) => { opts.set_rotate(); },
+            Some(false) => { opts.set_no_rotate(); },
+            None => {},
+        }
+
+        let resolver = FutureResolver::with_options(opts).map_err(|err| match err {
+            c_ares_resolver::Error::Io(err) => err,
+            c_ares_resolver::Error::Ares(err) => Error::other(err),
+        })?;
+
+        if !options.servers.is_empty() {
+            let servers: Vec<_> = options.servers.iter().map(SocketAddr::to_string).collect();
+            resolver.set_servers(&servers.iter().map(String::as_str).collect::<Vec<_>>())
+                .map_err(Error::other)?;
+        }
+
+        Ok(CAresResolverImpl {
+            resolver,
             cache: Default::default(),
+            family: LookupFamily::default(),
+        })
+    }
+
+    /// Sets the address families to look up, both by default.
+    pub fn lookup_family(self, family: LookupFamily) -> Self {
+        CAresResolverImpl {
+            family,
+            .. self
+        }
+    }
+
+    /// Sets the cache of the answers, a clone of `cache` can flush it or give its statistics.
+    pub fn cache(self, cache: ResolverCache) -> Self {
+        CAresResolverImpl {
+            cache,
+            .. self
         }
     }
+
+    fn query_a(&self, name: &str) -> Lookup {
+        Box::new(self.resolver.search_a(name)
+            .map(|results| results.iter().map(|res| (IpAddr::V4(res.ipv4()), ttl(res.ttl()))).collect()))
+    }
+
+    fn query_aaaa(&self, name: &str) -> Lookup {
+        Box::new(self.resolver.search_aaaa(name)
+            .map(|results| results.iter().map(|res| (IpAddr::V6(res.ipv6()), ttl(res.ttl()))).collect()))
+    }
+}
+
+fn ttl(ttl: i32) -> u32 {
+    cmp::max(ttl, 0) as u32
+}
+
+/// Merges the addresses of both families, a family failing when the other one has addresses is fine.
+fn merge(v6: Result<Answer, c_ares::Error>, v4: Result<Answer, c_ares::Error>) -> Result<Answer, c_ares::Error> {
+    let answer = match (v6, v4) {
+        (Ok(mut v6), Ok(v4)) => {
+            v6.extend(v4);
+            v6
+        },
+        (Ok(answer), Err(err)) | (Err(err), Ok(answer)) => {
+            if answer.is_empty() {
+                return Err(err);
+            }
+
+            answer
+        },
+        // a name without records of one family says nothing, the other error does
+        (Err(v6), Err(v4)) => return Err(if v4 == c_ares::Error::ENODATA { v6 } else { v4 }),
+    };
+
+    if answer.is_empty() {
+        return Err(c_ares::Error::ENODATA);
+    }
+
+    Ok(answer)
 }
 
 impl Resolve for CAresResolverImpl {
@@ -99,14 +447,204 @@ impl Resolve for CAresResolverImpl {
     type Future = CAresResolverFuture;
 
     fn resolve(&self, name: Name) -> Self::Future {
-        if let Some(values) = self.cache.lookup(name.as_str().to_string()) {
-            return CAresResolverFuture::FromCache(values);
+        if let Some(res) = self.cache.lookup(name.as_str(), self.family) {
+            return CAresResolverFuture::FromCache(res);
         }
 
+        let none = || -> Lookup { Box::new(future::ok(Vec::new())) };
+        let (v6, v4) = match self.family {
+            LookupFamily::V4Only => (none(), self.query_a(name.as_str())),
+            LookupFamily::V6Only => (self.query_aaaa(name.as_str()), none()),
+            LookupFamily::Both => (self.query_aaaa(name.as_str()), self.query_a(name.as_str())),
+        };
+
+        let lookup = v6.then(Ok::<_, c_ares::Error>)
+            .join(v4.then(Ok))
+            .and_then(|(v6, v4)| merge(v6, v4));
+
         CAresResolverFuture::FromResolver(
-            self.resolver.query_a(name.as_str()), 
-            name.as_str().to_string(), 
+            Box::new(lookup),
+            name.as_str().to_string(),
+            self.family,
             self.cache.clone()
         )
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    const BOTH: LookupFamily = LookupFamily::Both;
+
+    fn addrs(addrs: &[&str]) -> Vec<IpAddr> {
+        addrs.iter().map(|addr| addr.parse().unwrap()).collect()
+    }
+
+    /// How long the entry of `name` is kept from now on.
+    fn kept_for(cache: &ResolverCache, name: &str) -> Duration {
+        cache.inner.lock().unwrap().entries[&(name.to_string(), BOTH)].expires.saturating_duration_since(Instant::now())
+    }
+
+    fn is_about(kept: Duration, ttl: u64) -> bool {
+        kept <= Duration::from_secs(ttl) && kept > Duration::from_secs(ttl) - Duration::from_secs(1)
+    }
+
+    #[test]
+    fn clamps_ttls() {
+        let cache = ResolverCache::new()
+            .min_ttl(Duration::from_secs(10))
+            .max_ttl(Duration::from_secs(100));
+
+        cache.add("short".to_string(), BOTH, addrs(&["192.0.2.1"]), Duration::from_secs(1));
+        cache.add("long".to_string(), BOTH, addrs(&["192.0.2.2"]), Duration::from_secs(1000));
+        cache.add("within".to_string(), BOTH, addrs(&["192.0.2.3"]), Duration::from_secs(50));
+
+        assert!(is_about(kept_for(&cache, "short"), 10));
+        assert!(is_about(kept_for(&cache, "long"), 100));
+        assert!(is_about(kept_for(&cache, "within"), 50));
+    }
+
+    #[test]
+    fn forgets_expired_answers() {
+        let cache = ResolverCache::new();
+
+        // records with a TTL of zero aren't kept at all
+        cache.add("zero".to_string(), BOTH, addrs(&["192.0.2.1"]), Duration::from_secs(0));
+        assert!(cache.lookup("zero", BOTH).is_none());
+
+        cache.add("brief".to_string(), BOTH, addrs(&["192.0.2.1"]), Duration::from_millis(20));
+        assert_eq!(cache.lookup("brief", BOTH).unwrap().unwrap(), addrs(&["192.0.2.1"]));
+
+        thread::sleep(Duration::from_millis(30));
+        assert!(cache.lookup("brief", BOTH).is_none());
+        assert_eq!(cache.stats().entries, 0);
+    }
+
+    #[test]
+    fn caches_failures_about_the_name() {
+        let cache = ResolverCache::new().negative_ttl(Duration::from_secs(30));
+
+        for &err in &[c_ares::Error::ENOTFOUND, c_ares::Error::ENODATA, c_ares::Error::ESERVFAIL] {
+            cache.add_failure("missing".to_string(), BOTH, err);
+            assert_eq!(cache.lookup("missing", BOTH), Some(Err(err)));
+            assert!(is_about(kept_for(&cache, "missing"), 30));
+        }
+
+        for &err in &[c_ares::Error::ETIMEOUT, c_ares::Error::ECONNREFUSED, c_ares::Error::EREFUSED] {
+            cache.add_failure("unreachable".to_string(), BOTH, err);
+            assert!(cache.lookup("unreachable", BOTH).is_none());
+        }
+
+        let uncached = ResolverCache::new().negative_ttl(Duration::from_secs(0));
+        uncached.add_failure("missing".to_string(), BOTH, c_ares::Error::ENOTFOUND);
+        assert!(uncached.lookup("missing", BOTH).is_none());
+    }
+
+    #[test]
+    fn evicts_the_least_recently_used_name() {
+        let cache = ResolverCache::new().max_entries(2);
+        let ttl = Duration::from_secs(60);
+
+        cache.add("a".to_string(), BOTH, addrs(&["192.0.2.1"]), ttl);
+        cache.add("b".to_string(), BOTH, addrs(&["192.0.2.2"]), ttl);
+        assert!(cache.lookup("a", BOTH).is_some());
+
+        cache.add("c".to_string(), BOTH, addrs(&["192.0.2.3"]), ttl);
+        assert!(cache.lookup("a", BOTH).is_some());
+        assert!(cache.lookup("b", BOTH).is_none());
+        assert!(cache.lookup("c", BOTH).is_some());
+
+        // replacing a name makes no room
+        cache.add("c".to_string(), BOTH, addrs(&["192.0.2.4"]), ttl);
+        assert_eq!(cache.lookup("a", BOTH).unwrap().unwrap(), addrs(&["192.0.2.1"]));
+        assert_eq!(cache.lookup("c", BOTH).unwrap().unwrap(), addrs(&["192.0.2.4"]));
+    }
+
+    #[test]
+    fn evicts_expired_names_first() {
+        let cache = ResolverCache::new().max_entries(2);
+
+        cache.add("brief".to_string(), BOTH, addrs(&["192.0.2.1"]), Duration::from_millis(20));
+        cache.add("a".to_string(), BOTH, addrs(&["192.0.2.2"]), Duration::from_secs(60));
+        assert!(cache.lookup("brief", BOTH).is_some());
+
+        thread::sleep(Duration::from_millis(30));
+        cache.add("b".to_string(), BOTH, addrs(&["192.0.2.3"]), Duration::from_secs(60));
+
+        assert!(cache.lookup("a", BOTH).is_some());
+        assert!(cache.lookup("b", BOTH).is_some());
+    }
+
+    #[test]
+    fn counts_hits_and_misses() {
+        let cache = ResolverCache::new();
+        let clone = cache.clone();
+
+        assert!(cache.lookup("a", BOTH).is_none());
+        cache.add("a".to_string(), BOTH, addrs(&["192.0.2.1"]), Duration::from_secs(60));
+        cache.add_failure("missing".to_string(), BOTH, c_ares::Error::ENOTFOUND);
+        assert!(cache.lookup("a", BOTH).is_some());
+        assert!(cache.lookup("missing", BOTH).is_some());
+
+        // the clones share the answers and the statistics
+        assert!(clone.lookup("a", BOTH).is_some());
+        assert_eq!(clone.stats(), CacheStats { hits: 3, misses: 1, entries: 2 });
+
+        cache.flush();
+        assert!(cache.lookup("a", BOTH).is_none());
+        assert_eq!(cache.stats(), CacheStats { hits: 3, misses: 2, entries: 0 });
+
+        let disabled = ResolverCache::new().max_entries(0);
+        disabled.add("a".to_string(), BOTH, addrs(&["192.0.2.1"]), Duration::from_secs(60));
+        assert!(disabled.lookup("a", BOTH).is_none());
+        assert_eq!(disabled.stats(), CacheStats { hits: 0, misses: 1, entries: 0 });
+    }
+
+    #[test]
+    fn keeps_the_families_apart() {
+        let cache = ResolverCache::new();
+        let ttl = Duration::from_secs(60);
+
+        cache.add("a".to_string(), LookupFamily::V4Only, addrs(&["192.0.2.1"]), ttl);
+        cache.add_failure("a".to_string(), LookupFamily::V6Only, c_ares::Error::ENODATA);
+
+        assert_eq!(cache.lookup("a", LookupFamily::V4Only), Some(Ok(addrs(&["192.0.2.1"]))));
+        assert_eq!(cache.lookup("a", LookupFamily::V6Only), Some(Err(c_ares::Error::ENODATA)));
+        assert!(cache.lookup("a", BOTH).is_none());
+        assert_eq!(cache.stats().entries, 2);
+    }
+
+    fn answer(addrs: &[&str]) -> Answer {
+        self::addrs(addrs).into_iter().map(|addr| (addr, 60)).collect()
+    }
+
+    #[test]
+    fn merges_both_families() {
+        assert_eq!(merge(Ok(answer(&["2001:db8::1"])), Ok(answer(&["192.0.2.1"]))), Ok(answer(&["2001:db8::1", "192.0.2.1"])));
+        assert_eq!(merge(Ok(Vec::new()), Ok(answer(&["192.0.2.1"]))), Ok(answer(&["192.0.2.1"])));
+        assert_eq!(merge(Ok(Vec::new()), Ok(Vec::new())), Err(c_ares::Error::ENODATA));
+    }
+
+    #[test]
+    fn merges_a_failing_family() {
+        // the addresses of one family are enough
+        assert_eq!(merge(Err(c_ares::Error::ETIMEOUT), Ok(answer(&["192.0.2.1"]))), Ok(answer(&["192.0.2.1"])));
+        assert_eq!(merge(Ok(answer(&["2001:db8::1"])), Err(c_ares::Error::ESERVFAIL)), Ok(answer(&["2001:db8::1"])));
+
+        // without any, the failure tells why
+        assert_eq!(merge(Err(c_ares::Error::ETIMEOUT), Ok(Vec::new())), Err(c_ares::Error::ETIMEOUT));
+        assert_eq!(merge(Ok(Vec::new()), Err(c_ares::Error::ENOTFOUND)), Err(c_ares::Error::ENOTFOUND));
+    }
+
+    #[test]
+    fn merges_two_failures() {
+        // a family without records is less telling than any other failure
+        assert_eq!(merge(Err(c_ares::Error::ETIMEOUT), Err(c_ares::Error::ENODATA)), Err(c_ares::Error::ETIMEOUT));
+        assert_eq!(merge(Err(c_ares::Error::ENODATA), Err(c_ares::Error::ETIMEOUT)), Err(c_ares::Error::ETIMEOUT));
+        assert_eq!(merge(Err(c_ares::Error::ENODATA), Err(c_ares::Error::ENODATA)), Err(c_ares::Error::ENODATA));
+        assert_eq!(merge(Err(c_ares::Error::ENOTFOUND), Err(c_ares::Error::ENOTFOUND)), Err(c_ares::Error::ENOTFOUND));
+    }
+}
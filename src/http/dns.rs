@@ -1,10 +1,12 @@
-use std::{fmt, io, vec};
+use std::{error, fmt, io, vec};
+use std::str::FromStr;
 use std::net::{
     IpAddr, Ipv4Addr, Ipv6Addr,
     SocketAddr, ToSocketAddrs,
     SocketAddrV4, SocketAddrV6,
 };
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
 
 use futures_legacy::{try_ready, Async, Future, Poll};
 use futures_legacy::future::{Executor, ExecuteError};
@@ -37,6 +39,7 @@ impl<T: Resolve> Resolve for Arc<T> {
 /// A domain name to resolve into IP addresses.
 pub struct Name {
     host: String,
+    port: Option<u16>,
 }
 
 /// A resolver using blocking `getaddrinfo` calls in a threadpool.
@@ -59,6 +62,14 @@ impl Name {
     pub(super) fn new(host: String) -> Name {
         Name {
             host,
+            port: None,
+        }
+    }
+
+    pub(super) fn with_port(self, port: u16) -> Name {
+        Name {
+            port: Some(port),
+            .. self
         }
     }
 
@@ -66,6 +77,11 @@ impl Name {
     pub fn as_str(&self) -> &str {
         &self.host
     }
+
+    /// The port connected to once the name is resolved, when known.
+    pub fn port(&self) -> Option<u16> {
+        self.port
+    }
 }
 
 impl fmt::Debug for Name {
@@ -74,6 +90,33 @@ impl fmt::Debug for Name {
     }
 }
 
+impl FromStr for Name {
+    type Err = InvalidNameError;
+
+    fn from_str(host: &str) -> Result<Self, Self::Err> {
+        let valid = !host.is_empty() && host.bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'.' || b == b'_');
+
+        if valid {
+            Ok(Name::new(host.to_string()))
+        } else {
+            Err(InvalidNameError(()))
+        }
+    }
+}
+
+/// Error indicating a given string was not a valid domain name.
+#[derive(Debug)]
+pub struct InvalidNameError(());
+
+impl fmt::Display for InvalidNameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Not a valid domain name")
+    }
+}
+
+impl error::Error for InvalidNameError {}
+
 impl GaiResolver {
     /// Construct a new `GaiResolver`.
     ///
@@ -296,4 +339,293 @@ impl Future for TokioThreadpoolGaiFuture {
             Err(e) => Err(io::Error::new(io::ErrorKind::Other, e)),
         }
     }
-}
\ No newline at end of file
+}
+
+/// A resolver answering from a table of overrides, and asking another resolver for the other names.
+///
+/// Entries are like the ones of `curl --resolve`: a host name, optionally followed by a
+/// port, such as `example.com` or `example.com:443`. A wildcard such as `*.example.com`
+/// matches all the subdomains of `example.com`, and `*` matches every name. The most
+/// specific entry is used, an entry with the port of the connection before one without.
+///
+/// The clones of a resolver share its table, which can be changed while it is in use.
+#[derive(Clone)]
+pub struct OverrideResolver<R> {
+    inner: R,
+    overrides: Arc<RwLock<HashMap<String, Vec<IpAddr>>>>,
+}
+
+/// The future returned by `OverrideResolver`.
+pub struct OverrideFuture<F>(Answer<Option<Vec<IpAddr>>, F>);
+
+/// The addresses of an override, or the ones given by the inner resolver.
+pub struct OverrideAddrs<A>(Answer<vec::IntoIter<IpAddr>, A>);
+
+enum Answer<T, U> {
+    Override(T),
+    Inner(U),
+}
+
+impl<R> OverrideResolver<R> {
+    /// Wraps `inner`, without any override yet.
+    pub fn new(inner: R) -> Self {
+        OverrideResolver {
+            inner,
+            overrides: Default::default(),
+        }
+    }
+
+    /// Resolves `host`, which may have a port or be a wildcard, to `addrs` from now on.
+    ///
+    /// With no addresses, connections to `host` fail.
+    pub fn insert(&self, host: &str, addrs: Vec<IpAddr>) {
+        if let Ok(mut overrides) = self.overrides.write() {
+            overrides.insert(normalize(host), addrs);
+        }
+    }
+
+    /// Removes the override of `host`, given as it was inserted.
+    pub fn remove(&self, host: &str) -> Option<Vec<IpAddr>> {
+        self.overrides.write().ok()?.remove(&normalize(host))
+    }
+
+    /// Removes all the overrides.
+    pub fn clear(&self) {
+        if let Ok(mut overrides) = self.overrides.write() {
+            overrides.clear();
+        }
+    }
+
+    fn lookup(&self, name: &Name) -> Option<Vec<IpAddr>> {
+        let overrides = self.overrides.read().ok()?;
+        if overrides.is_empty() {
+            return None;
+        }
+
+        let host = normalize(name.as_str());
+
+        // the name itself, then the wildcards from the longest one to `*`
+        let mut candidates = vec![host.clone()];
+        let mut parent = &host[..];
+        while let Some(idx) = parent.find('.') {
+            parent = &parent[idx + 1 ..];
+            candidates.push(format!("*.{}", parent));
+        }
+        candidates.push("*".to_string());
+
+        candidates.iter()
+            .find_map(|candidate| {
+                name.port()
+                    .and_then(|port| overrides.get(&format!("{}:{}", candidate, port)))
+                    .or_else(|| overrides.get(candidate))
+            })
+            .cloned()
+    }
+}
+
+/// Host names are case insensitive, and the root label is implied.
+fn normalize(host: &str) -> String {
+    match host.rsplit_once(':') {
+        Some((name, port)) => format!("{}:{}", name.trim_end_matches('.'), port),
+        None => host.trim_end_matches('.').to_string(),
+    }.to_ascii_lowercase()
+}
+
+impl<R: Resolve> Resolve for OverrideResolver<R> {
+    type Addrs = OverrideAddrs<R::Addrs>;
+    type Future = OverrideFuture<R::Future>;
+
+    fn resolve(&self, name: Name) -> Self::Future {
+        match self.lookup(&name) {
+            Some(addrs) => OverrideFuture(Answer::Override(Some(addrs))),
+            None => OverrideFuture(Answer::Inner(self.inner.resolve(name))),
+        }
+    }
+}
+
+impl<R: fmt::Debug> fmt::Debug for OverrideResolver<R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("OverrideResolver")
+            .field("inner", &self.inner)
+            .field("overrides", &self.overrides)
+            .finish()
+    }
+}
+
+impl<F: Future<Error = io::Error>> Future for OverrideFuture<F> {
+    type Item = OverrideAddrs<F::Item>;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.0 {
+            Answer::Override(ref mut addrs) => {
+                let addrs = addrs.take().expect("polled after ready");
+                if addrs.is_empty() {
+                    return Err(io::Error::new(io::ErrorKind::NotFound, "no addresses for the host"));
+                }
+
+                Ok(Async::Ready(OverrideAddrs(Answer::Override(addrs.into_iter()))))
+            },
+            Answer::Inner(ref mut future) => {
+                let addrs = try_ready!(future.poll());
+                Ok(Async::Ready(OverrideAddrs(Answer::Inner(addrs))))
+            },
+        }
+    }
+}
+
+impl<F> fmt::Debug for OverrideFuture<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("OverrideFuture")
+    }
+}
+
+impl<A: Iterator<Item = IpAddr>> Iterator for OverrideAddrs<A> {
+    type Item = IpAddr;
+
+    fn next(&mut self) -> Option<IpAddr> {
+        match self.0 {
+            Answer::Override(ref mut addrs) => addrs.next(),
+            Answer::Inner(ref mut addrs) => addrs.next(),
+        }
+    }
+}
+
+impl<A> fmt::Debug for OverrideAddrs<A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("OverrideAddrs")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use futures_legacy::future::{self, FutureResult};
+
+    use super::*;
+
+    /// An inner resolver answering `192.0.2.100` to every name, which records the names asked.
+    #[derive(Clone, Default)]
+    struct Stub(Arc<Mutex<Vec<String>>>);
+
+    impl Resolve for Stub {
+        type Addrs = vec::IntoIter<IpAddr>;
+        type Future = FutureResult<Self::Addrs, io::Error>;
+
+        fn resolve(&self, name: Name) -> Self::Future {
+            self.0.lock().unwrap().push(name.as_str().to_string());
+            future::ok(addrs(&["192.0.2.100"]).into_iter())
+        }
+    }
+
+    fn addrs(addrs: &[&str]) -> Vec<IpAddr> {
+        addrs.iter().map(|addr| addr.parse().unwrap()).collect()
+    }
+
+    fn resolve(resolver: &OverrideResolver<Stub>, host: &str, port: Option<u16>) -> io::Result<Vec<IpAddr>> {
+        let name = Name::new(host.to_string());
+        let name = match port {
+            Some(port) => name.with_port(port),
+            None => name,
+        };
+
+        resolver.resolve(name).wait().map(Iterator::collect)
+    }
+
+    #[test]
+    fn falls_through_to_the_inner_resolver() {
+        let stub = Stub::default();
+        let resolver = OverrideResolver::new(stub.clone());
+
+        assert_eq!(resolve(&resolver, "example.com", None).unwrap(), addrs(&["192.0.2.100"]));
+
+        resolver.insert("example.com", addrs(&["192.0.2.1"]));
+        assert_eq!(resolve(&resolver, "example.com", None).unwrap(), addrs(&["192.0.2.1"]));
+        assert_eq!(resolve(&resolver, "example.org", None).unwrap(), addrs(&["192.0.2.100"]));
+        assert_eq!(resolve(&resolver, "www.example.com", None).unwrap(), addrs(&["192.0.2.100"]));
+
+        assert_eq!(*stub.0.lock().unwrap(), vec!["example.com", "example.org", "www.example.com"]);
+    }
+
+    #[test]
+    fn matches_wildcards() {
+        let resolver = OverrideResolver::new(Stub::default());
+        resolver.insert("*.example.com", addrs(&["192.0.2.1"]));
+        resolver.insert("*.api.example.com", addrs(&["192.0.2.2"]));
+
+        assert_eq!(resolve(&resolver, "www.example.com", None).unwrap(), addrs(&["192.0.2.1"]));
+        assert_eq!(resolve(&resolver, "a.b.example.com", None).unwrap(), addrs(&["192.0.2.1"]));
+        // the longest wildcard wins
+        assert_eq!(resolve(&resolver, "v1.api.example.com", None).unwrap(), addrs(&["192.0.2.2"]));
+        // a wildcard covers the subdomains only
+        assert_eq!(resolve(&resolver, "example.com", None).unwrap(), addrs(&["192.0.2.100"]));
+        assert_eq!(resolve(&resolver, "badexample.com", None).unwrap(), addrs(&["192.0.2.100"]));
+
+        // the name itself comes before any wildcard, and `*` after all of them
+        resolver.insert("www.example.com", addrs(&["192.0.2.3"]));
+        resolver.insert("*", addrs(&["192.0.2.4"]));
+        assert_eq!(resolve(&resolver, "www.example.com", None).unwrap(), addrs(&["192.0.2.3"]));
+        assert_eq!(resolve(&resolver, "mail.example.com", None).unwrap(), addrs(&["192.0.2.1"]));
+        assert_eq!(resolve(&resolver, "example.org", None).unwrap(), addrs(&["192.0.2.4"]));
+    }
+
+    #[test]
+    fn prefers_entries_with_the_port() {
+        let resolver = OverrideResolver::new(Stub::default());
+        resolver.insert("example.com", addrs(&["192.0.2.1"]));
+        resolver.insert("example.com:443", addrs(&["192.0.2.2"]));
+        resolver.insert("*.example.com:8080", addrs(&["192.0.2.3"]));
+
+        assert_eq!(resolve(&resolver, "example.com", Some(443)).unwrap(), addrs(&["192.0.2.2"]));
+        assert_eq!(resolve(&resolver, "example.com", Some(80)).unwrap(), addrs(&["192.0.2.1"]));
+        assert_eq!(resolve(&resolver, "example.com", None).unwrap(), addrs(&["192.0.2.1"]));
+
+        // a more specific name wins over the port
+        assert_eq!(resolve(&resolver, "www.example.com", Some(8080)).unwrap(), addrs(&["192.0.2.3"]));
+        assert_eq!(resolve(&resolver, "www.example.com", Some(443)).unwrap(), addrs(&["192.0.2.100"]));
+        assert_eq!(resolve(&resolver, "www.example.com", None).unwrap(), addrs(&["192.0.2.100"]));
+    }
+
+    #[test]
+    fn fails_names_without_addresses() {
+        let stub = Stub::default();
+        let resolver = OverrideResolver::new(stub.clone());
+        resolver.insert("blocked.example.com", Vec::new());
+
+        let err = resolve(&resolver, "blocked.example.com", Some(443)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(stub.0.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn ignores_case_and_the_root_label() {
+        let resolver = OverrideResolver::new(Stub::default());
+        resolver.insert("Example.COM.", addrs(&["192.0.2.1"]));
+        resolver.insert("*.Example.org.:443", addrs(&["192.0.2.2"]));
+
+        assert_eq!(resolve(&resolver, "example.com", None).unwrap(), addrs(&["192.0.2.1"]));
+        assert_eq!(resolve(&resolver, "EXAMPLE.com.", None).unwrap(), addrs(&["192.0.2.1"]));
+        assert_eq!(resolve(&resolver, "WWW.example.ORG.", Some(443)).unwrap(), addrs(&["192.0.2.2"]));
+    }
+
+    #[test]
+    fn removes_overrides() {
+        let resolver = OverrideResolver::new(Stub::default());
+        let shared = resolver.clone();
+        resolver.insert("example.com", addrs(&["192.0.2.1"]));
+        resolver.insert("example.com:443", addrs(&["192.0.2.2"]));
+        resolver.insert("example.org", addrs(&["192.0.2.3"]));
+
+        // clones share the table
+        assert_eq!(resolve(&shared, "example.org", None).unwrap(), addrs(&["192.0.2.3"]));
+
+        assert_eq!(shared.remove("EXAMPLE.com:443"), Some(addrs(&["192.0.2.2"])));
+        assert_eq!(shared.remove("example.com:443"), None);
+        assert_eq!(resolve(&resolver, "example.com", Some(443)).unwrap(), addrs(&["192.0.2.1"]));
+
+        resolver.clear();
+        assert_eq!(resolve(&shared, "example.com", None).unwrap(), addrs(&["192.0.2.100"]));
+        assert_eq!(resolve(&shared, "example.org", None).unwrap(), addrs(&["192.0.2.100"]));
+    }
+}
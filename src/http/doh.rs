@@ -0,0 +1,478 @@
+//! DNS over HTTPS (RFC 8484).
+//!
+//! Queries go through a `Client` of this crate. The host name of the endpoint is
+//! resolved to the bootstrap addresses given with it, and no other name is resolved
+//! by that client, so looking up names never needs the resolver being built.
+
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::Arc;
+use std::time::Duration;
+use std::vec::IntoIter;
+
+use futures::compat::Compat;
+use futures::future::{self, BoxFuture, FutureExt, TryFutureExt};
+use futures::stream::StreamExt;
+use futures_legacy::{future as future01, Future as Future01, Poll};
+use hyper::Uri;
+
+use crate::{Client, HttpsConnector, Method, Request, RequestBody};
+use super::HttpConnector;
+use super::ares::{LookupFamily, ResolverCache};
+use super::dns::{Name, OverrideResolver, Resolve};
+
+const A: u16 = 1;
+const AAAA: u16 = 28;
+
+/// DNS messages are at most 64 KiB.
+const MAX_MESSAGE: usize = 65_535;
+
+/// How queries are sent to the server.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DohFormat {
+    /// DNS messages posted as `application/dns-message` (RFC 8484).
+    #[default]
+    Wire,
+    /// `GET` requests answered with `application/dns-json`, as offered by Google and Cloudflare.
+    #[cfg(feature = "json")]
+    Json,
+}
+
+/// A resolver sending its queries to a DNS over HTTPS server.
+///
+/// Answers are cached for the TTL of their records, failures as negative answers.
+/// The clones of a resolver share their client and cache.
+#[derive(Clone)]
+pub struct DohResolver {
+    client: Arc<Client<HttpsConnector<HttpConnector<OverrideResolver<Unresolved>>>>>,
+    endpoint: Uri,
+    format: DohFormat,
+    family: LookupFamily,
+    timeout: Duration,
+    cache: ResolverCache,
+}
+
+/// The future returned by `DohResolver`.
+pub struct DohFuture(Compat<BoxFuture<'static, io::Result<IntoIter<IpAddr>>>>);
+
+/// Why a query got no addresses.
+enum Failure {
+    /// The server answered, with the error code c-ares would give.
+    Dns(c_ares::Error),
+    /// The server could not be asked.
+    Io(io::Error),
+}
+
+/// Addresses with the TTL of their record, in seconds.
+type Answer = Vec<(IpAddr, u32)>;
+
+impl DohResolver {
+    /// A resolver asking `endpoint`, such as `https://dns.example/dns-query`, whose host
+    /// name is resolved to `bootstrap`.
+    pub fn new(endpoint: Uri, bootstrap: Vec<IpAddr>) -> io::Result<Self> {
+        let host = endpoint.host()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "the endpoint has no host"))?;
+
+        let resolver = OverrideResolver::new(Unresolved);
+        resolver.insert(host, bootstrap);
+
+        let connector = HttpsConnector::new_with_resolver(resolver).map_err(io::Error::other)?;
+
+        Ok(DohResolver {
+            client: Arc::new(Client::builder().build(connector)),
+            endpoint,
+            format: DohFormat::default(),
+            family: LookupFamily::default(),
+            timeout: Duration::from_secs(5),
+            cache: ResolverCache::new(),
+        })
+    }
+
+    /// Sets how queries are sent, RFC 8484 messages by default.
+    pub fn format(self, format: DohFormat) -> Self {
+        DohResolver {
+            format,
+            .. self
+        }
+    }
+
+    /// Sets the address families to look up, both by default.
+    pub fn lookup_family(self, family: LookupFamily) -> Self {
+        DohResolver {
+            family,
+            .. self
+        }
+    }
+
+    /// Sets how long a query may take. Default is 5 seconds.
+    pub fn timeout(self, timeout: Duration) -> Self {
+        DohResolver {
+            timeout,
+            .. self
+        }
+    }
+
+    /// Sets the cache of the answers, a clone of `cache` can flush it or give its statistics.
+    pub fn cache(self, cache: ResolverCache) -> Self {
+        DohResolver {
+            cache,
+            .. self
+        }
+    }
+
+    async fn lookup(self, host: String) -> io::Result<IntoIter<IpAddr>> {
+        let (v6, v4) = match self.family {
+            LookupFamily::V4Only => (Ok(Vec::new()), self.query(&host, A).await),
+            LookupFamily::V6Only => (self.query(&host, AAAA).await, Ok(Vec::new())),
+            LookupFamily::Both => future::join(self.query(&host, AAAA), self.query(&host, A)).await,
+        };
+
+        match merge(v6, v4) {
+            Ok(answer) => {
+                let ttl = answer.iter().map(|&(_, ttl)| ttl).min().unwrap_or(0);
+                let addrs: Vec<_> = answer.into_iter().map(|(addr, _)| addr).collect();
+
+                self.cache.add(host, self.family, addrs.clone(), Duration::from_secs(ttl.into()));
+
+                Ok(addrs.into_iter())
+            },
+            Err(Failure::Dns(err)) => {
+                self.cache.add_failure(host, self.family, err);
+                Err(io::Error::other(err))
+            },
+            Err(Failure::Io(err)) => Err(err),
+        }
+    }
+
+    async fn query(&self, host: &str, qtype: u16) -> Result<Answer, Failure> {
+        let builder = Request::builder()
+            .uri(self.endpoint.clone())
+            .timeout(self.timeout);
+
+        let req = match self.format {
+            DohFormat::Wire => builder
+                .method(Method::POST)
+                .header("Content-Type", "application/dns-message")
+                .header("Accept", "application/dns-message")
+                .body(RequestBody::from(wire::query(host, qtype)?)),
+            #[cfg(feature = "json")]
+            DohFormat::Json => builder
+                .uri(json::uri(&self.endpoint, host, qtype)?)
+                .header("Accept", "application/dns-json")
+                .body(RequestBody::empty()),
+        };
+
+        let res = self.client.request(req.map_err(Failure::Io)?).await
+            .map_err(|err| Failure::Io(err.into()))?;
+
+        if !res.status().is_success() {
+            let msg = format!("the DNS over HTTPS server answered {}", res.status());
+            return Err(Failure::Io(io::Error::other(msg)));
+        }
+
+        let mut body = res.into_body();
+        let mut message = Vec::new();
+
+        while let Some(chunk) = body.next().await {
+            message.extend_from_slice(&chunk.map_err(Failure::Io)?);
+
+            if message.len() > MAX_MESSAGE {
+                return Err(Failure::Dns(c_ares::Error::EBADRESP));
+            }
+        }
+
+        match self.format {
+            DohFormat::Wire => wire::answer(&message, qtype),
+            #[cfg(feature = "json")]
+            DohFormat::Json => json::answer(&message, qtype),
+        }
+    }
+}
+
+impl Resolve for DohResolver {
+    type Addrs = IntoIter<IpAddr>;
+    type Future = DohFuture;
+
+    fn resolve(&self, name: Name) -> Self::Future {
+        let lookup = match self.cache.lookup(name.as_str(), self.family) {
+            Some(res) => future::ready(res.map(Vec::into_iter).map_err(io::Error::other)).boxed(),
+            None => self.clone().lookup(name.as_str().to_string()).boxed(),
+        };
+
+        DohFuture(lookup.compat())
+    }
+}
+
+impl fmt::Debug for DohResolver {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DohResolver")
+            .field("endpoint", &self.endpoint)
+            .field("format", &self.format)
+            .field("family", &self.family)
+            .finish()
+    }
+}
+
+impl Future01 for DohFuture {
+    type Item = IntoIter<IpAddr>;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        self.0.poll()
+    }
+}
+
+impl fmt::Debug for DohFuture {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("DohFuture")
+    }
+}
+
+/// Resolves no name, the client of a `DohResolver` only connects to its endpoint.
+#[derive(Clone)]
+struct Unresolved;
+
+impl Resolve for Unresolved {
+    type Addrs = IntoIter<IpAddr>;
+    type Future = future01::FutureResult<IntoIter<IpAddr>, io::Error>;
+
+    fn resolve(&self, name: Name) -> Self::Future {
+        let msg = format!("{} is not the DNS over HTTPS endpoint", name.as_str());
+        future01::err(io::Error::new(io::ErrorKind::NotFound, msg))
+    }
+}
+
+/// Merges the addresses of both families, a family failing when the other one has addresses is fine.
+fn merge(v6: Result<Answer, Failure>, v4: Result<Answer, Failure>) -> Result<Answer, Failure> {
+    let answer = match (v6, v4) {
+        (Ok(mut v6), Ok(v4)) => {
+            v6.extend(v4);
+            v6
+        },
+        (Ok(answer), Err(err)) | (Err(err), Ok(answer)) => {
+            if answer.is_empty() {
+                return Err(err);
+            }
+
+            answer
+        },
+        // a name without records of one family says nothing, the other error does
+        (Err(v6), Err(Failure::Dns(c_ares::Error::ENODATA))) => return Err(v6),
+        (Err(_), Err(v4)) => return Err(v4),
+    };
+
+    if answer.is_empty() {
+        return Err(Failure::Dns(c_ares::Error::ENODATA));
+    }
+
+    Ok(answer)
+}
+
+/// The error of a response code (RFC 1035, section 4.1.1), `None` for success.
+fn rcode(code: u64) -> Option<c_ares::Error> {
+    match code {
+        0 => None,
+        1 => Some(c_ares::Error::EFORMERR),
+        2 => Some(c_ares::Error::ESERVFAIL),
+        3 => Some(c_ares::Error::ENOTFOUND),
+        4 => Some(c_ares::Error::ENOTIMP),
+        5 => Some(c_ares::Error::EREFUSED),
+        _ => Some(c_ares::Error::EBADRESP),
+    }
+}
+
+fn address(qtype: u16, data: &[u8]) -> Option<IpAddr> {
+    match (qtype, data.len()) {
+        (A, 4) => Some(IpAddr::V4(Ipv4Addr::new(data[0], data[1], data[2], data[3]))),
+        (AAAA, 16) => {
+            let mut octets = [0; 16];
+            octets.copy_from_slice(data);
+            Some(IpAddr::V6(Ipv6Addr::from(octets)))
+        },
+        _ => None,
+    }
+}
+
+/// The wire format of DNS messages (RFC 1035, section 4).
+mod wire {
+    use super::{address, rcode, Answer, Failure};
+
+    /// A recursive query for the `qtype` records of `host`.
+    pub(super) fn query(host: &str, qtype: u16) -> Result<Vec<u8>, Failure> {
+        // the id is 0 so that HTTP caches can answer the same query (RFC 8484, section 4.1)
+        let mut message = vec![0, 0, 0x01, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+
+        for label in host.trim_end_matches('.').split('.') {
+            if label.is_empty() || label.len() > 63 {
+                return Err(Failure::Dns(c_ares::Error::EBADNAME));
+            }
+
+            message.push(label.len() as u8);
+            message.extend_from_slice(label.as_bytes());
+        }
+
+        message.push(0);
+
+        if message.len() - 12 > 255 {
+            return Err(Failure::Dns(c_ares::Error::EBADNAME));
+        }
+
+        message.extend_from_slice(&qtype.to_be_bytes());
+        message.extend_from_slice(&[0, 1]);
+
+        Ok(message)
+    }
+
+    /// The `qtype` addresses of a response.
+    pub(super) fn answer(message: &[u8], qtype: u16) -> Result<Answer, Failure> {
+        parse(message, qtype).unwrap_or(Err(Failure::Dns(c_ares::Error::EBADRESP)))
+    }
+
+    /// `None` when the message is malformed.
+    fn parse(message: &[u8], qtype: u16) -> Option<Result<Answer, Failure>> {
+        let header = message.get(.. 12)?;
+        let flags = u16::from_be_bytes([header[2], header[3]]);
+
+        if flags & 0x8000 == 0 {
+            return None;
+        }
+
+        if let Some(err) = rcode((flags & 0x000f).into()) {
+            return Some(Err(Failure::Dns(err)));
+        }
+
+        let questions = u16::from_be_bytes([header[4], header[5]]);
+        let answers = u16::from_be_bytes([header[6], header[7]]);
+        let mut pos = 12;
+
+        for _ in 0 .. questions {
+            pos = skip_name(message, pos)? + 4;
+        }
+
+        let mut answer = Vec::new();
+
+        for _ in 0 .. answers {
+            pos = skip_name(message, pos)?;
+
+            let record = message.get(pos .. pos + 10)?;
+            let rtype = u16::from_be_bytes([record[0], record[1]]);
+            let class = u16::from_be_bytes([record[2], record[3]]);
+            let ttl = u32::from_be_bytes([record[4], record[5], record[6], record[7]]);
+            let len = usize::from(u16::from_be_bytes([record[8], record[9]]));
+            let data = message.get(pos + 10 .. pos + 10 + len)?;
+            pos += 10 + len;
+
+            // aliases come with the records of their target
+            if rtype == qtype && class == 1 {
+                answer.push((address(qtype, data)?, ttl));
+            }
+        }
+
+        Some(Ok(answer))
+    }
+
+    /// The position following the name at `pos`.
+    fn skip_name(message: &[u8], mut pos: usize) -> Option<usize> {
+        loop {
+            match *message.get(pos)? {
+                0 => return Some(pos + 1),
+                // a pointer ends the name
+                len if len & 0xc0 == 0xc0 => return Some(pos + 2),
+                len => pos += 1 + usize::from(len),
+            }
+        }
+    }
+}
+
+/// The JSON format of Google and Cloudflare.
+#[cfg(feature = "json")]
+mod json {
+    use std::cmp;
+    use std::io;
+    use std::net::IpAddr;
+
+    use hyper::Uri;
+    use serde_json::Value;
+
+    use super::{rcode, Answer, Failure};
+
+    pub(super) fn uri(endpoint: &Uri, host: &str, qtype: u16) -> Result<Uri, Failure> {
+        let query = serde_urlencoded::to_string([("name", host), ("type", &qtype.to_string())])
+            .map_err(|err| Failure::Io(io::Error::new(io::ErrorKind::InvalidInput, err)))?;
+        let separator = if endpoint.query().is_some() { '&' } else { '?' };
+
+        format!("{}{}{}", endpoint, separator, query).parse()
+            .map_err(|err| Failure::Io(io::Error::new(io::ErrorKind::InvalidInput, err)))
+    }
+
+    pub(super) fn answer(message: &[u8], qtype: u16) -> Result<Answer, Failure> {
+        let bad = || Failure::Dns(c_ares::Error::EBADRESP);
+        let message: Value = serde_json::from_slice(message).map_err(|_| bad())?;
+
+        if let Some(err) = rcode(message["Status"].as_u64().ok_or_else(bad)?) {
+            return Err(Failure::Dns(err));
+        }
+
+        let records = match message["Answer"].as_array() {
+            Some(records) => records,
+            None => return Ok(Vec::new()),
+        };
+
+        records.iter()
+            .filter(|record| record["type"].as_u64() == Some(qtype.into()))
+            .map(|record| {
+                let addr: IpAddr = record["data"].as_str().and_then(|data| data.parse().ok()).ok_or_else(bad)?;
+                let ttl = record["TTL"].as_u64().unwrap_or(0);
+
+                Ok((addr, cmp::min(ttl, u32::MAX.into()) as u32))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn answer(addrs: &[&str]) -> Answer {
+        addrs.iter().map(|addr| (addr.parse().unwrap(), 60)).collect()
+    }
+
+    fn timeout() -> Failure {
+        Failure::Io(io::Error::from(io::ErrorKind::TimedOut))
+    }
+
+    /// The addresses merged, or the error code or I/O error kind of the failure.
+    fn merged(v6: Result<Answer, Failure>, v4: Result<Answer, Failure>) -> Result<Answer, Result<c_ares::Error, io::ErrorKind>> {
+        merge(v6, v4).map_err(|failure| match failure {
+            Failure::Dns(err) => Ok(err),
+            Failure::Io(err) => Err(err.kind()),
+        })
+    }
+
+    #[test]
+    fn merges_both_families() {
+        assert_eq!(merged(Ok(answer(&["2001:db8::1"])), Ok(answer(&["192.0.2.1"]))), Ok(answer(&["2001:db8::1", "192.0.2.1"])));
+        assert_eq!(merged(Ok(Vec::new()), Ok(Vec::new())), Err(Ok(c_ares::Error::ENODATA)));
+    }
+
+    #[test]
+    fn merges_a_failing_family() {
+        assert_eq!(merged(Err(timeout()), Ok(answer(&["192.0.2.1"]))), Ok(answer(&["192.0.2.1"])));
+        assert_eq!(merged(Ok(answer(&["2001:db8::1"])), Err(Failure::Dns(c_ares::Error::ESERVFAIL))), Ok(answer(&["2001:db8::1"])));
+
+        assert_eq!(merged(Err(timeout()), Ok(Vec::new())), Err(Err(io::ErrorKind::TimedOut)));
+        assert_eq!(merged(Ok(Vec::new()), Err(Failure::Dns(c_ares::Error::ENOTFOUND))), Err(Ok(c_ares::Error::ENOTFOUND)));
+    }
+
+    #[test]
+    fn merges_two_failures() {
+        let nodata = || Failure::Dns(c_ares::Error::ENODATA);
+
+        assert_eq!(merged(Err(timeout()), Err(nodata())), Err(Err(io::ErrorKind::TimedOut)));
+        assert_eq!(merged(Err(nodata()), Err(timeout())), Err(Err(io::ErrorKind::TimedOut)));
+        assert_eq!(merged(Err(Failure::Dns(c_ares::Error::ENOTFOUND)), Err(nodata())), Err(Ok(c_ares::Error::ENOTFOUND)));
+        assert_eq!(merged(Err(nodata()), Err(nodata())), Err(Ok(c_ares::Error::ENODATA)));
+    }
+}
@@ -1,5 +1,6 @@
 pub mod dns;
 pub mod ares;
+pub mod doh;
 
 use std::borrow::Cow;
 use std::fmt;
@@ -17,17 +18,23 @@ use tokio_tcp::{TcpStream, ConnectFuture};
 use tokio_timer::Delay;
 
 use crate::connect::{Connect, Connected, Destination};
+use crate::error::Error;
+use crate::timeout::TimeoutPhase;
 use self::dns::{GaiResolver, Resolve};
 use self::ares::CAresResolverImpl;
 use std::sync::Arc;
 use lazy_static::lazy_static;
 
 lazy_static! {
-    static ref ARES: Arc<CAresResolverImpl> = Arc::new(CAresResolverImpl::new());
+    // the error is kept as its parts, `io::Error` can't be cloned
+    static ref ARES: Result<Arc<CAresResolverImpl>, (io::ErrorKind, String)> = CAresResolverImpl::new()
+        .map(Arc::new)
+        .map_err(|err| (err.kind(), err.to_string()));
 }
 
 #[derive(Clone)]
 pub struct HttpConnector<R = Arc<CAresResolverImpl>> {
+    connect_timeout: Option<Duration>,
     enforce_http: bool,
     handle: Option<Handle>,
     happy_eyeballs_timeout: Option<Duration>,
@@ -41,10 +48,33 @@ pub struct HttpConnector<R = Arc<CAresResolverImpl>> {
 impl HttpConnector {
     /// Construct a new HttpConnector.
     ///
-    /// Takes number of DNS worker threads.
+    /// # Panics
+    ///
+    /// Panics if c-ares can't be initialized, for example when `/etc/resolv.conf` is
+    /// unreadable.
+    #[deprecated(since = "0.1.0", note = "use `try_new`, which returns the error instead of panicking")]
     #[inline]
-    pub fn new(_threads: usize) -> HttpConnector {
-        HttpConnector::new_with_resolver(ARES.clone())
+    pub fn new(threads: usize) -> HttpConnector {
+        match HttpConnector::try_new(threads) {
+            Ok(connector) => connector,
+            Err(err) => panic!("failed to initialize c-ares: {}", err),
+        }
+    }
+
+    /// Construct a new HttpConnector, failing if c-ares can't be initialized.
+    ///
+    /// Names are resolved by a c-ares resolver shared by the process and configured by
+    /// `/etc/resolv.conf`, the number of DNS worker threads is ignored. Use
+    /// `new_with_resolver` with `CAresResolverImpl::with_options` to configure it.
+    pub fn try_new(_threads: usize) -> io::Result<HttpConnector> {
+        shared_resolver(&ARES).map(HttpConnector::new_with_resolver)
+    }
+}
+
+fn shared_resolver(ares: &Result<Arc<CAresResolverImpl>, (io::ErrorKind, String)>) -> io::Result<Arc<CAresResolverImpl>> {
+    match *ares {
+        Ok(ref resolver) => Ok(resolver.clone()),
+        Err((kind, ref msg)) => Err(io::Error::new(kind, msg.clone())),
     }
 }
 
@@ -55,6 +85,7 @@ impl<R> HttpConnector<R> {
     /// Takes a `Resolve` to handle DNS lookups.
     pub fn new_with_resolver(resolver: R) -> HttpConnector<R> {
         HttpConnector {
+            connect_timeout: None,
             enforce_http: true,
             handle: None,
             happy_eyeballs_timeout: Some(Duration::from_millis(300)),
@@ -70,6 +101,15 @@ impl<R> HttpConnector<R> {
     pub fn enforce_http(&mut self, is_enforced: bool) {
         self.enforce_http = is_enforced;
     }
+
+    /// Set a timeout for each attempt to connect to one of the resolved addresses.
+    ///
+    /// When an attempt times out the next address is tried. A timeout carried by
+    /// the `Destination` takes precedence. Default is `None`.
+    #[inline]
+    pub fn set_connect_timeout(&mut self, dur: Option<Duration>) {
+        self.connect_timeout = dur;
+    }
 }
 
 impl<R> Connect for HttpConnector<R>
@@ -101,6 +141,7 @@ impl<R> Connect for HttpConnector<R>
 
         HttpConnecting {
             state: State::Lazy(self.resolver.clone(), host.into(), self.local_address),
+            connect_timeout: dst.connect_timeout().or(self.connect_timeout),
             handle: self.handle.clone(),
             happy_eyeballs_timeout: self.happy_eyeballs_timeout,
             keep_alive_timeout: self.keep_alive_timeout,
@@ -151,7 +192,8 @@ impl HttpInfo {
 #[inline]
 fn invalid_url<R: Resolve>(err: InvalidUrl, handle: &Option<Handle>) -> HttpConnecting<R> {
     HttpConnecting {
-        state: State::Error(Some(io::Error::new(io::ErrorKind::InvalidInput, err))),
+        state: State::Error(Some(Error::InvalidUrl(io::Error::new(io::ErrorKind::InvalidInput, err)).into())),
+        connect_timeout: None,
         handle: handle.clone(),
         keep_alive_timeout: None,
         nodelay: false,
@@ -164,6 +206,7 @@ fn invalid_url<R: Resolve>(err: InvalidUrl, handle: &Option<Handle>) -> HttpConn
 #[must_use = "futures do nothing unless polled"]
 pub struct HttpConnecting<R: Resolve = GaiResolver> {
     state: State<R>,
+    connect_timeout: Option<Duration>,
     handle: Option<Handle>,
     happy_eyeballs_timeout: Option<Duration>,
     keep_alive_timeout: Option<Duration>,
@@ -192,14 +235,14 @@ impl<R: Resolve> Future for HttpConnecting<R> {
                     // skip resolving the dns and start connecting right away.
                     if let Some(addrs) = dns::IpAddrs::try_parse(host, self.port) {
                         state = State::Connecting(ConnectingTcp::new(
-                            local_addr, addrs, self.happy_eyeballs_timeout, self.reuse_address));
+                            local_addr, addrs, self.happy_eyeballs_timeout, self.connect_timeout, self.reuse_address));
                     } else {
-                        let name = dns::Name::new(mem::replace(host, String::new()));
+                        let name = dns::Name::new(mem::replace(host, String::new())).with_port(self.port);
                         state = State::Resolving(resolver.resolve(name), local_addr);
                     }
                 },
                 State::Resolving(ref mut future, local_addr) => {
-                    match future.poll()? {
+                    match future.poll().map_err(Error::Dns)? {
                         Async::NotReady => return Ok(Async::NotReady),
                         Async::Ready(addrs) => {
                             let port = self.port;
@@ -208,21 +251,27 @@ impl<R: Resolve> Future for HttpConnecting<R> {
                                 .collect();
                             let addrs = dns::IpAddrs::new(addrs);
                             state = State::Connecting(ConnectingTcp::new(
-                                local_addr, addrs, self.happy_eyeballs_timeout, self.reuse_address));
+                                local_addr, addrs, self.happy_eyeballs_timeout, self.connect_timeout, self.reuse_address));
                         }
                     };
                 },
                 State::Connecting(ref mut c) => {
-                    let sock = try_ready!(c.poll(&self.handle));
+                    let sock = try_ready!(c.poll(&self.handle).map_err(|err| {
+                        if err.kind() == io::ErrorKind::TimedOut {
+                            Error::Timeout(TimeoutPhase::Connect, err)
+                        } else {
+                            Error::Connect(err)
+                        }
+                    }));
 
                     if let Some(dur) = self.keep_alive_timeout {
-                        sock.set_keepalive(Some(dur))?;
+                        sock.set_keepalive(Some(dur)).map_err(Error::Connect)?;
                     }
 
-                    sock.set_nodelay(self.nodelay)?;
+                    sock.set_nodelay(self.nodelay).map_err(Error::Connect)?;
 
                     let extra = HttpInfo {
-                        remote_addr: sock.peer_addr()?,
+                        remote_addr: sock.peer_addr().map_err(Error::Connect)?,
                     };
                     let connected = Connected::new()
                         .extra(extra);
@@ -254,6 +303,7 @@ impl ConnectingTcp {
         local_addr: Option<IpAddr>,
         remote_addrs: dns::IpAddrs,
         fallback_timeout: Option<Duration>,
+        attempt_timeout: Option<Duration>,
         reuse_address: bool,
     ) -> ConnectingTcp {
         if let Some(fallback_timeout) = fallback_timeout {
@@ -261,7 +311,7 @@ impl ConnectingTcp {
             if fallback_addrs.is_empty() {
                 return ConnectingTcp {
                     local_addr,
-                    preferred: ConnectingTcpRemote::new(preferred_addrs),
+                    preferred: ConnectingTcpRemote::new(preferred_addrs, attempt_timeout),
                     fallback: None,
                     reuse_address,
                 };
@@ -269,17 +319,17 @@ impl ConnectingTcp {
 
             ConnectingTcp {
                 local_addr,
-                preferred: ConnectingTcpRemote::new(preferred_addrs),
+                preferred: ConnectingTcpRemote::new(preferred_addrs, attempt_timeout),
                 fallback: Some(ConnectingTcpFallback {
                     delay: Delay::new(Instant::now() + fallback_timeout),
-                    remote: ConnectingTcpRemote::new(fallback_addrs),
+                    remote: ConnectingTcpRemote::new(fallback_addrs, attempt_timeout),
                 }),
                 reuse_address,
             }
         } else {
             ConnectingTcp {
                 local_addr,
-                preferred: ConnectingTcpRemote::new(remote_addrs),
+                preferred: ConnectingTcpRemote::new(remote_addrs, attempt_timeout),
                 fallback: None,
                 reuse_address,
             }
@@ -294,18 +344,58 @@ struct ConnectingTcpFallback {
 
 struct ConnectingTcpRemote {
     addrs: dns::IpAddrs,
-    current: Option<ConnectFuture>,
+    attempt_timeout: Option<Duration>,
+    current: Option<ConnectingAttempt>,
 }
 
 impl ConnectingTcpRemote {
-    fn new(addrs: dns::IpAddrs) -> Self {
+    fn new(addrs: dns::IpAddrs, attempt_timeout: Option<Duration>) -> Self {
         Self {
             addrs,
+            attempt_timeout,
             current: None,
         }
     }
 }
 
+/// A connection attempt to a single address, abandoned after its timeout.
+struct ConnectingAttempt {
+    future: ConnectFuture,
+    delay: Option<Delay>,
+}
+
+impl ConnectingAttempt {
+    fn new(future: ConnectFuture, timeout: Option<Duration>) -> Self {
+        ConnectingAttempt {
+            future,
+            delay: timeout.map(|timeout| Delay::new(Instant::now() + timeout)),
+        }
+    }
+}
+
+impl Future for ConnectingAttempt {
+    type Item = TcpStream;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<TcpStream, io::Error> {
+        if let Async::Ready(stream) = self.future.poll()? {
+            return Ok(Async::Ready(stream));
+        }
+
+        match self.delay.as_mut().map(Future::poll) {
+            Some(Ok(Async::Ready(()))) => {
+                Err(io::Error::new(io::ErrorKind::TimedOut, "connect attempt timed out"))
+            }
+            Some(Err(_)) => {
+                // No timer available - keep waiting without a timeout.
+                self.delay = None;
+                Ok(Async::NotReady)
+            }
+            _ => Ok(Async::NotReady),
+        }
+    }
+}
+
 impl ConnectingTcpRemote {
     // not a Future, since passing a &Handle to poll
     fn poll(
@@ -322,13 +412,15 @@ impl ConnectingTcpRemote {
                     Err(e) => {
                         err = Some(e);
                         if let Some(addr) = self.addrs.next() {
-                            *current = connect(&addr, local_addr, handle, reuse_address)?;
+                            let future = connect(&addr, local_addr, handle, reuse_address)?;
+                            *current = ConnectingAttempt::new(future, self.attempt_timeout);
                             continue;
                         }
                     }
                 }
             } else if let Some(addr) = self.addrs.next() {
-                self.current = Some(connect(&addr, local_addr, handle, reuse_address)?);
+                let future = connect(&addr, local_addr, handle, reuse_address)?;
+                self.current = Some(ConnectingAttempt::new(future, self.attempt_timeout));
                 continue;
             }
 
@@ -419,3 +511,17 @@ impl ConnectingTcp {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn returns_the_error_of_c_ares() {
+        let failed = Err((io::ErrorKind::NotFound, "no /etc/resolv.conf".to_string()));
+        let err = shared_resolver(&failed).err().unwrap();
+
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert_eq!(err.to_string(), "no /etc/resolv.conf");
+    }
+}
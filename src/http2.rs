@@ -0,0 +1,166 @@
+//! HTTP/2 connections (RFC 7540), with the `http2` feature.
+//!
+//! HTTP/2 is negotiated with ALPN for `https`, or used with prior knowledge for
+//! `http`. A connection is shared by all the requests to its origin, each one on
+//! its own stream, and is driven in the background.
+
+use std::cmp;
+use std::io::{self, Read};
+
+use bytes::Bytes;
+use futures::compat::*;
+use futures::stream::StreamExt;
+use futures_cpupool::{Builder as CpuPoolBuilder, CpuPool};
+use futures_legacy::{future as future01, Async, Future as Future01, Stream as Stream01};
+use h2::client;
+use h2::RecvStream;
+use ::http::{HeaderMap, Request as HttpRequest, Response as HttpResponse};
+use lazy_static::lazy_static;
+use tokio_executor::{DefaultExecutor, Executor};
+use tokio_io::AsyncRead;
+
+use crate::error::Error;
+use crate::pool::Conn;
+use crate::request_body::RequestBody;
+use crate::timeout::{timed, Deadline};
+
+pub(crate) type SendRequest = client::SendRequest<Bytes>;
+
+lazy_static! {
+    /// Drives the frames of the connections made outside of a Tokio runtime, one thread
+    /// is enough to move bytes around.
+    static ref FALLBACK_EXECUTOR: CpuPool = CpuPoolBuilder::new()
+        .name_prefix("simple-http-h2")
+        .pool_size(1)
+        .create();
+}
+
+/// Starts HTTP/2 on a connection.
+pub(crate) async fn handshake(conn: Conn) -> Result<SendRequest, Error> {
+    let (send, connection) = client::handshake(conn).compat().await.map_err(h2_error)?;
+
+    // The connection closes once all handles to it and all its streams are dropped. It
+    // is driven by the Tokio runtime making it, and by a thread of its own outside of one.
+    let connection = connection.map_err(|_| ());
+    let mut executor = DefaultExecutor::current();
+
+    if executor.status().is_ok() {
+        executor.spawn(Box::new(connection)).map_err(|err| Error::Io(io::Error::other(err)))?;
+    } else {
+        FALLBACK_EXECUTOR.spawn(connection).forget();
+    }
+
+    Ok(send)
+}
+
+/// Waits until the connection accepts another stream, fails when the connection was closed.
+pub(crate) async fn ready(send: SendRequest) -> Result<SendRequest, Error> {
+    send.ready().compat().await.map_err(h2_error)
+}
+
+/// Sends a request on a new stream and waits for the response head until `head_deadline`.
+///
+/// The body is sent as the flow control of the stream allows.
+pub(crate) async fn send(
+    mut send: SendRequest,
+    req: HttpRequest<()>,
+    body: Option<RequestBody>,
+    length: Option<u64>,
+    trailers: HeaderMap,
+    total: Option<Deadline>,
+    head_deadline: Option<Deadline>,
+) -> Result<HttpResponse<RecvStream>, Error> {
+    let body = body.filter(|body| !body.is_empty());
+    let (response, mut stream) = send.send_request(req, body.is_none() && trailers.is_empty())
+        .map_err(h2_error)?;
+
+    if let Some(mut body) = body {
+        let mut written = 0u64;
+
+        while let Some(res) = timed(async { Ok(body.next().await) }, total).await? {
+            let mut chunk = Bytes::from(res.map_err(Error::Body)?);
+            written += chunk.len() as u64;
+
+            if let Some(len) = length.filter(|&len| written > len) {
+                return Err(crate::body_length_mismatch(len));
+            }
+
+            while !chunk.is_empty() {
+                stream.reserve_capacity(chunk.len());
+
+                let capacity = timed(async {
+                    future01::poll_fn(|| stream.poll_capacity()).compat().await.map_err(h2_error)
+                }, total).await?;
+
+                let capacity = capacity.ok_or_else(|| Error::Io(io::Error::new(io::ErrorKind::BrokenPipe, "stream closed by the server")))?;
+                let part = chunk.split_to(cmp::min(capacity, chunk.len()));
+                stream.send_data(part, false).map_err(h2_error)?;
+            }
+        }
+
+        if let Some(len) = length.filter(|&len| written != len) {
+            return Err(crate::body_length_mismatch(len));
+        }
+
+        if trailers.is_empty() {
+            stream.send_data(Bytes::new(), true).map_err(h2_error)?;
+        }
+    }
+
+    if !trailers.is_empty() {
+        stream.send_trailers(trailers).map_err(h2_error)?;
+    }
+
+    timed(async { response.compat().await.map_err(h2_error) }, head_deadline).await
+}
+
+/// The body of a response, read like a connection.
+pub(crate) struct RecvBody {
+    recv: RecvStream,
+    chunk: Bytes,
+}
+
+impl RecvBody {
+    pub(crate) fn new(recv: RecvStream) -> Self {
+        RecvBody {
+            recv,
+            chunk: Bytes::new(),
+        }
+    }
+}
+
+impl Read for RecvBody {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.chunk.is_empty() {
+            match self.recv.poll() {
+                Ok(Async::Ready(Some(chunk))) => {
+                    // the server may send more as soon as the data left the stream
+                    self.recv.release_capacity().release_capacity(chunk.len()).map_err(into_io)?;
+                    self.chunk = chunk;
+                },
+                Ok(Async::Ready(None)) => return Ok(0),
+                Ok(Async::NotReady) => return Err(io::ErrorKind::WouldBlock.into()),
+                Err(err) => return Err(into_io(err)),
+            }
+        }
+
+        let len = cmp::min(buf.len(), self.chunk.len());
+        buf[.. len].copy_from_slice(&self.chunk.split_to(len));
+
+        Ok(len)
+    }
+}
+
+impl AsyncRead for RecvBody {}
+
+fn into_io(err: h2::Error) -> io::Error {
+    if err.is_io() {
+        err.into_io().unwrap_or_else(|| io::ErrorKind::Other.into())
+    } else {
+        io::Error::other(err)
+    }
+}
+
+fn h2_error(err: h2::Error) -> Error {
+    Error::Io(into_io(err))
+}
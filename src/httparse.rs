@@ -1,116 +1,442 @@
+//! Incremental parser of HTTP/1.x response heads (RFC 7230, section 3).
+//!
+//! The parser is stateless: it is handed everything received so far and
+//! either returns the complete head or reports that more bytes are needed.
+
+use std::borrow::Cow;
 use std::fmt;
-use std::iter;
 
-fn ltrim(mut val: &[u8]) -> &[u8] {
-    while let Some(&b' ') = val.get(0) {
-        val = &val[1..]
-    }
+/// Result of parsing possibly incomplete input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Status<T> {
+    /// The head is complete.
+    Complete(T),
+    /// The input ends before the end of the head.
+    Partial,
+}
 
-    val
+/// Limits protecting against unbounded response heads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Maximum number of header fields.
+    pub max_headers: usize,
+    /// Maximum size of the head in bytes, including the status line and the empty line ending it.
+    pub max_head_size: usize,
 }
 
-fn rtrim(mut val: &[u8]) -> &[u8] {
-    while let Some(&b' ') = val.get(val.len() - 1) {
-        val = &val[..val.len() - 2]
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_headers: 100,
+            max_head_size: 64 * 1024,
+        }
     }
-
-    val
 }
 
-
-#[derive(Debug)]
-pub enum Header<'a> {
-    Status(u16, &'a [u8], &'a [u8]),
-    Header(&'a [u8], &'a [u8]),
+/// A parsed response head, borrowing from the input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Head<'a> {
+    /// Major and minor version of the protocol.
+    pub version: (u8, u8),
+    pub code: u16,
+    /// The reason phrase, possibly empty.
+    pub reason: &'a [u8],
+    /// Header fields in the order received, with values trimmed and folded lines joined.
+    pub headers: Vec<(&'a [u8], Cow<'a, [u8]>)>,
 }
 
-#[derive(Debug)]
-pub enum ParseError<'a> {
+/// Errors found while parsing a response head.
+///
+/// Each variant holds the offending input or the exceeded limit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
     WrongStatusReason,
-    WrongStatusHeader(&'a [u8]),
-    WrongStatusCode(&'a [u8]),
-    WrongHeader(&'a [u8]),
+    WrongStatusHeader(Vec<u8>),
+    WrongStatusCode(Vec<u8>),
+    WrongHeader(Vec<u8>),
+    TooManyHeaders(usize),
+    HeadTooLarge(usize),
 }
 
-impl fmt::Display for ParseError<'_> {
+impl fmt::Display for ParseError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        writeln!(f, "{:?}", self)
+        match *self {
+            ParseError::WrongStatusReason => write!(f, "invalid status reason"),
+            ParseError::WrongStatusHeader(ref v) => write!(f, "invalid status line {:?}", String::from_utf8_lossy(v)),
+            ParseError::WrongStatusCode(ref v) => write!(f, "invalid status code {:?}", String::from_utf8_lossy(v)),
+            ParseError::WrongHeader(ref v) => write!(f, "invalid header {:?}", String::from_utf8_lossy(v)),
+            ParseError::TooManyHeaders(max) => write!(f, "more than {} headers", max),
+            ParseError::HeadTooLarge(max) => write!(f, "header too large, more than {} bytes", max),
+        }
     }
 }
 
-impl std::error::Error for ParseError<'_> {}
+impl std::error::Error for ParseError {}
 
-fn parse_header(input: &[u8]) -> Result<(&[u8], &[u8]), ParseError> {
-    let mut iter = input.split(|x| *x == b':');
-    let name = ltrim(rtrim(iter.next().unwrap()));
-    let value = iter.next().ok_or_else(|| ParseError::WrongHeader(name))?;
+/// Parses the response head at the start of `input`.
+///
+/// Returns the head and the number of bytes it occupies, or `Status::Partial`
+/// when `input` ends before the empty line closing the head. Lines may end with
+/// CRLF or a bare LF, and obsolete line folding is replaced by a single space.
+pub fn parse_headers<'a>(input: &'a [u8], limits: &Limits) -> Result<Status<(Head<'a>, usize)>, ParseError> {
+    let mut lines = Lines { input, pos: 0, limits };
 
-    Ok((name, ltrim(rtrim(value))))
-}
+    let (version, code, reason) = match lines.next()? {
+        Some(line) => parse_status(line)?,
+        None => return lines.partial(),
+    };
+
+    let mut headers: Vec<(&[u8], Cow<[u8]>)> = Vec::new();
+
+    loop {
+        let line = match lines.next()? {
+            Some(line) => line,
+            None => return lines.partial(),
+        };
+
+        match line.first() {
+            None => {
+                let head = Head { version, code, reason, headers };
+                return Ok(Status::Complete((head, lines.pos)));
+            },
+            Some(&b' ') | Some(&b'\t') => {
+                // obs-fold, the line continues the value of the previous header
+                let value = match headers.last_mut() {
+                    Some((_, value)) => value,
+                    None => return Err(ParseError::WrongHeader(line.to_vec())),
+                };
 
-fn parse_status(input: &[u8]) -> Result<(u16, &[u8], &[u8]), ParseError> {
-    let mut iter = input.split(|i| *i == b' ');
+                let folded = trim(line);
+                if !folded.iter().all(|&b| is_value_byte(b)) {
+                    return Err(ParseError::WrongHeader(line.to_vec()));
+                }
 
-    let x = iter.next()
-        .ok_or_else(|| ParseError::WrongStatusHeader(&input[0..0]))?;
+                if !folded.is_empty() {
+                    let value = value.to_mut();
+                    if !value.is_empty() {
+                        value.push(b' ');
+                    }
+                    value.extend_from_slice(folded);
+                }
+            },
+            Some(_) => {
+                if headers.len() >= limits.max_headers {
+                    return Err(ParseError::TooManyHeaders(limits.max_headers));
+                }
 
-    if &x[0..4] != b"HTTP" {
-        return Err(ParseError::WrongStatusHeader(&input[0..4]))
+                let (name, value) = parse_header(line)?;
+                headers.push((name, Cow::Borrowed(value)));
+            },
+        }
     }
+}
+
+/// Splits the input into lines ending with LF or CRLF.
+struct Lines<'a, 'l> {
+    input: &'a [u8],
+    pos: usize,
+    limits: &'l Limits,
+}
+
+impl<'a, 'l> Lines<'a, 'l> {
+    fn next(&mut self) -> Result<Option<&'a [u8]>, ParseError> {
+        let rest = &self.input[self.pos ..];
+
+        let idx = match rest.iter().position(|&b| b == b'\n') {
+            Some(idx) => idx,
+            None => return Ok(None),
+        };
+
+        if self.pos + idx + 1 > self.limits.max_head_size {
+            return Err(ParseError::HeadTooLarge(self.limits.max_head_size));
+        }
+
+        self.pos += idx + 1;
 
-    let http_version = &x[5..];
+        let line = &rest[.. idx];
+        Ok(Some(match line.split_last() {
+            Some((&b'\r', line)) => line,
+            _ => line,
+        }))
+    }
 
-    let code = iter.next()
-        .ok_or_else(|| ParseError::WrongStatusCode(&input[0..0]))?;
+    fn partial<T>(&self) -> Result<Status<T>, ParseError> {
+        if self.input.len() >= self.limits.max_head_size {
+            return Err(ParseError::HeadTooLarge(self.limits.max_head_size));
+        }
 
-    if code.len() < 3 {
-        return Err(ParseError::WrongStatusCode(code))
+        Ok(Status::Partial)
     }
+}
+
+/// Parses `HTTP/x.y SP 3DIGIT SP reason-phrase`, the reason phrase may be missing.
+fn parse_status(line: &[u8]) -> Result<((u8, u8), u16, &[u8]), ParseError> {
+    let wrong_status = || ParseError::WrongStatusHeader(line.to_vec());
 
-    let (a, b, c) = unsafe {
-        ((*code.get_unchecked(0) as i16 - b'0' as i16),
-         (*code.get_unchecked(1) as i16 - b'0' as i16),
-         (*code.get_unchecked(2) as i16 - b'0' as i16))
+    let version = match line.get(.. 9) {
+        Some(&[b'H', b'T', b'T', b'P', b'/', major, b'.', minor, b' '])
+            if major.is_ascii_digit() && minor.is_ascii_digit() => (major - b'0', minor - b'0'),
+        _ => return Err(wrong_status()),
     };
 
-    if  a < 1 || a > 5 ||
-        b < 0 || b > 9 ||
-        c < 0 || c > 9 {
+    let rest = &line[9 ..];
+    let code = match rest.get(.. 3) {
+        Some(&[a, b, c]) if (b'1' ..= b'9').contains(&a) && b.is_ascii_digit() && c.is_ascii_digit() => {
+            u16::from(a - b'0') * 100 + u16::from(b - b'0') * 10 + u16::from(c - b'0')
+        },
+        _ => return Err(ParseError::WrongStatusCode(rest.to_vec())),
+    };
 
-        return Err(ParseError::WrongStatusCode(code));
+    let reason = match rest.get(3) {
+        None => &rest[3 ..],
+        Some(&b' ') => &rest[4 ..],
+        Some(_) => return Err(ParseError::WrongStatusCode(rest.to_vec())),
+    };
+
+    if !reason.iter().all(|&b| is_value_byte(b)) {
+        return Err(ParseError::WrongStatusReason);
     }
 
-    let reason = iter.next()
-        .ok_or_else(|| ParseError::WrongStatusReason)?;
+    Ok((version, code, reason))
+}
+
+/// Parses `field-name ":" OWS field-value OWS`.
+fn parse_header(line: &[u8]) -> Result<(&[u8], &[u8]), ParseError> {
+    let idx = line.iter().position(|&b| b == b':')
+        .ok_or_else(|| ParseError::WrongHeader(line.to_vec()))?;
+
+    let name = &line[.. idx];
+    let value = trim(&line[idx + 1 ..]);
+
+    // no whitespace is allowed between the name and the colon
+    if name.is_empty() || !name.iter().all(|&b| is_token_byte(b)) || !value.iter().all(|&b| is_value_byte(b)) {
+        return Err(ParseError::WrongHeader(line.to_vec()));
+    }
 
-    Ok(((a * 100 + b * 10 + c) as u16, http_version, reason))
+    Ok((name, value))
 }
 
-pub fn parse_headers(mut input: &[u8]) -> impl Iterator<Item = Result<Header<'_>, ParseError>> {
-    let mut status_parsed = false;
+fn trim(mut val: &[u8]) -> &[u8] {
+    while let Some((&b' ', rest)) | Some((&b'\t', rest)) = val.split_first() {
+        val = rest;
+    }
 
-    iter::from_fn(move || {
-        let idx = input.windows(2).position(|s| &s[0..2] == b"\r\n")?;
-        let line = &input[..idx];
-        input = &input[idx + 2..];
+    while let Some((&b' ', rest)) | Some((&b'\t', rest)) = val.split_last() {
+        val = rest;
+    }
 
-        if !status_parsed {
-            status_parsed = true;
-            match parse_status(line) {
-                Ok((status, version, reason)) => {
-                    Some(Ok(Header::Status(status, version, reason)))
-                },
-                Err(err) => Some(Err(err)),
+    val
+}
+
+/// `tchar` of RFC 7230, section 3.2.6.
+fn is_token_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+/// `VCHAR`, `obs-text`, space and tab.
+fn is_value_byte(b: u8) -> bool {
+    b == b'\t' || b == b' ' || (0x21 ..= 0x7e).contains(&b) || b >= 0x80
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    fn parse(input: &[u8]) -> Result<Status<(Head, usize)>, ParseError> {
+        parse_headers(input, &Limits::default())
+    }
+
+    fn token() -> impl Strategy<Value = String> {
+        "[A-Za-z0-9!#$%&'*+.^_`|~-]{1,20}"
+    }
+
+    fn value() -> impl Strategy<Value = String> {
+        // no leading or trailing whitespace, those are not part of the value
+        "([!-~]([ \t!-~]{0,30}[!-~])?)?"
+    }
+
+    fn reason() -> impl Strategy<Value = String> {
+        "[ \t!-~]{0,20}"
+    }
+
+    prop_compose! {
+        fn head()(
+            minor in 0u8 ..= 1,
+            code in 100u16 ..= 999,
+            reason in reason(),
+            headers in prop::collection::vec((token(), value()), 0 .. 20),
+            crlf in any::<bool>(),
+        ) -> (u8, u16, String, Vec<(String, String)>, Vec<u8>) {
+            let eol = if crlf { "\r\n" } else { "\n" };
+            let mut raw = format!("HTTP/1.{} {} {}{}", minor, code, reason, eol);
+
+            for (name, value) in &headers {
+                raw.push_str(&format!("{}: {}{}", name, value, eol));
+            }
+
+            raw.push_str(eol);
+
+            (minor, code, reason, headers, raw.into_bytes())
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn never_panics(input in prop::collection::vec(any::<u8>(), 0 .. 512)) {
+            let _ = parse(&input);
+        }
+
+        #[test]
+        fn never_panics_after_status_line(input in prop::collection::vec(any::<u8>(), 0 .. 512)) {
+            let mut raw = b"HTTP/1.1 200 OK\r\n".to_vec();
+            raw.extend_from_slice(&input);
+            let _ = parse(&raw);
+        }
+
+        #[test]
+        fn parses_generated_heads((minor, code, reason, headers, raw) in head()) {
+            let (head, len) = match parse(&raw) {
+                Ok(Status::Complete(parsed)) => parsed,
+                other => panic!("unexpected result {:?}", other),
+            };
+
+            prop_assert_eq!(len, raw.len());
+            prop_assert_eq!(head.version, (1, minor));
+            prop_assert_eq!(head.code, code);
+            prop_assert_eq!(head.reason, reason.as_bytes());
+            prop_assert_eq!(head.headers.len(), headers.len());
+
+            for ((name, value), (expected_name, expected_value)) in head.headers.iter().zip(&headers) {
+                prop_assert_eq!(*name, expected_name.as_bytes());
+                prop_assert_eq!(&value[..], expected_value.as_bytes());
+            }
+        }
+
+        #[test]
+        fn prefixes_are_partial((_, _, _, _, raw) in head(), cut in any::<prop::sample::Index>()) {
+            let cut = cut.index(raw.len());
+            prop_assert_eq!(parse(&raw[.. cut]), Ok(Status::Partial));
+        }
+
+        #[test]
+        fn ignores_bytes_after_the_head((_, _, _, _, raw) in head(), body in prop::collection::vec(any::<u8>(), 0 .. 64)) {
+            let expected = parse(&raw);
+
+            let mut input = raw.clone();
+            input.extend_from_slice(&body);
+
+            prop_assert_eq!(parse(&input), expected);
+        }
+
+        #[test]
+        fn joins_folded_lines(parts in prop::collection::vec("[!-~]{1,10}", 1 .. 5)) {
+            let raw = format!("HTTP/1.1 200 OK\r\nX-Folded: {}\r\n\r\n", parts.join("\r\n \t"));
+
+            let (head, _) = match parse(raw.as_bytes()) {
+                Ok(Status::Complete(parsed)) => parsed,
+                other => panic!("unexpected result {:?}", other),
+            };
+
+            let expected = parts.join(" ");
+            prop_assert_eq!(&head.headers[0].1[..], expected.as_bytes());
+        }
+
+        #[test]
+        fn limits_header_count((_, _, _, headers, raw) in head(), max in 0usize .. 20) {
+            let limits = Limits { max_headers: max, .. Limits::default() };
+
+            match parse_headers(&raw, &limits) {
+                Ok(Status::Complete(_)) => prop_assert!(headers.len() <= max),
+                Err(ParseError::TooManyHeaders(n)) => prop_assert!(headers.len() > max && n == max),
+                other => panic!("unexpected result {:?}", other),
             }
-        } else if line.len() > 0 {
-            match parse_header(line) {
-                Ok((name, value)) => {
-                    Some(Ok(Header::Header(name, value)))
+        }
+
+        #[test]
+        fn limits_head_size((_, _, _, _, raw) in head(), max in 1usize .. 1024) {
+            let limits = Limits { max_head_size: max, .. Limits::default() };
+
+            match parse_headers(&raw, &limits) {
+                Ok(Status::Complete((_, len))) => prop_assert!(len <= max),
+                Err(ParseError::HeadTooLarge(n)) => prop_assert!(raw.len() > max && n == max),
+                other => panic!("unexpected result {:?}", other),
+            }
+        }
+    }
+
+    fn header(raw: &[u8], name: &str) -> Option<Vec<u8>> {
+        match parse(raw) {
+            Ok(Status::Complete((head, _))) => head.headers.iter()
+                .find(|(n, _)| *n == name.as_bytes())
+                .map(|(_, value)| value.to_vec()),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn keeps_colons_in_values() {
+        let raw = b"HTTP/1.1 301 Moved Permanently\r\nLocation: http://host:8080/x\r\n\r\n";
+        assert_eq!(header(raw, "Location"), Some(b"http://host:8080/x".to_vec()));
+    }
+
+    #[test]
+    fn trims_whitespace_around_values() {
+        let raw = b"HTTP/1.1 200 OK\r\nServer: \t  a  b \t \r\n\r\n";
+        assert_eq!(header(raw, "Server"), Some(b"a  b".to_vec()));
+    }
+
+    #[test]
+    fn accepts_missing_reason_phrase() {
+        for raw in &[&b"HTTP/1.1 204\r\n\r\n"[..], b"HTTP/1.1 204 \r\n\r\n"] {
+            match parse(raw) {
+                Ok(Status::Complete((head, _))) => {
+                    assert_eq!(head.code, 204);
+                    assert_eq!(head.reason, b"");
                 },
-                Err(err) => Some(Err(err)),
+                other => panic!("unexpected result {:?}", other),
             }
-        } else {
-            None
         }
-    })
-}
\ No newline at end of file
+    }
+
+    #[test]
+    fn accepts_bare_lf() {
+        let raw = b"HTTP/1.0 200 OK\nContent-Length: 2\n\nok";
+
+        match parse(raw) {
+            Ok(Status::Complete((head, len))) => {
+                assert_eq!(head.version, (1, 0));
+                assert_eq!(&raw[len ..], b"ok");
+            },
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_short_status_lines() {
+        for raw in &[&b"\r\n"[..], b"H\r\n", b"HTTP\r\n", b"HTTP/1.1\r\n", b"HTTP/1.1 20\r\n"] {
+            assert!(parse(raw).is_err(), "{:?}", String::from_utf8_lossy(raw));
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_headers() {
+        let lines: &[&[u8]] = &[
+            b"Name : value",
+            b"No colon",
+            b": empty name",
+            b"Bad\x00Name: value",
+            b"Name: bad\x00value",
+            b" leading fold",
+        ];
+
+        for line in lines {
+            let mut raw = b"HTTP/1.1 200 OK\r\n".to_vec();
+            raw.extend_from_slice(line);
+            raw.extend_from_slice(b"\r\n\r\n");
+
+            assert!(matches!(parse(&raw), Err(ParseError::WrongHeader(_))), "{:?}", String::from_utf8_lossy(line));
+        }
+    }
+}
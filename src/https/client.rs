@@ -3,8 +3,9 @@ use std::io;
 
 use futures_legacy::{Async, Future, future, Poll};
 use crate::connect::{Connect, Connected, Destination, HttpConnector};
+use crate::error::Error as ClientError;
 pub use native_tls::Error;
-use native_tls::{self, HandshakeError, TlsConnector};
+use native_tls::{self, HandshakeError, TlsConnector, TlsConnectorBuilder};
 use super::stream::{MaybeHttpsStream, TlsStream};
 
 /// A Connector for the `https` scheme.
@@ -30,16 +31,20 @@ impl HttpsConnector<HttpConnector> {
     ///
     /// If you would like to force the use of HTTPS then call https_only(true)
     /// on the returned connector.
-    pub fn new(threads: usize) -> Result<Self, Error> {
-        TlsConnector::builder()
-            .build()
-            .map(|tls| HttpsConnector::new_(threads, tls))
+    ///
+    /// Fails with `simple_http::Error::Dns` if c-ares can't be initialized, see
+    /// `HttpConnector::try_new`, and with `simple_http::Error::Tls` if the TLS connector
+    /// can't be built.
+    pub fn new(threads: usize) -> Result<Self, ClientError> {
+        HttpsConnector::with_http(HttpConnector::try_new(threads))
     }
 
-    fn new_(threads: usize, tls: TlsConnector) -> Self {
-        let mut http = HttpConnector::new(threads);
+    fn with_http(http: io::Result<HttpConnector>) -> Result<Self, ClientError> {
+        let mut http = http.map_err(ClientError::Dns)?;
+        let tls = tls_builder().build().map_err(ClientError::Tls)?;
+
         http.enforce_http(false);
-        HttpsConnector::from((http, tls))
+        Ok(HttpsConnector::from((http, tls)))
     }
 }
 
@@ -59,7 +64,7 @@ impl<R> HttpsConnector<HttpConnector<R>> {
     /// If you would like to force the use of HTTPS then call https_only(true)
     /// on the returned connector.
     pub fn new_with_resolver(resolver: R) -> Result<Self, Error> {
-        TlsConnector::builder()
+        tls_builder()
             .build()
             .map(|tls| HttpsConnector::new_with_resolver_(resolver, tls))
     }
@@ -72,6 +77,17 @@ impl<R> HttpsConnector<HttpConnector<R>> {
     }
 }
 
+/// The default TLS configuration, which offers HTTP/2 via ALPN with the `http2` feature.
+fn tls_builder() -> TlsConnectorBuilder {
+    #[cfg_attr(not(feature = "http2"), allow(unused_mut))]
+    let mut builder = TlsConnector::builder();
+
+    #[cfg(feature = "http2")]
+    builder.request_alpns(&["h2", "http/1.1"]);
+
+    builder
+}
+
 impl<T> HttpsConnector<T> {
     /// Force the use of HTTPS when connecting.
     ///
@@ -120,8 +136,8 @@ impl<T> Connect for HttpsConnector<T>
         let is_https = dst.scheme() == "https";
         // Early abort if HTTPS is forced but can't be used
         if !is_https && self.force_https {
-            let err = io::Error::new(io::ErrorKind::Other, "HTTPS scheme forced but can't be used");
-            return HttpsConnecting(Box::new(future::err(err)));
+            let err = io::Error::new(io::ErrorKind::InvalidInput, "HTTPS scheme forced but can't be used");
+            return HttpsConnecting(Box::new(future::err(ClientError::InvalidUrl(err).into())));
         }
 
         let host = dst.host().to_owned();
@@ -133,8 +149,16 @@ impl<T> Connect for HttpsConnector<T>
                     inner: Some(tls.connect(&host, tcp)),
                 };
                 handshake
-                    .map(|conn| (MaybeHttpsStream::Https(TlsStream::new(conn)), connected))
-                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+                    .map(|conn| {
+                        #[cfg(feature = "http2")]
+                        let connected = match conn.negotiated_alpn() {
+                            Ok(Some(ref protocol)) if protocol == b"h2" => connected.negotiated_h2(),
+                            _ => connected,
+                        };
+
+                        (MaybeHttpsStream::Https(TlsStream::new(conn)), connected)
+                    })
+                    .map_err(|e| ClientError::Tls(e).into())
             });
             Box::new(fut)
         } else {
@@ -192,4 +216,18 @@ impl<T: io::Read + io::Write> Future for Handshaking<T> {
             Err(HandshakeError::Failure(err)) => Err(err),
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fails_without_a_resolver() {
+        let http = Err(io::Error::new(io::ErrorKind::NotFound, "no /etc/resolv.conf"));
+
+        match HttpsConnector::with_http(http) {
+            Err(ClientError::Dns(err)) => assert_eq!(err.kind(), io::ErrorKind::NotFound),
+            res => panic!("expected a DNS error, got {:?}", res.map(drop)),
+        }
+    }
+}
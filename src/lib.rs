@@ -2,147 +2,981 @@ pub mod http;
 pub mod https;
 pub mod proxy;
 mod request;
+mod request_body;
 mod response;
 mod httparse;
 mod connect;
 mod body;
+mod error;
+mod pool;
+mod timeout;
+mod encoding;
+#[cfg(feature = "http2")]
+mod http2;
+pub mod redirect;
+pub mod middleware;
+pub mod retry;
+pub mod multipart;
+pub mod auth;
+#[cfg(feature = "cookies")]
+pub mod cookie;
 
-use std::{io, mem};
-use std::pin::Pin;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
 use futures::stream::{Stream, StreamExt};
-pub use self::response::Response;
+pub use self::response::{ETag, InvalidETag, Response};
 pub use self::body::Body;
 pub use self::request::Request;
-use self::httparse::{parse_headers, Header};
+pub use self::request_body::RequestBody;
+pub use self::error::Error;
+pub use self::httparse::ParseError;
+pub use self::timeout::TimeoutPhase;
+use self::httparse::{parse_headers, Limits, Status};
 
 pub use hyper::Uri;
-use self::connect::{Connect, Destination};
+use self::connect::{Alpn, Connect, Destination};
 pub use self::https::HttpsConnector;
 pub use self::connect::HttpConnector;
-pub use ::http::Method;
+pub use ::http::{HeaderMap, Method, Version};
+pub use typed_headers::mime;
+use ::http::StatusCode;
+use ::http::header::{HeaderName, HeaderValue, AUTHORIZATION, CONNECTION, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, COOKIE, HOST, TRANSFER_ENCODING};
 use std::marker::PhantomData;
 use futures::compat::*;
+use self::pool::{Conn, Key, Pool};
+use self::body::Length;
+use self::request::{encode_chunk, encode_last_chunk, has_connection_option, Framing};
+use self::timeout::{sleep, timed, Deadline, Timeouts};
+use self::encoding::Codings;
+use self::middleware::{BoxFuture, Endpoint, Middleware, Next};
 
 pub struct Client<C>
     where C: Connect<Error=io::Error>,
 {
-    inner: C
+    inner: C,
+    pool: Pool,
+    timeouts: Timeouts,
+    redirect: redirect::Policy,
+    retry: retry::Policy,
+    limits: Limits,
+    codings: Codings,
+    #[cfg(feature = "json")]
+    max_body_size: Option<usize>,
+    #[cfg(feature = "cookies")]
+    cookie_store: Option<Arc<cookie::Jar>>,
+    auth: auth::Auth,
+    #[cfg(feature = "http2")]
+    http2_prior_knowledge: bool,
+    middleware: Vec<Arc<dyn Middleware>>,
 }
 
 impl<C> Client<C>
     where C: Connect<Error=io::Error>,
 {
     pub fn new(inner: C) -> Self {
-        Self { inner }
+        Self::builder().build(inner)
     }
 
     pub fn builder() -> ClientBuilder<C> {
         ClientBuilder {
+            pool_idle_timeout: Some(Duration::from_secs(90)),
+            pool_max_idle_per_host: usize::MAX,
+            timeouts: Timeouts::default(),
+            redirect: redirect::Policy::default(),
+            retry: retry::Policy::default(),
+            limits: Limits::default(),
+            codings: Codings::default(),
+            #[cfg(feature = "json")]
+            max_body_size: None,
+            #[cfg(feature = "cookies")]
+            cookie_store: None,
+            auth: auth::Auth::default(),
+            #[cfg(feature = "http2")]
+            http2_prior_knowledge: false,
+            middleware: Vec::new(),
             m: Default::default()
         }
     }
 
-    pub async fn request<'a, B>(&'a self, mut req: Request<B>) -> io::Result<Response<Body>>
-        where B: Stream<Item = io::Result<Vec<u8>>> + Send + 'a
+    /// Sends the request through the middleware, following redirects as allowed by the redirect policy.
+    ///
+    /// A `307` or `308` redirect of a request whose body is a stream is not followed,
+    /// since the stream can't be sent again.
+    pub async fn request<B>(&self, req: Request<B>) -> Result<Response<Body>, Error>
+        where B: Stream<Item = io::Result<Vec<u8>>> + Send + Into<RequestBody>
     {
-        let (conn, _)= self.inner.connect(Destination {
-            uri: req.uri.clone()
-        }).compat().await?;
+        let req = req.map(Into::into);
+
+        if self.middleware.is_empty() {
+            return self.execute(req).await;
+        }
+
+        Next::new(&self.middleware, self).run(req).await
+    }
+
+    async fn execute(&self, mut req: Request) -> Result<Response<Body>, Error> {
+        let timeouts = req.timeouts.or(self.timeouts);
+        let total = Deadline::after(timeouts.total, TimeoutPhase::Total);
+        let mut redirects = Vec::new();
+        let mut methods = Vec::new();
+
+        loop {
+            let replayable = req.is_replayable();
+
+            // credentials of the client are added for every hop, to requests without their own
+            let credentials = Some(&req.uri)
+                .filter(|_| !req.headers.contains_key(AUTHORIZATION))
+                .and_then(|uri| self.auth.credentials(uri));
+
+            if let Some(value) = credentials.as_ref().and_then(auth::Credentials::preemptive) {
+                req.headers.insert(AUTHORIZATION, value);
+            }
+
+            let mut res = self.send_with_retries(&mut req, timeouts, total).await?;
+
+            let answer = credentials.as_ref()
+                .filter(|_| res.status() == StatusCode::UNAUTHORIZED && replayable)
+                .and_then(|credentials| self.auth.answer(credentials, &req.method, &req.uri, res.headers()));
+
+            if let Some(value) = answer {
+                req.headers.insert(AUTHORIZATION, value);
+                res = self.send_with_retries(&mut req, timeouts, total).await?;
+            }
+
+            if credentials.is_some() {
+                req.headers.remove(AUTHORIZATION);
+            }
+
+            let next = Some(res.status())
+                .filter(|&status| !self.redirect.is_none() && redirect::is_redirect(status))
+                .and_then(|_| res.location());
+
+            let next = match next {
+                Some(next) => next,
+                None => {
+                    res.redirects = redirects;
+                    return Ok(res);
+                },
+            };
+
+            let method = redirect::next_method(res.status(), &req.method);
+            let replay = method == req.method && method != Method::GET && method != Method::HEAD;
+
+            redirects.push(req.uri.clone());
+            methods.push(req.method.clone());
+
+            let action = self.redirect.check(&redirect::Attempt {
+                status: res.status(),
+                next: &next,
+                method: &method,
+                previous: &redirects,
+                methods: &methods,
+            })?;
+
+            if action == redirect::Action::Stop || (replay && !replayable) {
+                redirects.pop();
+                res.redirects = redirects;
+                return Ok(res);
+            }
+
+            if !replay {
+                req.method = method;
+                req.body = None;
+                req.content_length = None;
+                req.trailers.clear();
+                for name in &[CONTENT_LENGTH, CONTENT_TYPE, CONTENT_ENCODING, TRANSFER_ENCODING] {
+                    req.headers.remove(name);
+                }
+            }
+
+            if redirect::is_cross_origin(&req.uri, &next) {
+                req.headers.remove(AUTHORIZATION);
+                req.headers.remove(COOKIE);
+                req.headers.remove(HOST);
+            }
 
-        // sending headers
-        let header = self.build_req(req.method, req.uri, req.headers);
+            req.uri = next;
+        }
+    }
+
+    /// Sends the request, and again as allowed by the retry policy.
+    async fn send_with_retries(&self, req: &mut Request, timeouts: Timeouts, total: Option<Deadline>) -> Result<Response<Body>, Error> {
+        // a body stream is consumed by the first attempt
+        let replayable = req.is_replayable();
+        let mut number = 1;
+
+        loop {
+            let res = self.send(req, timeouts, total).await;
 
-        let (mut conn, _) = tokio_io::io::write_all(conn, header.as_bytes()).compat().await?;
+            let attempt = retry::Attempt {
+                number,
+                method: &req.method,
+                uri: &req.uri,
+                result: res.as_ref().map(Response::status),
+                retry_after: res.as_ref().ok().and_then(|res| retry::retry_after(res.headers())),
+            };
 
-        // sending body
-        if let Some(mut body) = req.body.take() {
-            let mut x = unsafe {Pin::new_unchecked(&mut body)};
+            let delay = Some(&attempt)
+                .filter(|_| replayable)
+                .and_then(|attempt| self.retry.check(attempt))
+                .filter(|&delay| total.map(|total| delay < total.remaining()).unwrap_or(true));
 
-            while let Some(res) = x.next().await {
-                let (c, _) = tokio_io::io::write_all(conn, res?).compat().await?;
-                conn = c
+            self.retry.observe_attempt(&attempt, delay);
+
+            match delay {
+                Some(delay) => sleep(delay).await?,
+                None => return res,
             }
+
+            number += 1;
         }
+    }
 
-        // receiving headers
-        let mut buf: [u8; 4096] = unsafe { mem::uninitialized() };
-        let mut left = 0usize;
+    async fn send(&self, req: &mut Request, timeouts: Timeouts, total: Option<Deadline>) -> Result<Response<Body>, Error> {
+        let key = Key::new(&req.uri);
+        let framing = req.framing();
+        if framing == Framing::Chunked && req.version == Version::HTTP_10 {
+            return Err(Error::Body(io::Error::new(io::ErrorKind::InvalidInput, "HTTP/1.0 requests need the length of their body")));
+        }
 
-        let (header, rest) = loop {
-            let (tconn, _, len) = tokio_io::io::read(conn, &mut buf[left ..]).compat().await?;
-            conn = tconn;
+        let header = self.build_req(req, framing);
 
-            if len == 0 {
-                return Err(io::Error::new(io::ErrorKind::Other, "Broken headers".to_string()));
+        loop {
+            #[cfg(feature = "http2")]
+            {
+                if let Some(h2) = key.as_ref().and_then(|key| self.pool.checkout_h2(key)) {
+                    match timed(http2::ready(h2), total).await {
+                        Ok(h2) => return self.send_h2(h2, req, framing, timeouts, total).await,
+                        Err(err @ Error::Timeout(..)) => return Err(err),
+                        // the connection was closed, another one is opened
+                        Err(_) => self.pool.remove_h2(key.as_ref().expect("pooled connections have a key")),
+                    }
+                }
             }
 
-            left += len;
+            let pooled = key.as_ref().and_then(|key| self.pool.checkout(key));
+            let reused = pooled.is_some();
 
-            if let Some(mut idx) = buf[..left].windows(4).position(|s| s == b"\r\n\r\n") {
-                idx += 4;
-                let mut status = 0u16;
-                let mut headers = Vec::new();
-                let mut reason = String::new();
+            let conn = match pooled {
+                Some(conn) => conn,
+                None => {
+                    let deadline = Deadline::earliest(Deadline::after(timeouts.connect, TimeoutPhase::Connect), total);
 
-                for res in parse_headers(&buf[0 .. idx]) {
-                    let item = res.map_err(|_err| io::Error::new(io::ErrorKind::Other, "Parse header error"))?;
+                    match timed(self.connect(&req.uri, timeouts.connect_attempt), deadline).await? {
+                        Connection::Http1(conn) => conn,
+                        #[cfg(feature = "http2")]
+                        Connection::Http2(h2) => {
+                            // another request connected to the origin meanwhile, its connection is shared
+                            // and this one closes
+                            if let Some(ref key) = key {
+                                if self.pool.insert_h2(key.clone(), h2.clone()).is_some() {
+                                    continue;
+                                }
+                            }
 
-                    match item {
-                        Header::Status(code, r, _) => {
-                            status = code;
-                            reason = String::from_utf8_lossy(r).to_string();
+                            return self.send_h2(h2, req, framing, timeouts, total).await;
                         },
-                        Header::Header(name, value) => headers.push((
-                            String::from_utf8_lossy(name).to_lowercase(),
-                            String::from_utf8_lossy(value).to_string())),
                     }
+                },
+            };
+
+            // An idle connection may have been closed by the server in the meantime, so a
+            // request that could not be written is sent again on a new one. Once it was
+            // written, the server may have acted on it before closing the connection, so
+            // only idempotent requests are sent again when no response was received.
+            let can_resend = reused && req.is_replayable();
+            let can_retry = can_resend && retry::is_idempotent(&req.method);
+
+            // sending headers
+            let mut conn = match write(conn, header.clone(), total).await {
+                Ok(conn) => conn,
+                Err(Error::Io(_)) if can_resend => continue,
+                Err(err) => return Err(err),
+            };
+
+            // sending body
+            if let Some(mut body) = req.take_body() {
+                let mut written = 0u64;
+
+                while let Some(res) = timed(async { Ok(body.next().await) }, total).await? {
+                    let chunk = res.map_err(Error::Body)?;
+                    written += chunk.len() as u64;
+
+                    let chunk = match framing {
+                        Framing::Chunked if chunk.is_empty() => continue,
+                        Framing::Chunked => encode_chunk(&chunk),
+                        Framing::Length(len) if written > len => return Err(body_length_mismatch(len)),
+                        _ => chunk,
+                    };
+
+                    conn = write(conn, chunk, total).await?;
                 }
 
-                break ((status, reason, headers), &buf[idx .. left]);
+                match framing {
+                    Framing::Chunked => conn = write(conn, encode_last_chunk(&req.trailers), total).await?,
+                    Framing::Length(len) if written != len => return Err(body_length_mismatch(len)),
+                    _ => (),
+                }
             }
+
+            // receiving headers
+            let limits = self.limits;
+            let head = async move {
+                // grows up to the maximum head size, the parser fails once it is reached
+                let mut buf = vec![0u8; limits.max_head_size.min(4096).max(1)];
+                let mut left = 0usize;
+                // once anything was received, the connection was not stale
+                let mut received = false;
+
+                loop {
+                    if left == buf.len() {
+                        let len = (buf.len() * 2).min(limits.max_head_size);
+                        buf.resize(len.max(left + 1), 0);
+                    }
+
+                    let (tconn, _, len) = match tokio_io::io::read(conn, &mut buf[left ..]).compat().await {
+                        Ok(res) => res,
+                        Err(_) if can_retry && !received => return Ok(None),
+                        Err(err) => return Err(err.into()),
+                    };
+                    conn = tconn;
+
+                    if len == 0 {
+                        if can_retry && !received {
+                            return Ok(None);
+                        }
+
+                        return Err(Error::Io(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed before the end of the response head")));
+                    }
+
+                    left += len;
+                    received = true;
+
+                    while let Status::Complete((head, len)) = parse_headers(&buf[.. left], &limits)? {
+                        // interim responses are skipped, the final one follows on the connection;
+                        // a 101 is final, the connection speaks another protocol after it
+                        if head.code / 100 == 1 && head.code != 101 {
+                            buf.copy_within(len .. left, 0);
+                            left -= len;
+                            continue;
+                        }
+
+                        let mut headers = HeaderMap::with_capacity(head.headers.len());
+
+                        for (name, value) in head.headers {
+                            let name = HeaderName::from_bytes(name)
+                                .map_err(|_| ParseError::WrongHeader(name.to_vec()))?;
+                            let value = HeaderValue::from_bytes(&value)
+                                .map_err(|_| ParseError::WrongHeader(value.to_vec()))?;
+
+                            headers.append(name, value);
+                        }
+
+                        return Ok(Some((conn, head.code, head.version, headers, buf[len .. left].to_vec())));
+                    }
+                }
+            };
+
+            let deadline = Deadline::earliest(Deadline::after(timeouts.head, TimeoutPhase::Head), total);
+            let received = timed(head, deadline).await?;
+
+            let (conn, status, version, headers, rest) = match received {
+                Some(received) => received,
+                None => continue,
+            };
+
+            let rest = Some(rest);
+            let length = if req.method == Method::HEAD || status == 101 || status == 204 || status == 304 {
+                Length::Exact(0)
+            } else if let Some(te) = headers.get_all(TRANSFER_ENCODING).iter().last() {
+                // chunked must be the final encoding, otherwise the body is delimited by close,
+                // and HTTP/1.0 has no transfer codings at all
+                let te = te.to_str().unwrap_or("");
+                if version != (1, 0) && te.rsplit(',').next().map(|v| v.trim().eq_ignore_ascii_case("chunked")).unwrap_or(false) {
+                    Length::Chunked
+                } else {
+                    Length::CloseDelimited
+                }
+            } else {
+                headers.get(CONTENT_LENGTH)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|v| v.trim().parse::<usize>().ok())
+                    .map(Length::Exact)
+                    .unwrap_or(Length::CloseDelimited)
+            };
+
+            // HTTP/1.1 connections persist unless closed or upgraded, HTTP/1.0 ones only when asked to
+            let keep_alive = req.keep_alive() && status != 101 && if version == (1, 0) {
+                has_connection_option(&headers, "keep-alive")
+            } else {
+                !has_connection_option(&headers, "close")
+            };
+
+            let pool = key.filter(|_| keep_alive).map(|key| (self.pool.clone(), key));
+            let body = Body::from_conn(conn, rest, length, pool)
+                .timeouts(timeouts.body_idle, total);
+
+            let version = if version == (1, 0) { Version::HTTP_10 } else { Version::HTTP_11 };
+
+            return Ok(self.response(req, status, version, headers, body, length));
+        }
+    }
+
+    /// Sends the request on a new stream of an HTTP/2 connection.
+    #[cfg(feature = "http2")]
+    async fn send_h2(&self, h2: http2::SendRequest, req: &mut Request, framing: Framing, timeouts: Timeouts, total: Option<Deadline>) -> Result<Response<Body>, Error> {
+        let head = self.build_h2_req(req, framing);
+        let length = match framing {
+            Framing::Length(len) => Some(len),
+            _ => None,
         };
 
-        let content_length = header.2.iter().find_map(|(k, v)| if k == "content-length" { v.parse::<usize>().ok() } else { None });
+        let trailers = req.trailers.iter()
+            .filter_map(|(name, value)| Some((HeaderName::from_bytes(name.as_bytes()).ok()?, HeaderValue::from_str(value).ok()?)))
+            .collect();
+
+        let deadline = Deadline::earliest(Deadline::after(timeouts.head, TimeoutPhase::Head), total);
+        let (parts, recv) = http2::send(h2, head, req.take_body(), length, trailers, total, deadline).await?.into_parts();
+
+        // the end of the stream is the end of the body
+        let status = parts.status.as_u16();
+        let length = if req.method == Method::HEAD || status == 204 || status == 304 {
+            Length::Exact(0)
+        } else {
+            Length::CloseDelimited
+        };
+
+        let body = Body::new(http2::RecvBody::new(recv), None, None)
+            .timeouts(timeouts.body_idle, total);
+
+        Ok(self.response(req, status, Version::HTTP_2, parts.headers, body, length))
+    }
+
+    /// The response to `req`, with its cookies stored and its body decoded.
+    fn response(&self, req: &Request, status: u16, version: Version, mut headers: HeaderMap, mut body: Body, length: Length) -> Response<Body> {
+        #[cfg(feature = "cookies")]
+        {
+            if let Some(ref jar) = self.cookie_store {
+                for value in headers.get_all(::http::header::SET_COOKIE) {
+                    if let Ok(value) = value.to_str() {
+                        jar.set_cookie(&req.uri, value);
+                    }
+                }
+            }
+        }
+
+        let content_encoding = headers.get_all(CONTENT_ENCODING).iter()
+            .map(|v| v.to_str().unwrap_or("unknown"))
+            .collect::<Vec<_>>()
+            .join(",");
+
+        let decoder = Some(length)
+            .filter(|&length| req.decompress() && length != Length::Exact(0))
+            .and_then(|_| self.codings.decoder(&content_encoding));
 
-        Ok(Response::new(header.0, header.2, Body::new(conn, Some(rest.to_vec()), content_length)))
+        if let Some(decoder) = decoder {
+            // the headers describe the encoded body, not the decoded one
+            headers.remove(CONTENT_ENCODING);
+            headers.remove(CONTENT_LENGTH);
+            body = body.decoded(decoder);
+        }
+
+        let mut res = Response::new(status, headers, body);
+        res.uri = req.uri.clone();
+        res.version = version;
+        #[cfg(feature = "json")]
+        {
+            res.max_body_size = self.max_body_size;
+        }
+
+        res
+    }
+
+    #[cfg_attr(not(feature = "http2"), allow(unused_variables))]
+    async fn connect(&self, uri: &Uri, attempt_timeout: Option<Duration>) -> Result<Connection, Error> {
+        #[cfg(feature = "http2")]
+        let alpn = if self.http2_prior_knowledge { Alpn::H2 } else { Alpn::Http1 };
+        #[cfg(not(feature = "http2"))]
+        let alpn = Alpn::Http1;
+
+        let (conn, connected) = self.inner.connect(Destination {
+            uri: uri.clone(),
+            connect_timeout: attempt_timeout,
+            alpn,
+        }).compat().await?;
+
+        let conn: Conn = Box::new(conn);
+
+        // HTTP/2 is negotiated for https, and spoken with prior knowledge for http unless through a proxy
+        #[cfg(feature = "http2")]
+        {
+            let https = uri.scheme_str() == Some("https");
+
+            if connected.is_negotiated_h2() || (self.http2_prior_knowledge && !https && !connected.is_proxied()) {
+                let h2 = http2::handshake(conn).await?;
+                return Ok(Connection::Http2(http2::ready(h2).await?));
+            }
+
+            if self.http2_prior_knowledge && https {
+                return Err(Error::Connect(io::Error::other("the server did not negotiate HTTP/2")));
+            }
+        }
+
+        Ok(Connection::Http1(conn))
     }
 
-    fn build_req(&self, method: Method, url: Uri, headers: Vec<(String, String)>) -> String {
-        let req = url.path_and_query().map(|v|v.as_str()).unwrap_or("/");
-        let mut header = format!("{} {} HTTP/1.0\r\n", method.as_str(), req);
+    fn build_req(&self, req: &Request, framing: Framing) -> Vec<u8> {
+        let url = &req.uri;
+        let path = url.path_and_query().map(|v|v.as_str()).unwrap_or("/");
+        let version = if req.version == Version::HTTP_10 { "HTTP/1.0" } else { "HTTP/1.1" };
+        let mut header = format!("{} {} {}\r\n", req.method.as_str(), path, version);
+
+        // a Host header set on the request is sent with the other headers instead
+        if !req.headers.contains_key(HOST) {
+            header.push_str(&format!("Host: {}\r\n", host(url)));
+        }
+
+        if !req.headers.contains_key(CONNECTION) {
+            if !req.keep_alive {
+                header.push_str("Connection: close\r\n");
+            } else if req.version == Version::HTTP_10 {
+                header.push_str("Connection: keep-alive\r\n");
+            }
+        }
+
+        if let Some(codings) = self.codings.accept_encoding().filter(|_| req.decompress()) {
+            header.push_str(&format!("Accept-Encoding: {}\r\n", codings));
+        }
+
+        let has_length = req.headers.contains_key(CONTENT_LENGTH);
+
+        match framing {
+            Framing::Length(len) if !has_length => {
+                header.push_str(&format!("Content-Length: {}\r\n", len));
+            },
+            Framing::Chunked => {
+                header.push_str("Transfer-Encoding: chunked\r\n");
+
+                if !req.trailers.is_empty() {
+                    let names: Vec<_> = req.trailers.iter().map(|(name, _)| &name[..]).collect();
+                    header.push_str(&format!("Trailer: {}\r\n", names.join(", ")));
+                }
+            },
+            _ => (),
+        }
+
+        let mut header = header.into_bytes();
 
-        header.push_str("Host: ");
-        header.push_str(&url.host().unwrap().to_string());
-        header.push_str("\r\n");
-        header.push_str("Connection: close\r\n");
+        if let Some(cookies) = self.cookie_header(req) {
+            header.extend_from_slice(b"Cookie: ");
+            header.extend_from_slice(&cookies);
+            header.extend_from_slice(b"\r\n");
+        }
 
-        for (name, value) in &headers {
-            if name.to_lowercase() == "host" {
+        for (name, value) in &req.headers {
+            if (name == TRANSFER_ENCODING && framing == Framing::Chunked) || name == COOKIE {
                 continue;
             }
 
-            header.push_str(name);
-            header.push_str(": ");
-            header.push_str(value);
-            header.push_str("\r\n");
+            header.extend_from_slice(name.as_str().as_bytes());
+            header.extend_from_slice(b": ");
+            header.extend_from_slice(value.as_bytes());
+            header.extend_from_slice(b"\r\n");
         }
 
-        header.push_str("\r\n");
+        header.extend_from_slice(b"\r\n");
 
         header
     }
+
+    /// The head of an HTTP/2 request, the connection specific headers left out (RFC 7540, section 8.1.2.2).
+    #[cfg(feature = "http2")]
+    fn build_h2_req(&self, req: &Request, framing: Framing) -> ::http::Request<()> {
+        use ::http::header::{ACCEPT_ENCODING, TE, UPGRADE};
+
+        let mut head = ::http::Request::new(());
+        *head.method_mut() = req.method.clone();
+        *head.version_mut() = Version::HTTP_2;
+
+        // a Host header set on the request becomes the authority
+        let authority = req.headers.get(HOST)
+            .and_then(|host| host.to_str().ok())
+            .and_then(|host| host.parse().ok());
+
+        *head.uri_mut() = match authority {
+            Some(authority) => {
+                let mut parts = ::http::uri::Parts::from(req.uri.clone());
+                parts.authority = Some(authority);
+                Uri::from_parts(parts).unwrap_or_else(|_| req.uri.clone())
+            },
+            None => req.uri.clone(),
+        };
+
+        let listed: Vec<_> = req.headers.get_all(CONNECTION).iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(|name| HeaderName::from_bytes(name.trim().as_bytes()).ok())
+            .collect();
+
+        let skipped = [HOST, CONNECTION, TRANSFER_ENCODING, UPGRADE, COOKIE, HeaderName::from_static("keep-alive"), HeaderName::from_static("proxy-connection")];
+        let headers = head.headers_mut();
+
+        for (name, value) in &req.headers {
+            if skipped.contains(name) || listed.contains(name) || (name == TE && value != "trailers") {
+                continue;
+            }
+
+            headers.append(name.clone(), value.clone());
+        }
+
+        if let Some(codings) = self.codings.accept_encoding().filter(|_| req.decompress()) {
+            headers.insert(ACCEPT_ENCODING, HeaderValue::from_str(&codings).expect("codings are tokens"));
+        }
+
+        if let Framing::Length(len) = framing {
+            if !headers.contains_key(CONTENT_LENGTH) {
+                headers.insert(CONTENT_LENGTH, HeaderValue::from(len));
+            }
+        }
+
+        if let Some(cookies) = self.cookie_header(req).and_then(|cookies| HeaderValue::from_bytes(&cookies).ok()) {
+            headers.insert(COOKIE, cookies);
+        }
+
+        head
+    }
+
+    /// The cookies of the request and of the cookie store, sent in a single header (RFC 6265, section 5.4).
+    fn cookie_header(&self, req: &Request) -> Option<Vec<u8>> {
+        #[cfg(feature = "cookies")]
+        let stored = self.cookie_store.as_ref().and_then(|jar| jar.cookies(&req.uri));
+        #[cfg(not(feature = "cookies"))]
+        let stored: Option<String> = None;
+
+        let cookies: Vec<_> = req.headers.get_all(COOKIE).iter()
+            .map(HeaderValue::as_bytes)
+            .chain(stored.as_ref().map(String::as_bytes))
+            .collect();
+
+        Some(cookies.join(&b"; "[..])).filter(|_| !cookies.is_empty())
+    }
+}
+
+/// A new connection to an origin.
+enum Connection {
+    Http1(Conn),
+    #[cfg(feature = "http2")]
+    Http2(http2::SendRequest),
+}
+
+/// Clones share the connection pool, the cookie store and the middleware.
+impl<C> Clone for Client<C>
+    where C: Connect<Error=io::Error> + Clone,
+{
+    fn clone(&self) -> Self {
+        Client {
+            inner: self.inner.clone(),
+            pool: self.pool.clone(),
+            timeouts: self.timeouts,
+            redirect: self.redirect.clone(),
+            retry: self.retry.clone(),
+            limits: self.limits,
+            codings: self.codings,
+            #[cfg(feature = "json")]
+            max_body_size: self.max_body_size,
+            #[cfg(feature = "cookies")]
+            cookie_store: self.cookie_store.clone(),
+            auth: self.auth.clone(),
+            #[cfg(feature = "http2")]
+            http2_prior_knowledge: self.http2_prior_knowledge,
+            middleware: self.middleware.clone(),
+        }
+    }
+}
+
+impl<C> Endpoint for Client<C>
+    where C: Connect<Error=io::Error>,
+{
+    fn call<'a>(&'a self, req: Request) -> BoxFuture<'a, Result<Response<Body>, Error>> {
+        Box::pin(self.execute(req))
+    }
+}
+
+async fn write(conn: Conn, buf: Vec<u8>, deadline: Option<Deadline>) -> Result<Conn, Error> {
+    let write = async {
+        let (conn, _) = tokio_io::io::write_all(conn, buf).compat().await?;
+        Ok(conn)
+    };
+
+    timed(write, deadline).await
+}
+
+/// Value of the `Host` header for `uri`, with the port when it is not the default one of the scheme.
+fn host(uri: &Uri) -> String {
+    let host = uri.host().unwrap_or("");
+    let default_port = match uri.scheme_str() {
+        Some("https") => 443,
+        _ => 80,
+    };
+
+    match uri.port_u16() {
+        Some(port) if port != default_port => format!("{}:{}", host, port),
+        _ => host.to_string(),
+    }
+}
+
+fn body_length_mismatch(len: u64) -> Error {
+    Error::Body(io::Error::new(io::ErrorKind::InvalidInput, format!("request body does not match its length of {} bytes", len)))
 }
 
 pub struct ClientBuilder<C>
     where C: Connect<Error=io::Error>,
 {
+    pool_idle_timeout: Option<Duration>,
+    pool_max_idle_per_host: usize,
+    timeouts: Timeouts,
+    redirect: redirect::Policy,
+    retry: retry::Policy,
+    limits: Limits,
+    codings: Codings,
+    #[cfg(feature = "json")]
+    max_body_size: Option<usize>,
+    #[cfg(feature = "cookies")]
+    cookie_store: Option<Arc<cookie::Jar>>,
+    auth: auth::Auth,
+    #[cfg(feature = "http2")]
+    http2_prior_knowledge: bool,
+    middleware: Vec<Arc<dyn Middleware>>,
     m: PhantomData<fn(C)>
 }
 
 impl<C> ClientBuilder<C>
     where C: Connect<Error=io::Error>,
 {
+    /// How long an idle keep-alive connection is kept in the pool.
+    ///
+    /// `None` keeps idle connections until the server closes them. Default is 90 seconds.
+    pub fn pool_idle_timeout(self, timeout: Option<Duration>) -> Self {
+        Self {
+            pool_idle_timeout: timeout,
+            .. self
+        }
+    }
+
+    /// Maximum number of idle connections kept per scheme, host and port.
+    ///
+    /// Set to `0` to disable connection reuse.
+    pub fn pool_max_idle_per_host(self, max: usize) -> Self {
+        Self {
+            pool_max_idle_per_host: max,
+            .. self
+        }
+    }
+
+    /// Timeout for establishing a connection, including DNS resolution,
+    /// the TLS handshake and a proxy tunnel.
+    ///
+    /// Timeouts are driven by the timer of the Tokio runtime, or by a timer thread of the
+    /// client outside of a runtime. Every timeout can be overridden per request.
+    pub fn connect_timeout(self, timeout: Option<Duration>) -> Self {
+        Self {
+            timeouts: Timeouts { connect: timeout, .. self.timeouts },
+            .. self
+        }
+    }
+
+    /// Timeout for each TCP connection attempt to one of the resolved addresses.
+    ///
+    /// When an attempt times out the next address is tried.
+    pub fn connect_attempt_timeout(self, timeout: Option<Duration>) -> Self {
+        Self {
+            timeouts: Timeouts { connect_attempt: timeout, .. self.timeouts },
+            .. self
+        }
+    }
+
+    /// Timeout for receiving the response head once the request was sent.
+    pub fn head_timeout(self, timeout: Option<Duration>) -> Self {
+        Self {
+            timeouts: Timeouts { head: timeout, .. self.timeouts },
+            .. self
+        }
+    }
+
+    /// Timeout between two reads of the response body.
+    pub fn body_idle_timeout(self, timeout: Option<Duration>) -> Self {
+        Self {
+            timeouts: Timeouts { body_idle: timeout, .. self.timeouts },
+            .. self
+        }
+    }
+
+    /// Deadline for the whole request, from connecting until the end of the response body.
+    pub fn timeout(self, timeout: Option<Duration>) -> Self {
+        Self {
+            timeouts: Timeouts { total: timeout, .. self.timeouts },
+            .. self
+        }
+    }
+
+    /// The policy deciding which redirects are followed.
+    ///
+    /// Default is `Policy::limited(10)`.
+    pub fn redirect(self, policy: redirect::Policy) -> Self {
+        Self {
+            redirect: policy,
+            .. self
+        }
+    }
+
+    /// The policy deciding which failed requests are retried.
+    ///
+    /// Default is `Policy::none()`.
+    pub fn retry(self, policy: retry::Policy) -> Self {
+        Self {
+            retry: policy,
+            .. self
+        }
+    }
+
+    /// Maximum size of a response head, including the status line.
+    ///
+    /// Larger responses fail with `ParseError::HeadTooLarge`. Default is 64 KiB.
+    pub fn max_head_size(self, size: usize) -> Self {
+        Self {
+            limits: Limits { max_head_size: size, .. self.limits },
+            .. self
+        }
+    }
+
+    /// Maximum number of header fields in a response head.
+    ///
+    /// Responses with more fail with `ParseError::TooManyHeaders`. Default is 100.
+    pub fn max_headers(self, max: usize) -> Self {
+        Self {
+            limits: Limits { max_headers: max, .. self.limits },
+            .. self
+        }
+    }
+
+    /// Whether gzip encoded responses are requested and decoded.
+    ///
+    /// Default is `true`. Can be disabled per request with `RequestBuilder::decompress`.
+    #[cfg(feature = "gzip")]
+    pub fn gzip(self, enable: bool) -> Self {
+        Self {
+            codings: Codings { gzip: enable, .. self.codings },
+            .. self
+        }
+    }
+
+    /// Whether deflate encoded responses are requested and decoded.
+    ///
+    /// Default is `true`. Can be disabled per request with `RequestBuilder::decompress`.
+    #[cfg(feature = "deflate")]
+    pub fn deflate(self, enable: bool) -> Self {
+        Self {
+            codings: Codings { deflate: enable, .. self.codings },
+            .. self
+        }
+    }
+
+    /// Whether brotli encoded responses are requested and decoded.
+    ///
+    /// Default is `true`. Can be disabled per request with `RequestBuilder::decompress`.
+    #[cfg(feature = "brotli")]
+    pub fn brotli(self, enable: bool) -> Self {
+        Self {
+            codings: Codings { brotli: enable, .. self.codings },
+            .. self
+        }
+    }
+
+    /// Maximum size of a response body read with `Response::bytes`, `text` or `json`.
+    ///
+    /// The size is the one of the decoded body. Default is no limit.
+    #[cfg(feature = "json")]
+    pub fn max_body_size(self, size: usize) -> Self {
+        Self {
+            max_body_size: Some(size),
+            .. self
+        }
+    }
+
+    /// Stores the cookies of responses in `jar` and sends them with matching requests.
+    ///
+    /// The jar can be shared with other clients. Default is no cookie store.
+    #[cfg(feature = "cookies")]
+    pub fn cookie_store(self, jar: Arc<cookie::Jar>) -> Self {
+        Self {
+            cookie_store: Some(jar),
+            .. self
+        }
+    }
+
+    /// Credentials for the requests to `host`, which includes the port when it is not the default one.
+    ///
+    /// Credentials given for a host are used before the ones of the credential provider.
+    pub fn credentials(self, host: impl Into<String>, credentials: auth::Credentials) -> Self {
+        let mut hosts = self.auth.hosts;
+        hosts.insert(host.into().to_ascii_lowercase(), credentials);
+
+        Self {
+            auth: auth::Auth { hosts, .. self.auth },
+            .. self
+        }
+    }
+
+    /// Gives the credentials for the requests to hosts without credentials of their own.
+    pub fn credential_provider(self, provider: impl auth::Provider) -> Self {
+        Self {
+            auth: auth::Auth { provider: Some(Arc::new(provider)), .. self.auth },
+            .. self
+        }
+    }
+
+    /// Speaks HTTP/2 to `http` origins without asking first, and requires `https` origins to negotiate it.
+    ///
+    /// Otherwise HTTP/2 is used when an `https` origin negotiates it with ALPN. Default is `false`.
+    #[cfg(feature = "http2")]
+    pub fn http2_prior_knowledge(self, enable: bool) -> Self {
+        Self {
+            http2_prior_knowledge: enable,
+            .. self
+        }
+    }
+
+    /// Adds a middleware around every request.
+    ///
+    /// Middleware run in the order they are added, the first one sees the request first.
+    pub fn middleware(self, middleware: impl Middleware) -> Self {
+        let mut list = self.middleware;
+        list.push(Arc::new(middleware));
+
+        Self {
+            middleware: list,
+            .. self
+        }
+    }
+
     pub fn build(self, connector: C) -> Client<C> {
-        Client::new(connector)
+        Client {
+            inner: connector,
+            pool: Pool::new(self.pool_idle_timeout, self.pool_max_idle_per_host),
+            timeouts: self.timeouts,
+            redirect: self.redirect,
+            retry: self.retry,
+            limits: self.limits,
+            codings: self.codings,
+            #[cfg(feature = "json")]
+            max_body_size: self.max_body_size,
+            #[cfg(feature = "cookies")]
+            cookie_store: self.cookie_store,
+            auth: self.auth,
+            #[cfg(feature = "http2")]
+            http2_prior_knowledge: self.http2_prior_knowledge,
+            middleware: self.middleware,
+        }
     }
-}
\ No newline at end of file
+}
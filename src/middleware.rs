@@ -0,0 +1,97 @@
+//! Middleware wrapping every request sent by a `Client`.
+//!
+//! Middleware are installed with `ClientBuilder::middleware` and run in the order
+//! they were added: the first one sees the request first and the response last.
+//! They wrap the whole exchange, redirects included.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use crate::{Body, Error, Request, Response};
+
+/// The future returned by a middleware.
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// A layer around the requests of a `Client`.
+///
+/// A middleware may change the request before passing it on with `next.run(req)`,
+/// change the response or the error it gets back, or answer on its own without
+/// calling `next` at all.
+pub trait Middleware: Send + Sync + 'static {
+    fn handle<'a>(&'a self, req: Request, next: Next<'a>) -> BoxFuture<'a, Result<Response<Body>, Error>>;
+}
+
+/// The rest of the middleware chain, ending with the client sending the request.
+pub struct Next<'a> {
+    middleware: &'a [Arc<dyn Middleware>],
+    endpoint: &'a dyn Endpoint,
+}
+
+impl<'a> Next<'a> {
+    pub(crate) fn new(middleware: &'a [Arc<dyn Middleware>], endpoint: &'a dyn Endpoint) -> Self {
+        Next { middleware, endpoint }
+    }
+
+    /// Passes the request to the next middleware, or sends it when this is the last one.
+    pub fn run(self, req: Request) -> BoxFuture<'a, Result<Response<Body>, Error>> {
+        match self.middleware.split_first() {
+            Some((middleware, rest)) => middleware.handle(req, Next::new(rest, self.endpoint)),
+            None => self.endpoint.call(req),
+        }
+    }
+}
+
+/// Sends a request once it went through all middleware.
+pub(crate) trait Endpoint: Sync {
+    fn call<'a>(&'a self, req: Request) -> BoxFuture<'a, Result<Response<Body>, Error>>;
+}
+
+#[cfg(feature = "tower")]
+mod tower {
+    use std::io;
+    use std::task::{Context, Poll};
+    use tower_service::Service;
+
+    use super::BoxFuture;
+    use crate::connect::Connect;
+    use crate::{Body, Client, Error, Request, Response};
+
+    /// Lets a client be wrapped by `tower` layers.
+    ///
+    /// Each call sends the request with a clone of the client, so the future is `'static`.
+    impl<C> Service<Request> for Client<C>
+        where C: Connect<Error=io::Error> + Clone + 'static,
+    {
+        type Response = Response<Body>;
+        type Error = Error;
+        type Future = BoxFuture<'static, Result<Response<Body>, Error>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, req: Request) -> Self::Future {
+            let client = self.clone();
+            Box::pin(async move { client.request(req).await })
+        }
+    }
+
+    /// Lets a borrowed client be called as a service, without cloning it.
+    impl<'a, C> Service<Request> for &'a Client<C>
+        where C: Connect<Error=io::Error>,
+    {
+        type Response = Response<Body>;
+        type Error = Error;
+        type Future = BoxFuture<'a, Result<Response<Body>, Error>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, req: Request) -> Self::Future {
+            let client: &'a Client<C> = self;
+            Box::pin(client.request(req))
+        }
+    }
+}
@@ -0,0 +1,304 @@
+//! `multipart/form-data` request bodies (RFC 7578).
+//!
+//! A `Form` is sent with `RequestBuilder::multipart`, which also sets the
+//! `Content-Type` header carrying the boundary.
+
+use std::io;
+use std::path::Path;
+
+use ::http::header::{HeaderMap, HeaderName, HeaderValue};
+use typed_headers::mime::{self, Mime};
+
+use crate::request_body::RequestBody;
+
+/// The parts of a `multipart/form-data` body.
+#[derive(Debug)]
+pub struct Form {
+    boundary: String,
+    parts: Vec<(String, Part)>,
+}
+
+impl Form {
+    /// An empty form with a random boundary.
+    pub fn new() -> Self {
+        Form {
+            boundary: format!("{:016x}{:016x}", rand::random::<u64>(), rand::random::<u64>()),
+            parts: Vec::new(),
+        }
+    }
+
+    pub fn boundary(&self) -> &str {
+        &self.boundary
+    }
+
+    /// Value of the `Content-Type` header of a request with this form for body.
+    pub fn content_type(&self) -> String {
+        format!("multipart/form-data; boundary={}", self.boundary)
+    }
+
+    /// Adds a text field.
+    pub fn text(self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.part(name, Part::text(value))
+    }
+
+    /// Adds the file at `path` as a field, see `Part::file`.
+    pub fn file(self, name: impl Into<String>, path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(self.part(name, Part::file(path)?))
+    }
+
+    pub fn part(self, name: impl Into<String>, part: Part) -> Self {
+        let mut parts = self.parts;
+        parts.push((name.into(), part));
+
+        Self {
+            parts,
+            .. self
+        }
+    }
+}
+
+impl Default for Form {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The body of the form, its length is known when the length of every part is.
+impl From<Form> for RequestBody {
+    fn from(form: Form) -> Self {
+        let mut bodies = Vec::with_capacity(form.parts.len() * 2 + 1);
+        let mut delimiter = format!("--{}\r\n", form.boundary);
+
+        for (name, part) in form.parts {
+            let mut head = delimiter.into_bytes();
+            head.extend_from_slice(format!("Content-Disposition: form-data; name=\"{}\"", escape(&name)).as_bytes());
+
+            if let Some(ref filename) = part.filename {
+                head.extend_from_slice(format!("; filename=\"{}\"", escape(filename)).as_bytes());
+            }
+
+            head.extend_from_slice(b"\r\n");
+
+            if let Some(ref mime) = part.mime {
+                head.extend_from_slice(format!("Content-Type: {}\r\n", mime).as_bytes());
+            }
+
+            for (name, value) in &part.headers {
+                head.extend_from_slice(name.as_str().as_bytes());
+                head.extend_from_slice(b": ");
+                head.extend_from_slice(value.as_bytes());
+                head.extend_from_slice(b"\r\n");
+            }
+
+            head.extend_from_slice(b"\r\n");
+
+            bodies.push(RequestBody::from(head));
+            bodies.push(part.body);
+
+            delimiter = format!("\r\n--{}\r\n", form.boundary);
+        }
+
+        let end = if bodies.is_empty() {
+            format!("--{}--\r\n", form.boundary)
+        } else {
+            format!("\r\n--{}--\r\n", form.boundary)
+        };
+
+        bodies.push(RequestBody::from(end));
+        RequestBody::concat(bodies)
+    }
+}
+
+/// A field of a `Form`.
+#[derive(Debug)]
+pub struct Part {
+    body: RequestBody,
+    filename: Option<String>,
+    mime: Option<Mime>,
+    headers: HeaderMap,
+}
+
+impl Part {
+    pub fn text(value: impl Into<String>) -> Self {
+        Self::body(value.into().into())
+    }
+
+    pub fn bytes(value: impl Into<Vec<u8>>) -> Self {
+        Self::body(value.into().into())
+    }
+
+    /// A part with any body, including a stream of unknown length.
+    pub fn body(body: RequestBody) -> Self {
+        Part {
+            body,
+            filename: None,
+            mime: None,
+            headers: HeaderMap::new(),
+        }
+    }
+
+    /// The file at `path`, read when the request is sent.
+    ///
+    /// The file name is taken from the path and the type is `application/octet-stream`.
+    pub fn file(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let part = Self::body(RequestBody::file(path)?).mime(mime::APPLICATION_OCTET_STREAM);
+
+        Ok(match path.file_name() {
+            Some(name) => part.file_name(name.to_string_lossy()),
+            None => part,
+        })
+    }
+
+    pub fn file_name(self, filename: impl Into<String>) -> Self {
+        Self {
+            filename: Some(filename.into()),
+            .. self
+        }
+    }
+
+    /// The `Content-Type` of the part.
+    pub fn mime(self, mime: Mime) -> Self {
+        Self {
+            mime: Some(mime),
+            .. self
+        }
+    }
+
+    /// Adds a header to the part, besides `Content-Disposition` and `Content-Type`.
+    pub fn header(self, name: HeaderName, value: HeaderValue) -> Self {
+        let mut headers = self.headers;
+        headers.append(name, value);
+
+        Self {
+            headers,
+            .. self
+        }
+    }
+}
+
+/// Escapes a name or file name for a quoted string, as HTML forms do.
+fn escape(name: &str) -> String {
+    name.replace('"', "%22")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+    use futures::stream::{self, StreamExt};
+
+    use super::*;
+
+    fn form() -> Form {
+        Form {
+            boundary: "boundary".to_string(),
+            parts: Vec::new(),
+        }
+    }
+
+    /// The length announced by the body of `form`, and its bytes.
+    fn encode(form: Form) -> (Option<u64>, Vec<u8>) {
+        let mut body = RequestBody::from(form);
+        let len = body.len();
+
+        let bytes = block_on(async {
+            let mut bytes = Vec::new();
+
+            while let Some(chunk) = body.next().await {
+                bytes.extend(chunk.unwrap());
+            }
+
+            bytes
+        });
+
+        (len, bytes)
+    }
+
+    #[test]
+    fn encodes_fields() {
+        let form = form()
+            .text("name", "value")
+            .part("file", Part::bytes(&b"\x00\x01"[..]).file_name("a.bin").mime(mime::APPLICATION_OCTET_STREAM))
+            .part("extra", Part::text("").header(HeaderName::from_static("x-extra"), HeaderValue::from_static("1")));
+
+        let expected = b"--boundary\r\n\
+            Content-Disposition: form-data; name=\"name\"\r\n\
+            \r\n\
+            value\r\n\
+            --boundary\r\n\
+            Content-Disposition: form-data; name=\"file\"; filename=\"a.bin\"\r\n\
+            Content-Type: application/octet-stream\r\n\
+            \r\n\
+            \x00\x01\r\n\
+            --boundary\r\n\
+            Content-Disposition: form-data; name=\"extra\"\r\n\
+            x-extra: 1\r\n\
+            \r\n\
+            \r\n\
+            --boundary--\r\n";
+
+        let (len, bytes) = encode(form);
+        assert_eq!(bytes, &expected[..]);
+        assert_eq!(len, Some(expected.len() as u64));
+    }
+
+    #[test]
+    fn escapes_names() {
+        let form = form().part("a\"b\r\nc", Part::text("v").file_name("\"quoted\"\n.txt"));
+
+        let (len, bytes) = encode(form);
+        let expected = "--boundary\r\n\
+            Content-Disposition: form-data; name=\"a%22b%0D%0Ac\"; filename=\"%22quoted%22%0A.txt\"\r\n\
+            \r\n\
+            v\r\n\
+            --boundary--\r\n";
+
+        assert_eq!(String::from_utf8(bytes).unwrap(), expected);
+        assert_eq!(len, Some(expected.len() as u64));
+    }
+
+    #[test]
+    fn encodes_empty_forms() {
+        assert_eq!(encode(form()), (Some(14), b"--boundary--\r\n".to_vec()));
+    }
+
+    #[test]
+    fn has_no_length_with_a_stream_of_unknown_length() {
+        let chunks = stream::iter(vec![Ok(b"a".to_vec()), Ok(b"b".to_vec())]);
+        let form = form().text("known", "1").part("stream", Part::body(RequestBody::stream(chunks)));
+
+        let (len, bytes) = encode(form);
+        assert_eq!(len, None);
+        assert!(String::from_utf8(bytes).unwrap().ends_with("\r\n\r\nab\r\n--boundary--\r\n"));
+    }
+
+    #[test]
+    fn reads_files() {
+        let path = std::env::temp_dir().join(format!("simple-http-multipart-{}.txt", std::process::id()));
+        std::fs::write(&path, "file content").unwrap();
+
+        let (len, bytes) = encode(form().file("upload", &path).unwrap());
+        std::fs::remove_file(&path).unwrap();
+
+        let expected = format!("--boundary\r\n\
+            Content-Disposition: form-data; name=\"upload\"; filename=\"{}\"\r\n\
+            Content-Type: application/octet-stream\r\n\
+            \r\n\
+            file content\r\n\
+            --boundary--\r\n", path.file_name().unwrap().to_string_lossy());
+
+        assert_eq!(String::from_utf8(bytes).unwrap(), expected);
+        assert_eq!(len, Some(expected.len() as u64));
+    }
+
+    #[test]
+    fn sets_the_content_type() {
+        assert_eq!(form().content_type(), "multipart/form-data; boundary=boundary");
+
+        let random = Form::new();
+        assert_eq!(random.boundary().len(), 32);
+        assert_ne!(random.boundary(), Form::new().boundary());
+    }
+}
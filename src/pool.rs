@@ -0,0 +1,233 @@
+use std::collections::HashMap;
+#[cfg(feature = "http2")]
+use std::collections::hash_map::Entry;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use http::uri::Scheme;
+use hyper::Uri;
+use tokio_io::{AsyncRead, AsyncWrite};
+
+#[cfg(feature = "http2")]
+use crate::http2::SendRequest;
+
+/// A connected transport with the concrete connector type erased.
+pub(crate) trait Io: AsyncRead + AsyncWrite + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Send> Io for T {}
+
+pub(crate) type Conn = Box<dyn Io>;
+
+/// Identifies the origin an idle connection can be reused for.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct Key {
+    scheme: String,
+    host: String,
+    port: u16,
+}
+
+impl Key {
+    pub(crate) fn new(uri: &Uri) -> Option<Key> {
+        let host = uri.host()?;
+        let scheme = uri.scheme_part().cloned().unwrap_or(Scheme::HTTP);
+        let port = match uri.port_u16() {
+            Some(port) => port,
+            None => if scheme == Scheme::HTTPS { 443 } else { 80 },
+        };
+
+        Some(Key {
+            scheme: scheme.as_str().to_string(),
+            host: host.to_lowercase(),
+            port,
+        })
+    }
+}
+
+struct Idle {
+    conn: Conn,
+    idle_at: Instant,
+}
+
+struct PoolInner {
+    idle: HashMap<Key, Vec<Idle>>,
+    /// HTTP/2 connections are shared by all the requests to their origin.
+    #[cfg(feature = "http2")]
+    h2: HashMap<Key, SendRequest>,
+    idle_timeout: Option<Duration>,
+    max_idle_per_host: usize,
+}
+
+/// Keeps idle keep-alive connections per destination.
+#[derive(Clone)]
+pub(crate) struct Pool {
+    inner: Arc<Mutex<PoolInner>>,
+}
+
+impl Pool {
+    pub(crate) fn new(idle_timeout: Option<Duration>, max_idle_per_host: usize) -> Pool {
+        Pool {
+            inner: Arc::new(Mutex::new(PoolInner {
+                idle: HashMap::new(),
+                #[cfg(feature = "http2")]
+                h2: HashMap::new(),
+                idle_timeout,
+                max_idle_per_host,
+            })),
+        }
+    }
+
+    /// Takes the most recently used idle connection for `key`, dropping expired ones.
+    pub(crate) fn checkout(&self, key: &Key) -> Option<Conn> {
+        let mut inner = self.inner.lock().ok()?;
+        let idle_timeout = inner.idle_timeout;
+        let list = inner.idle.get_mut(key)?;
+
+        if let Some(timeout) = idle_timeout {
+            let now = Instant::now();
+            list.retain(|idle| now.duration_since(idle.idle_at) < timeout);
+        }
+
+        let conn = list.pop().map(|idle| idle.conn);
+
+        if list.is_empty() {
+            inner.idle.remove(key);
+        }
+
+        conn
+    }
+
+    /// Returns a connection whose response has been fully read.
+    pub(crate) fn checkin(&self, key: Key, conn: Conn) {
+        let mut inner = match self.inner.lock() {
+            Ok(inner) => inner,
+            Err(_) => return,
+        };
+
+        let max = inner.max_idle_per_host;
+        if max == 0 {
+            return;
+        }
+
+        let list = inner.idle.entry(key).or_insert_with(Vec::new);
+        if list.len() >= max {
+            list.remove(0);
+        }
+
+        list.push(Idle {
+            conn,
+            idle_at: Instant::now(),
+        });
+    }
+
+    /// The HTTP/2 connection to `key`, if there is one.
+    #[cfg(feature = "http2")]
+    pub(crate) fn checkout_h2(&self, key: &Key) -> Option<SendRequest> {
+        self.inner.lock().ok()?.h2.get(key).cloned()
+    }
+
+    /// Shares an HTTP/2 connection to `key`, or returns the one already shared.
+    #[cfg(feature = "http2")]
+    pub(crate) fn insert_h2(&self, key: Key, conn: SendRequest) -> Option<SendRequest> {
+        let mut inner = self.inner.lock().ok()?;
+
+        match inner.h2.entry(key) {
+            Entry::Occupied(entry) => Some(entry.get().clone()),
+            Entry::Vacant(entry) => {
+                entry.insert(conn);
+                None
+            },
+        }
+    }
+
+    /// Forgets a closed HTTP/2 connection.
+    #[cfg(feature = "http2")]
+    pub(crate) fn remove_h2(&self, key: &Key) {
+        if let Ok(mut inner) = self.inner.lock() {
+            inner.h2.remove(key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read};
+
+    use super::*;
+
+    /// A connection telling which one it is when read.
+    fn conn(id: u8) -> Conn {
+        Box::new(Cursor::new(vec![id]))
+    }
+
+    fn id(mut conn: Conn) -> u8 {
+        let mut buf = [0];
+        conn.read_exact(&mut buf).unwrap();
+        buf[0]
+    }
+
+    fn key(uri: &str) -> Key {
+        Key::new(&uri.parse().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn keys_default_the_port_of_the_scheme() {
+        assert_eq!(key("http://Example.com/a"), key("http://example.com:80/b"));
+        assert_eq!(key("https://example.com/"), key("https://example.com:443/"));
+        assert_ne!(key("http://example.com/"), key("https://example.com/"));
+        assert_ne!(key("http://example.com/"), key("http://example.com:8080/"));
+        assert!(Key::new(&"/path".parse().unwrap()).is_none());
+    }
+
+    #[test]
+    fn reuses_the_most_recent_connection() {
+        let pool = Pool::new(None, usize::MAX);
+        pool.checkin(key("http://a.test/"), conn(1));
+        pool.checkin(key("http://a.test/"), conn(2));
+
+        assert!(pool.checkout(&key("http://b.test/")).is_none());
+        assert_eq!(id(pool.checkout(&key("http://a.test/")).unwrap()), 2);
+        assert_eq!(id(pool.checkout(&key("http://a.test/")).unwrap()), 1);
+        assert!(pool.checkout(&key("http://a.test/")).is_none());
+    }
+
+    #[test]
+    fn drops_connections_idle_for_too_long() {
+        let pool = Pool::new(Some(Duration::from_secs(90)), usize::MAX);
+        pool.checkin(key("http://a.test/"), conn(1));
+        pool.checkin(key("http://a.test/"), conn(2));
+
+        // the first connection has been idle for longer than the timeout
+        {
+            let mut inner = pool.inner.lock().unwrap();
+            let idle = &mut inner.idle.get_mut(&key("http://a.test/")).unwrap()[0];
+            idle.idle_at = idle.idle_at.checked_sub(Duration::from_secs(91)).unwrap();
+        }
+
+        assert_eq!(id(pool.checkout(&key("http://a.test/")).unwrap()), 2);
+        assert!(pool.checkout(&key("http://a.test/")).is_none());
+    }
+
+    #[test]
+    fn keeps_at_most_max_idle_per_host() {
+        let pool = Pool::new(None, 2);
+
+        for id in 1 ..= 3 {
+            pool.checkin(key("http://a.test/"), conn(id));
+        }
+
+        pool.checkin(key("http://b.test/"), conn(4));
+
+        assert_eq!(id(pool.checkout(&key("http://a.test/")).unwrap()), 3);
+        assert_eq!(id(pool.checkout(&key("http://a.test/")).unwrap()), 2);
+        assert!(pool.checkout(&key("http://a.test/")).is_none());
+        assert_eq!(id(pool.checkout(&key("http://b.test/")).unwrap()), 4);
+    }
+
+    #[test]
+    fn keeps_nothing_without_idle_connections() {
+        let pool = Pool::new(None, 0);
+        pool.checkin(key("http://a.test/"), conn(1));
+
+        assert!(pool.checkout(&key("http://a.test/")).is_none());
+    }
+}
@@ -4,6 +4,7 @@ mod tunnel;
 use futures_legacy::Future;
 use http::header::{HeaderMap, HeaderName, HeaderValue};
 use crate::connect::{Connect, Connected, Destination};
+use crate::error::Error;
 use hyper::Uri;
 use native_tls::TlsConnector as NativeTlsConnector;
 use std::fmt;
@@ -301,7 +302,7 @@ impl<C> Connect for ProxyConnector<C>
                             proxy_stream
                                 .and_then(move |(io, _)| {
                                     let tls = TlsConnector::from(tls);
-                                    tls.connect(&host, io).map_err(io_err)
+                                    tls.connect(&host, io).map_err(|err| Error::Tls(err).into())
                                 }).map(|s| (ProxyStream::Secured(s), Connected::new().proxy(true))),
                         )
                     }
@@ -337,12 +338,12 @@ fn proxy_dst(dst: &Destination, proxy: &Uri) -> io::Result<Destination> {
     let mut dst = dst.clone();
     proxy
         .scheme_part()
-        .map(|s| dst.set_scheme(s.as_str()).map_err(io_err))
-        .unwrap_or_else(|| Err(io_err(format!("proxy uri missing scheme: {}", proxy))))?;
+        .map(|s| dst.set_scheme(s.as_str()).map_err(invalid_url))
+        .unwrap_or_else(|| Err(invalid_url(format!("proxy uri missing scheme: {}", proxy))))?;
     proxy
         .host()
-        .map(|h| dst.set_host(h).map_err(io_err))
-        .unwrap_or_else(|| Err(io_err(format!("proxy uri missing host: {}", proxy))))?;
+        .map(|h| dst.set_host(h).map_err(invalid_url))
+        .unwrap_or_else(|| Err(invalid_url(format!("proxy uri missing host: {}", proxy))))?;
     dst.set_port(proxy.port_u16());
 
     Ok(dst)
@@ -351,4 +352,9 @@ fn proxy_dst(dst: &Destination, proxy: &Uri) -> io::Result<Destination> {
 #[inline]
 fn io_err<E: Into<Box<::std::error::Error + Send + Sync>>>(e: E) -> io::Error {
     io::Error::new(io::ErrorKind::Other, e)
-}
\ No newline at end of file
+}
+
+#[inline]
+fn invalid_url<E: Into<Box<dyn ::std::error::Error + Send + Sync>>>(e: E) -> io::Error {
+    Error::InvalidUrl(io_err(e)).into()
+}
@@ -2,7 +2,9 @@ use bytes::{BufMut, IntoBuf};
 use futures_legacy::{Async, Future, Poll};
 use http::HeaderMap;
 use crate::connect::Connected;
+use crate::error::Error;
 use super::io_err;
+use http::StatusCode;
 use std::fmt::{self, Display, Formatter};
 use std::io::{self, Cursor};
 use tokio_io::{AsyncRead, AsyncWrite};
@@ -92,7 +94,7 @@ impl<S: AsyncRead + AsyncWrite + 'static> Future for Tunnel<S> {
                         .read_buf(&mut self.buf.get_mut())
                 );
                 if n == 0 {
-                    return Err(io_err("unexpected EOF while tunnel reading"));
+                    return Err(Error::Tunnel(None, io_err("unexpected EOF while tunnel reading")).into());
                 } else {
                     let read = &self.buf.get_ref()[..];
                     if read.len() > 12 {
@@ -105,7 +107,8 @@ impl<S: AsyncRead + AsyncWrite + 'static> Future for Tunnel<S> {
                             }
                             // else read more
                         } else {
-                            return Err(io_err("unsuccessful tunnel"));
+                            let status = StatusCode::from_bytes(&read[9..12]).ok();
+                            return Err(Error::Tunnel(status, io_err("unsuccessful tunnel")).into());
                         }
                     }
                 }
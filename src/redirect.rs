@@ -0,0 +1,269 @@
+//! Redirect following.
+//!
+//! A `Policy` set with `ClientBuilder::redirect` decides which redirect
+//! responses are followed. By default up to 10 hops are followed.
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::sync::Arc;
+
+use ::http::{Method, StatusCode};
+use hyper::Uri;
+
+use crate::pool::Key;
+
+type Callback = dyn Fn(&Attempt) -> Action + Send + Sync;
+
+/// Decides whether a redirect is followed.
+#[derive(Clone)]
+pub struct Policy {
+    max_hops: usize,
+    allow_https_downgrade: bool,
+    callback: Option<Arc<Callback>>,
+}
+
+impl Policy {
+    /// Follows at most `max_hops` redirects, then fails with `RedirectError::TooManyHops`.
+    ///
+    /// `limited(0)` is `none()`: it doesn't fail, redirect responses are returned as is.
+    pub fn limited(max_hops: usize) -> Self {
+        Policy {
+            max_hops,
+            allow_https_downgrade: true,
+            callback: None,
+        }
+    }
+
+    /// Never follows redirects, every redirect response is returned as is.
+    pub fn none() -> Self {
+        Self::limited(0)
+    }
+
+    /// Whether a redirect from `https` to `http` is followed.
+    ///
+    /// When disallowed such a redirect fails with `RedirectError::Downgrade`. Default is `true`.
+    pub fn allow_https_downgrade(self, allow: bool) -> Self {
+        Self {
+            allow_https_downgrade: allow,
+            .. self
+        }
+    }
+
+    /// Asks `callback` about every hop that passed the other checks of the policy.
+    pub fn custom<F>(self, callback: F) -> Self
+        where F: Fn(&Attempt) -> Action + Send + Sync + 'static
+    {
+        Self {
+            callback: Some(Arc::new(callback)),
+            .. self
+        }
+    }
+
+    pub(crate) fn is_none(&self) -> bool {
+        self.max_hops == 0
+    }
+
+    pub(crate) fn check(&self, attempt: &Attempt) -> Result<Action, RedirectError> {
+        if self.is_none() {
+            return Ok(Action::Stop);
+        }
+
+        // the hop answered with this redirect is already among the previous ones
+        if attempt.previous.len() > self.max_hops {
+            return Err(RedirectError::TooManyHops(self.max_hops));
+        }
+
+        let visited = attempt.previous.iter().zip(attempt.methods)
+            .any(|(uri, method)| uri == attempt.next && method == attempt.method);
+
+        if visited {
+            return Err(RedirectError::Loop(attempt.next.clone()));
+        }
+
+        let downgrade = attempt.previous.last()
+            .map(|prev| prev.scheme_str() == Some("https") && attempt.next.scheme_str() == Some("http"))
+            .unwrap_or(false);
+
+        if downgrade && !self.allow_https_downgrade {
+            return Err(RedirectError::Downgrade(attempt.next.clone()));
+        }
+
+        Ok(match self.callback {
+            Some(ref callback) => callback(attempt),
+            None => Action::Follow,
+        })
+    }
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self::limited(10)
+    }
+}
+
+impl fmt::Debug for Policy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Policy")
+            .field("max_hops", &self.max_hops)
+            .field("allow_https_downgrade", &self.allow_https_downgrade)
+            .field("custom", &self.callback.is_some())
+            .finish()
+    }
+}
+
+/// A redirect about to be followed.
+#[derive(Debug)]
+pub struct Attempt<'a> {
+    pub(crate) status: StatusCode,
+    pub(crate) next: &'a Uri,
+    pub(crate) method: &'a Method,
+    pub(crate) previous: &'a [Uri],
+    pub(crate) methods: &'a [Method],
+}
+
+impl<'a> Attempt<'a> {
+    /// The status code of the redirect response.
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// The URI the redirect points to.
+    pub fn uri(&self) -> &Uri {
+        self.next
+    }
+
+    /// The method the URI the redirect points to is requested with.
+    pub fn method(&self) -> &Method {
+        self.method
+    }
+
+    /// The URIs requested so far, the last one answered with this redirect.
+    pub fn previous(&self) -> &[Uri] {
+        self.previous
+    }
+}
+
+/// What to do with a redirect.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Request the URI the redirect points to.
+    Follow,
+    /// Return the redirect response as is.
+    Stop,
+}
+
+/// Why a redirect was refused.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RedirectError {
+    /// More than the allowed number of redirects.
+    TooManyHops(usize),
+    /// The redirect points to a URI that was already requested with the same method.
+    Loop(Uri),
+    /// The redirect points from `https` to `http`, which the policy does not allow.
+    Downgrade(Uri),
+}
+
+impl fmt::Display for RedirectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RedirectError::TooManyHops(max) => write!(f, "more than {} redirects", max),
+            RedirectError::Loop(ref uri) => write!(f, "redirect loop at {}", uri),
+            RedirectError::Downgrade(ref uri) => write!(f, "redirect from https to {}", uri),
+        }
+    }
+}
+
+impl StdError for RedirectError {}
+
+/// Whether `status` is a redirect that can be followed.
+pub(crate) fn is_redirect(status: StatusCode) -> bool {
+    matches!(status,
+        StatusCode::MOVED_PERMANENTLY
+        | StatusCode::FOUND
+        | StatusCode::SEE_OTHER
+        | StatusCode::TEMPORARY_REDIRECT
+        | StatusCode::PERMANENT_REDIRECT)
+}
+
+/// The method of the request following a redirect.
+///
+/// `303 See Other` is followed with `GET`, as are `301` and `302` answering a `POST`.
+pub(crate) fn next_method(status: StatusCode, method: &Method) -> Method {
+    match status {
+        StatusCode::SEE_OTHER if method != Method::HEAD => Method::GET,
+        StatusCode::MOVED_PERMANENTLY | StatusCode::FOUND if method == Method::POST => Method::GET,
+        _ => method.clone(),
+    }
+}
+
+/// Whether two URIs belong to different origins.
+pub(crate) fn is_cross_origin(a: &Uri, b: &Uri) -> bool {
+    Key::new(a) != Key::new(b)
+}
+
+/// Resolves the `Location` of a redirect against the URI it answered (RFC 3986, section 5.2).
+pub(crate) fn resolve(base: &Uri, location: &str) -> Option<Uri> {
+    let location = match location.find('#') {
+        Some(idx) => &location[.. idx],
+        None => location,
+    }.trim();
+
+    let scheme = base.scheme_str()?;
+    let authority = base.authority_part()?.as_str();
+
+    let has_scheme = location.find(':')
+        .map(|idx| location[.. idx].chars().all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.'))
+        .unwrap_or(false);
+
+    let uri = if has_scheme {
+        location.to_string()
+    } else if location.starts_with("//") {
+        format!("{}:{}", scheme, location)
+    } else if location.starts_with('/') {
+        let (path, query) = split_query(location);
+        format!("{}://{}{}{}", scheme, authority, remove_dot_segments(path), query)
+    } else if location.is_empty() || location.starts_with('?') {
+        let query = if location.is_empty() { base.query().map(|q| format!("?{}", q)).unwrap_or_default() } else { location.to_string() };
+        format!("{}://{}{}{}", scheme, authority, base.path(), query)
+    } else {
+        let (path, query) = split_query(location);
+        let dir = match base.path().rfind('/') {
+            Some(idx) => &base.path()[..= idx],
+            None => "/",
+        };
+
+        format!("{}://{}{}{}", scheme, authority, remove_dot_segments(&format!("{}{}", dir, path)), query)
+    };
+
+    let uri: Uri = uri.parse().ok()?;
+    uri.host()?;
+
+    Some(uri)
+}
+
+fn split_query(s: &str) -> (&str, &str) {
+    match s.find('?') {
+        Some(idx) => (&s[.. idx], &s[idx ..]),
+        None => (s, ""),
+    }
+}
+
+fn remove_dot_segments(path: &str) -> String {
+    let mut out: Vec<&str> = Vec::new();
+    let mut segments = path.split('/').skip(1).peekable();
+
+    while let Some(segment) = segments.next() {
+        let last = segments.peek().is_none();
+
+        match segment {
+            "." => if last { out.push("") },
+            ".." => {
+                out.pop();
+                if last { out.push("") }
+            },
+            segment => out.push(segment),
+        }
+    }
+
+    format!("/{}", out.join("/"))
+}
@@ -1,20 +1,35 @@
+use bytes::Bytes;
 use futures::stream::{self, Stream};
 use hyper::Uri;
+use std::convert::TryFrom;
 use std::io;
 use std::marker::PhantomData;
+use std::time::Duration;
 use http::{
+    Extensions,
+    HeaderMap,
     Version,
     Method,
 };
+use http::header::{HeaderName, HeaderValue, ACCEPT_ENCODING, CONNECTION, CONTENT_LENGTH, CONTENT_TYPE, USER_AGENT};
+use crate::multipart::Form;
+use crate::request_body::RequestBody;
+use crate::timeout::Timeouts;
 
 
-pub struct Request<B>
+pub struct Request<B = RequestBody>
     where B: Stream<Item = io::Result<Vec<u8>>> + Send
 {
     pub(crate) method: Method,
     pub(crate) version: Version,
     pub(crate) uri: Uri,
-    pub(crate) headers: Vec<(String, String)>,
+    pub(crate) headers: HeaderMap,
+    pub(crate) extensions: Extensions,
+    pub(crate) content_length: Option<u64>,
+    pub(crate) trailers: Vec<(String, String)>,
+    pub(crate) timeouts: Timeouts,
+    pub(crate) decompress: bool,
+    pub(crate) keep_alive: bool,
     pub(crate) body: Option<B>,
 }
 
@@ -22,13 +37,20 @@ impl<B> Request<B>
     where B: Stream<Item = io::Result<Vec<u8>>> + Send
 {
     pub fn new(method: Method, uri: Uri) -> Request<B> {
+        let mut headers = HeaderMap::new();
+        headers.insert(USER_AGENT, HeaderValue::from_static("Simple http request"));
+
         Request {
             method,
             uri,
-            version: Version::HTTP_10,
-            headers: vec![
-                ("User-Agent".to_string(), "Simple http request".to_string())
-            ],
+            version: Version::HTTP_11,
+            headers,
+            extensions: Extensions::new(),
+            content_length: None,
+            trailers: Vec::new(),
+            timeouts: Timeouts::default(),
+            decompress: true,
+            keep_alive: true,
             body: None
         }
     }
@@ -36,15 +58,267 @@ impl<B> Request<B>
     pub fn builder() -> RequestBuilder<B> {
         RequestBuilder::default()
     }
+
+    pub fn method(&self) -> &Method {
+        &self.method
+    }
+
+    pub fn method_mut(&mut self) -> &mut Method {
+        &mut self.method
+    }
+
+    pub fn uri(&self) -> &Uri {
+        &self.uri
+    }
+
+    pub fn uri_mut(&mut self) -> &mut Uri {
+        &mut self.uri
+    }
+
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    pub fn version_mut(&mut self) -> &mut Version {
+        &mut self.version
+    }
+
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    pub fn headers_mut(&mut self) -> &mut HeaderMap {
+        &mut self.headers
+    }
+
+    pub fn extensions(&self) -> &Extensions {
+        &self.extensions
+    }
+
+    pub fn extensions_mut(&mut self) -> &mut Extensions {
+        &mut self.extensions
+    }
+
+    /// Changes the body of the request, keeping everything else.
+    pub fn map<F, T>(self, f: F) -> Request<T>
+        where F: FnOnce(B) -> T,
+              T: Stream<Item = io::Result<Vec<u8>>> + Send
+    {
+        Request {
+            method: self.method,
+            version: self.version,
+            uri: self.uri,
+            headers: self.headers,
+            extensions: self.extensions,
+            content_length: self.content_length,
+            trailers: self.trailers,
+            timeouts: self.timeouts,
+            decompress: self.decompress,
+            keep_alive: self.keep_alive,
+            body: self.body.map(f),
+        }
+    }
+
+    /// Whether the client negotiates and decodes the content coding of the response.
+    pub(crate) fn decompress(&self) -> bool {
+        self.decompress && !self.headers.contains_key(ACCEPT_ENCODING)
+    }
+
+    /// Whether the connection may be reused once the response was received.
+    pub(crate) fn keep_alive(&self) -> bool {
+        self.keep_alive && !has_connection_option(&self.headers, "close")
+    }
+}
+
+impl Request<RequestBody> {
+    /// Decides how the body is delimited on the wire.
+    ///
+    /// An explicit `Content-Length` header or a known body length is sent as is,
+    /// any other body is sent with `Transfer-Encoding: chunked`.
+    pub(crate) fn framing(&self) -> Framing {
+        // an empty body, such as the one of a converted `http::Request`, is not sent
+        if self.body.as_ref().map(RequestBody::is_empty).unwrap_or(true) {
+            return Framing::Empty;
+        }
+
+        let header = self.headers.get(CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse().ok());
+
+        match header.or(self.content_length).or_else(|| self.body.as_ref().and_then(RequestBody::len)) {
+            Some(len) => Framing::Length(len),
+            None => Framing::Chunked,
+        }
+    }
+
+    /// Whether the request can be sent again.
+    pub(crate) fn is_replayable(&self) -> bool {
+        self.body.as_ref().map(RequestBody::is_replayable).unwrap_or(true)
+    }
+
+    /// The body to send, a copy of it when it can be sent again.
+    pub(crate) fn take_body(&mut self) -> Option<RequestBody> {
+        match self.body {
+            Some(ref body) if body.is_replayable() => body.try_clone(),
+            _ => self.body.take(),
+        }
+    }
+}
+
+/// Keeps method, URI, version, headers and extensions of the request.
+///
+/// The body must be a stream, `http` requests with a body held in memory, such as
+/// `()`, `Vec<u8>` or `String`, convert into a `Request<RequestBody>` instead.
+impl<B> From<http::Request<B>> for Request<B>
+    where B: Stream<Item = io::Result<Vec<u8>>> + Send
+{
+    fn from(req: http::Request<B>) -> Self {
+        let (parts, body) = req.into_parts();
+        from_parts(parts, body)
+    }
+}
+
+macro_rules! from_http_request {
+    ($($body:ty),*) => {
+        $(
+            /// Keeps method, URI, version, headers and extensions of the request.
+            impl From<http::Request<$body>> for Request<RequestBody> {
+                fn from(req: http::Request<$body>) -> Self {
+                    let (parts, body) = req.into_parts();
+                    from_parts(parts, body.into())
+                }
+            }
+        )*
+    };
+}
+
+from_http_request!(Vec<u8>, String, Bytes, &'static [u8], &'static str);
+
+/// A request without a body.
+impl From<http::Request<()>> for Request<RequestBody> {
+    fn from(req: http::Request<()>) -> Self {
+        let (parts, ()) = req.into_parts();
+        from_parts(parts, RequestBody::empty())
+    }
+}
+
+fn from_parts<B>(parts: http::request::Parts, body: B) -> Request<B>
+    where B: Stream<Item = io::Result<Vec<u8>>> + Send
+{
+    Request {
+        method: parts.method,
+        version: parts.version,
+        uri: parts.uri,
+        headers: parts.headers,
+        extensions: parts.extensions,
+        content_length: None,
+        trailers: Vec::new(),
+        timeouts: Timeouts::default(),
+        decompress: true,
+        keep_alive: true,
+        body: Some(body),
+    }
+}
+
+/// The body is `None` for a request without one.
+impl<B> From<Request<B>> for http::Request<Option<B>>
+    where B: Stream<Item = io::Result<Vec<u8>>> + Send
+{
+    fn from(req: Request<B>) -> Self {
+        into_http(req, |body| body)
+    }
+}
+
+/// The body is empty for a request without one.
+impl From<Request<RequestBody>> for http::Request<RequestBody> {
+    fn from(req: Request<RequestBody>) -> Self {
+        into_http(req, Option::unwrap_or_default)
+    }
+}
+
+/// Succeeds for a request whose body is held in memory, and gives the request back otherwise.
+impl TryFrom<Request<RequestBody>> for http::Request<Vec<u8>> {
+    type Error = Request<RequestBody>;
+
+    fn try_from(req: Request<RequestBody>) -> Result<Self, Self::Error> {
+        let body = match req.body {
+            Some(ref body) => match body.as_bytes() {
+                Some(bytes) => bytes.to_vec(),
+                None => return Err(req),
+            },
+            None => Vec::new(),
+        };
+
+        Ok(into_http(req, |_| body))
+    }
 }
 
-pub struct RequestBuilder<B>
+fn into_http<B, T>(req: Request<B>, body: impl FnOnce(Option<B>) -> T) -> http::Request<T>
+    where B: Stream<Item = io::Result<Vec<u8>>> + Send
+{
+    let mut converted = http::Request::new(body(req.body));
+
+    *converted.method_mut() = req.method;
+    *converted.version_mut() = req.version;
+    *converted.uri_mut() = req.uri;
+    *converted.headers_mut() = req.headers;
+    *converted.extensions_mut() = req.extensions;
+
+    converted
+}
+
+/// Whether a `Connection` header of `headers` lists `option`.
+pub(crate) fn has_connection_option(headers: &HeaderMap, option: &str) -> bool {
+    headers.get_all(CONNECTION).iter()
+        .filter_map(|value| value.to_str().ok())
+        .any(|value| value.split(',').any(|value| value.trim().eq_ignore_ascii_case(option)))
+}
+
+/// How the request body is delimited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Framing {
+    Empty,
+    Length(u64),
+    Chunked,
+}
+
+/// Wraps a piece of the body into a chunk, `data` must not be empty.
+pub(crate) fn encode_chunk(data: &[u8]) -> Vec<u8> {
+    let mut buf = format!("{:X}\r\n", data.len()).into_bytes();
+    buf.reserve(data.len() + 2);
+    buf.extend_from_slice(data);
+    buf.extend_from_slice(b"\r\n");
+    buf
+}
+
+/// The zero-size chunk ending a chunked body, followed by the trailer section.
+pub(crate) fn encode_last_chunk(trailers: &[(String, String)]) -> Vec<u8> {
+    let mut buf = String::from("0\r\n");
+
+    for (name, value) in trailers {
+        buf.push_str(name);
+        buf.push_str(": ");
+        buf.push_str(value);
+        buf.push_str("\r\n");
+    }
+
+    buf.push_str("\r\n");
+    buf.into_bytes()
+}
+
+pub struct RequestBuilder<B = RequestBody>
     where B: Stream<Item = io::Result<Vec<u8>>> + Send
 {
     pub(crate) method: Method,
     pub(crate) version: Version,
     pub(crate) uri: Uri,
-    pub(crate) headers: Vec<(String, String)>,
+    pub(crate) headers: HeaderMap,
+    pub(crate) content_length: Option<u64>,
+    pub(crate) trailers: Vec<(String, String)>,
+    pub(crate) timeouts: Timeouts,
+    pub(crate) decompress: bool,
+    pub(crate) keep_alive: bool,
+    pub(crate) error: Option<http::Error>,
     _m: PhantomData<B>,
 }
 
@@ -57,6 +331,12 @@ impl <B> Default for RequestBuilder<B>
             version: Default::default(),
             uri: Default::default(),
             headers: Default::default(),
+            content_length: None,
+            trailers: Default::default(),
+            timeouts: Default::default(),
+            decompress: true,
+            keep_alive: true,
+            error: None,
             _m: Default::default(),
         }
     }
@@ -64,11 +344,21 @@ impl <B> Default for RequestBuilder<B>
 
 impl RequestBuilder<stream::Empty<io::Result<Vec<u8>>>> {
     pub fn done(self) -> Result<Request<stream::Empty<io::Result<Vec<u8>>>>, io::Error> {
+        if let Some(err) = self.error {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, err));
+        }
+
         Ok(Request {
             method: self.method,
             version: self.version,
             uri: self.uri,
             headers: self.headers,
+            extensions: Extensions::new(),
+            content_length: self.content_length,
+            trailers: self.trailers,
+            timeouts: self.timeouts,
+            decompress: self.decompress,
+            keep_alive: self.keep_alive,
             body: None,
         })
     }
@@ -77,6 +367,13 @@ impl RequestBuilder<stream::Empty<io::Result<Vec<u8>>>> {
 impl <B> RequestBuilder<B>
     where B: Stream<Item = io::Result<Vec<u8>>> + Send
 {
+    pub fn method(self, method: Method) -> Self {
+        Self {
+            method,
+            .. self
+        }
+    }
+
     pub fn version(self, version: Version) -> Self {
         Self {
             version,
@@ -91,33 +388,318 @@ impl <B> RequestBuilder<B>
         }
     }
 
+    /// Appends a header, an invalid name or value fails the request when it is built.
     pub fn header(self, name: &str, value: &str) -> Self {
         let RequestBuilder {
             method,
             version,
             uri,
             mut headers,
+            content_length,
+            trailers,
+            timeouts,
+            decompress,
+            keep_alive,
+            mut error,
             _m
         } = self;
 
-        headers.push((name.to_string(), value.to_string()));
+        let header = HeaderName::from_bytes(name.as_bytes())
+            .map_err(http::Error::from)
+            .and_then(|name| Ok((name, HeaderValue::from_str(value)?)));
+
+        match header {
+            Ok((name, value)) => { headers.append(name, value); },
+            Err(err) => { error.get_or_insert(err); },
+        }
 
         Self {
             method,
             version,
             uri,
             headers,
+            content_length,
+            trailers,
+            timeouts,
+            decompress,
+            keep_alive,
+            error,
             _m
         }
     }
 
+    /// Size of the body in bytes, when it is known up front.
+    ///
+    /// The body is then sent with a `Content-Length` header instead of being
+    /// chunk-encoded, and the request fails if the stream yields a different amount.
+    pub fn content_length(self, len: u64) -> Self {
+        Self {
+            content_length: Some(len),
+            .. self
+        }
+    }
+
+    /// Adds a trailer field, sent after the last chunk of a chunked body.
+    ///
+    /// Trailers are ignored when the body length is known up front.
+    pub fn trailer(self, name: &str, value: &str) -> Self {
+        let mut trailers = self.trailers;
+        trailers.push((name.to_string(), value.to_string()));
+
+        Self {
+            trailers,
+            .. self
+        }
+    }
+
+    /// Overrides the connect timeout of the client for this request.
+    pub fn connect_timeout(self, timeout: Duration) -> Self {
+        Self {
+            timeouts: Timeouts { connect: Some(timeout), .. self.timeouts },
+            .. self
+        }
+    }
+
+    /// Overrides the timeout of each connection attempt for this request.
+    pub fn connect_attempt_timeout(self, timeout: Duration) -> Self {
+        Self {
+            timeouts: Timeouts { connect_attempt: Some(timeout), .. self.timeouts },
+            .. self
+        }
+    }
+
+    /// Overrides the response head timeout of the client for this request.
+    pub fn head_timeout(self, timeout: Duration) -> Self {
+        Self {
+            timeouts: Timeouts { head: Some(timeout), .. self.timeouts },
+            .. self
+        }
+    }
+
+    /// Overrides the body idle timeout of the client for this request.
+    pub fn body_idle_timeout(self, timeout: Duration) -> Self {
+        Self {
+            timeouts: Timeouts { body_idle: Some(timeout), .. self.timeouts },
+            .. self
+        }
+    }
+
+    /// Overrides the total deadline of the client for this request.
+    pub fn timeout(self, timeout: Duration) -> Self {
+        Self {
+            timeouts: Timeouts { total: Some(timeout), .. self.timeouts },
+            .. self
+        }
+    }
+
+    /// Whether the response body is decompressed according to its `Content-Encoding`.
+    ///
+    /// Disable to receive the body as sent by the server. Setting an `Accept-Encoding`
+    /// header has the same effect. Default is `true`.
+    pub fn decompress(self, enable: bool) -> Self {
+        Self {
+            decompress: enable,
+            .. self
+        }
+    }
+
+    /// Whether the connection may be reused for other requests after this one.
+    ///
+    /// When disabled, `Connection: close` is sent and the connection is not
+    /// returned to the pool. Default is `true`.
+    pub fn keep_alive(self, enable: bool) -> Self {
+        Self {
+            keep_alive: enable,
+            .. self
+        }
+    }
+
     pub fn body(self, body: B) -> Result<Request<B>, io::Error> {
+        if let Some(err) = self.error {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, err));
+        }
+
         Ok(Request {
             method: self.method,
             version: self.version,
             uri: self.uri,
             headers: self.headers,
+            extensions: Extensions::new(),
+            content_length: self.content_length,
+            trailers: self.trailers,
+            timeouts: self.timeouts,
+            decompress: self.decompress,
+            keep_alive: self.keep_alive,
             body: Some(body),
         })
     }
 }
+
+impl RequestBuilder<RequestBody> {
+    /// Builds the request with `form` for body, and the matching `Content-Type` header.
+    pub fn multipart(self, form: Form) -> Result<Request, io::Error> {
+        let mut headers = self.headers;
+        let content_type = HeaderValue::from_str(&form.content_type())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        headers.insert(CONTENT_TYPE, content_type);
+
+        Self { headers, .. self }.body(form.into())
+    }
+}
+
+#[cfg(feature = "json")]
+impl RequestBuilder<RequestBody> {
+    /// Builds the request with `value` serialized as JSON for body.
+    ///
+    /// Sets `Content-Type: application/json` unless the header is already set.
+    pub fn json<T: serde::Serialize + ?Sized>(self, value: &T) -> Result<Request, io::Error> {
+        let body = serde_json::to_vec(value)?;
+        self.with_content_type("application/json").body(body.into())
+    }
+
+    /// Builds the request with `value` serialized as an URL encoded form for body.
+    ///
+    /// Sets `Content-Type: application/x-www-form-urlencoded` unless the header is already set.
+    pub fn form<T: serde::Serialize + ?Sized>(self, value: &T) -> Result<Request, io::Error> {
+        let body = serde_urlencoded::to_string(value)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        self.with_content_type("application/x-www-form-urlencoded").body(body.into())
+    }
+
+    fn with_content_type(mut self, content_type: &'static str) -> Self {
+        if !self.headers.contains_key(CONTENT_TYPE) {
+            self.headers.insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+        }
+
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+    use futures::stream::StreamExt;
+
+    use super::*;
+
+    fn http_request<T>(body: T) -> http::Request<T> {
+        let mut req = http::Request::builder()
+            .method(Method::POST)
+            .uri("http://example.com/a?b")
+            .version(Version::HTTP_10)
+            .header("x-a", "1")
+            .body(body)
+            .unwrap();
+
+        req.extensions_mut().insert(7u32);
+        req
+    }
+
+    fn read(req: Request) -> Vec<u8> {
+        let mut body = req.body.unwrap();
+        let mut bytes = Vec::new();
+
+        while let Some(chunk) = block_on(body.next()) {
+            bytes.extend(chunk.unwrap());
+        }
+
+        bytes
+    }
+
+    fn assert_parts<B>(req: &Request<B>)
+        where B: Stream<Item = io::Result<Vec<u8>>> + Send
+    {
+        assert_eq!(req.method(), Method::POST);
+        assert_eq!(req.uri(), "http://example.com/a?b");
+        assert_eq!(req.version(), Version::HTTP_10);
+        assert_eq!(req.headers()["x-a"], "1");
+        assert_eq!(req.extensions().get::<u32>(), Some(&7));
+    }
+
+    #[test]
+    fn converts_http_requests_with_bodies_in_memory() {
+        let req = Request::from(http_request(()));
+        assert_parts(&req);
+        assert_eq!(req.body.as_ref().and_then(RequestBody::len), Some(0));
+
+        let req = Request::from(http_request(b"bytes".to_vec()));
+        assert_parts(&req);
+        assert_eq!(read(req), b"bytes");
+
+        assert_eq!(read(Request::from(http_request("string".to_string()))), b"string");
+        assert_eq!(read(Request::from(http_request(Bytes::from_static(b"shared")))), b"shared");
+        assert_eq!(read(Request::from(http_request("static"))), b"static");
+        assert_eq!(read(Request::from(http_request(&b"static"[..]))), b"static");
+    }
+
+    #[test]
+    fn converts_http_requests_with_streams() {
+        let body = RequestBody::stream(stream::iter(vec![Ok(b"a".to_vec()), Ok(b"b".to_vec())]));
+        let req = Request::from(http_request(body));
+
+        assert_parts(&req);
+        assert!(!req.is_replayable());
+        assert_eq!(read(req), b"ab");
+    }
+
+    #[test]
+    fn converts_back_into_http_requests() {
+        let req: Request = Request::from(http_request(b"body".to_vec()));
+        let converted = http::Request::<RequestBody>::from(req);
+        assert_eq!(converted.headers()["x-a"], "1");
+
+        // and back again
+        let req = Request::from(converted);
+        assert_parts(&req);
+        assert_eq!(read(req), b"body");
+
+        // without a body
+        let req: Request = Request::new(Method::GET, "http://example.com/".parse().unwrap());
+        assert!(http::Request::<Option<RequestBody>>::from(req).body().is_none());
+
+        let req: Request = Request::new(Method::GET, "http://example.com/".parse().unwrap());
+        assert_eq!(http::Request::<RequestBody>::from(req).body().len(), Some(0));
+    }
+
+    #[test]
+    fn converts_bodies_in_memory_into_bytes() {
+        let req = Request::from(http_request(b"body".to_vec()));
+        let converted = http::Request::<Vec<u8>>::try_from(req).ok().unwrap();
+        assert_eq!(converted.body(), b"body");
+        assert_eq!(converted.extensions().get::<u32>(), Some(&7));
+
+        let req: Request = Request::new(Method::GET, "http://example.com/".parse().unwrap());
+        assert!(http::Request::<Vec<u8>>::try_from(req).ok().unwrap().body().is_empty());
+
+        // a stream is given back
+        let body = RequestBody::stream(stream::iter(vec![Ok(b"a".to_vec())]));
+        let req = http::Request::<Vec<u8>>::try_from(Request::from(http_request(body))).err().unwrap();
+        assert_parts(&req);
+        assert_eq!(read(req), b"a");
+    }
+
+    #[test]
+    fn encodes_chunks() {
+        assert_eq!(encode_chunk(b"hello"), b"5\r\nhello\r\n");
+
+        let chunk = encode_chunk(&[b'a'; 0x1abc]);
+        assert!(chunk.starts_with(b"1ABC\r\naaa"));
+        assert!(chunk.ends_with(b"aaa\r\n"));
+        assert_eq!(chunk.len(), 6 + 0x1abc + 2);
+
+        assert_eq!(encode_last_chunk(&[]), b"0\r\n\r\n");
+        assert_eq!(encode_last_chunk(&[("a".to_string(), "1".to_string()), ("b".to_string(), "2".to_string())]), b"0\r\na: 1\r\nb: 2\r\n\r\n");
+    }
+
+    #[test]
+    fn frames_bodies() {
+        let framing = |builder: RequestBuilder, body: RequestBody| builder.body(body).unwrap().framing();
+        let stream = || RequestBody::stream(stream::iter(vec![Ok(b"a".to_vec())]));
+
+        assert_eq!(framing(Request::builder(), RequestBody::empty()), Framing::Empty);
+        assert_eq!(framing(Request::builder(), RequestBody::from("abc")), Framing::Length(3));
+        assert_eq!(framing(Request::builder(), stream()), Framing::Chunked);
+        assert_eq!(framing(Request::builder().content_length(1), stream()), Framing::Length(1));
+        assert_eq!(framing(Request::builder().header("content-length", "1"), stream()), Framing::Length(1));
+    }
+}
@@ -0,0 +1,363 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::thread;
+use bytes::Bytes;
+use futures::channel::mpsc;
+use futures::executor::block_on;
+use futures::sink::SinkExt;
+use futures::stream::{self, Stream};
+
+/// The body of a request.
+///
+/// Bodies held in memory or read from a file can be sent again, for a retry or
+/// a redirect, while a streaming body is sent once.
+pub struct RequestBody {
+    kind: Kind,
+    len: Option<u64>,
+}
+
+enum Kind {
+    Bytes(Bytes),
+    File(PathBuf, Option<mpsc::Receiver<io::Result<Vec<u8>>>>),
+    Concat(VecDeque<RequestBody>),
+    Stream(Pin<Box<dyn Stream<Item = io::Result<Vec<u8>>> + Send>>),
+}
+
+impl RequestBody {
+    pub fn empty() -> Self {
+        Self::from(Bytes::new())
+    }
+
+    /// Reads the body from the file at `path` when the request is sent.
+    ///
+    /// The file is read on a thread of its own, and its length is taken when the body is created.
+    pub fn file(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let len = path.metadata()?.len();
+
+        Ok(RequestBody {
+            kind: Kind::File(path, None),
+            len: Some(len),
+        })
+    }
+
+    /// A body of unknown length, sent with `Transfer-Encoding: chunked`.
+    pub fn stream(stream: impl Stream<Item = io::Result<Vec<u8>>> + Send + 'static) -> Self {
+        RequestBody {
+            kind: Kind::Stream(Box::pin(stream)),
+            len: None,
+        }
+    }
+
+    /// A body of `len` bytes, the request fails if the stream yields a different amount.
+    pub fn sized(stream: impl Stream<Item = io::Result<Vec<u8>>> + Send + 'static, len: u64) -> Self {
+        RequestBody {
+            len: Some(len),
+            .. Self::stream(stream)
+        }
+    }
+
+    /// The bodies one after the other.
+    pub(crate) fn concat(bodies: Vec<RequestBody>) -> Self {
+        let len = bodies.iter().map(RequestBody::len).sum();
+
+        RequestBody {
+            kind: Kind::Concat(bodies.into()),
+            len,
+        }
+    }
+
+    /// The length of the body in bytes, when it is known up front.
+    pub fn len(&self) -> Option<u64> {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == Some(0)
+    }
+
+    /// Whether the body can be sent again, which is the case unless it is a stream.
+    pub fn is_replayable(&self) -> bool {
+        match self.kind {
+            Kind::Concat(ref bodies) => bodies.iter().all(RequestBody::is_replayable),
+            Kind::Stream(_) => false,
+            _ => true,
+        }
+    }
+
+    /// The body when it is held in memory.
+    pub(crate) fn as_bytes(&self) -> Option<&Bytes> {
+        match self.kind {
+            Kind::Bytes(ref bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    /// A copy of the body to send again from the start, `None` for a stream.
+    pub fn try_clone(&self) -> Option<RequestBody> {
+        let kind = match self.kind {
+            Kind::Bytes(ref bytes) => Kind::Bytes(bytes.clone()),
+            Kind::File(ref path, _) => Kind::File(path.clone(), None),
+            Kind::Concat(ref bodies) => Kind::Concat(bodies.iter().map(RequestBody::try_clone).collect::<Option<_>>()?),
+            Kind::Stream(_) => return None,
+        };
+
+        Some(RequestBody { kind, len: self.len })
+    }
+}
+
+impl Stream for RequestBody {
+    type Item = io::Result<Vec<u8>>;
+
+    fn poll_next(self: Pin<&mut Self>, ctx: &mut Context) -> Poll<Option<Self::Item>> {
+        match self.get_mut().kind {
+            Kind::Bytes(ref mut bytes) if bytes.is_empty() => Poll::Ready(None),
+            Kind::Bytes(ref mut bytes) => Poll::Ready(Some(Ok(mem::replace(bytes, Bytes::new()).to_vec()))),
+            Kind::File(ref path, ref mut chunks) => {
+                let chunks = chunks.get_or_insert_with(|| read_file(path.clone()));
+                Pin::new(chunks).poll_next(ctx)
+            },
+            Kind::Concat(ref mut bodies) => {
+                while let Some(body) = bodies.front_mut() {
+                    match Pin::new(body).poll_next(ctx) {
+                        Poll::Ready(None) => { bodies.pop_front(); },
+                        poll => return poll,
+                    }
+                }
+
+                Poll::Ready(None)
+            },
+            Kind::Stream(ref mut stream) => stream.as_mut().poll_next(ctx),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self.len {
+            Some(0) => (0, Some(0)),
+            _ => (0, None),
+        }
+    }
+}
+
+/// Reads the file at `path` on a thread of its own, which stays a chunk ahead of the stream.
+///
+/// Blocking calls would stall the other tasks of the executor polling the body.
+fn read_file(path: PathBuf) -> mpsc::Receiver<io::Result<Vec<u8>>> {
+    let (mut tx, rx) = mpsc::channel(1);
+    let mut failed = tx.clone();
+
+    let spawned = thread::Builder::new()
+        .name("simple-http-file".to_string())
+        .spawn(move || {
+            let mut file = match File::open(&path) {
+                Ok(file) => file,
+                Err(err) => {
+                    let _ = block_on(tx.send(Err(err)));
+                    return;
+                },
+            };
+
+            loop {
+                let mut buf = vec![0; 64 * 1024];
+                let chunk = match file.read(&mut buf) {
+                    Ok(0) => return,
+                    Ok(len) => {
+                        buf.truncate(len);
+                        Ok(buf)
+                    },
+                    Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(err) => Err(err),
+                };
+
+                // stops once the body is dropped, or after an error
+                let end = chunk.is_err();
+                if block_on(tx.send(chunk)).is_err() || end {
+                    return;
+                }
+            }
+        });
+
+    if let Err(err) = spawned {
+        let _ = failed.try_send(Err(err));
+    }
+
+    rx
+}
+
+impl fmt::Debug for RequestBody {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self.kind {
+            Kind::Bytes(_) => "bytes",
+            Kind::File(..) => "file",
+            Kind::Concat(_) => "concat",
+            Kind::Stream(_) => "stream",
+        };
+
+        f.debug_struct("RequestBody")
+            .field("kind", &kind)
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
+impl Default for RequestBody {
+    fn default() -> Self {
+        Self::empty()
+    }
+}
+
+impl From<Bytes> for RequestBody {
+    fn from(bytes: Bytes) -> Self {
+        RequestBody {
+            len: Some(bytes.len() as u64),
+            kind: Kind::Bytes(bytes),
+        }
+    }
+}
+
+impl From<Vec<u8>> for RequestBody {
+    fn from(data: Vec<u8>) -> Self {
+        Self::from(Bytes::from(data))
+    }
+}
+
+impl From<String> for RequestBody {
+    fn from(data: String) -> Self {
+        Self::from(Bytes::from(data))
+    }
+}
+
+impl From<&'static [u8]> for RequestBody {
+    fn from(data: &'static [u8]) -> Self {
+        Self::from(Bytes::from_static(data))
+    }
+}
+
+impl From<&'static str> for RequestBody {
+    fn from(data: &'static str) -> Self {
+        Self::from(Bytes::from_static(data.as_bytes()))
+    }
+}
+
+impl From<stream::Empty<io::Result<Vec<u8>>>> for RequestBody {
+    fn from(_: stream::Empty<io::Result<Vec<u8>>>) -> Self {
+        Self::empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::stream::StreamExt;
+
+    use super::*;
+
+    fn read(mut body: RequestBody) -> io::Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+
+        while let Some(chunk) = block_on(body.next()) {
+            bytes.extend(chunk?);
+        }
+
+        Ok(bytes)
+    }
+
+    fn stream(chunks: &[&'static str]) -> RequestBody {
+        RequestBody::stream(stream::iter(chunks.iter().map(|chunk| Ok(chunk.as_bytes().to_vec())).collect::<Vec<_>>()))
+    }
+
+    /// A file of `len` bytes counting up, removed once the test is over.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, len: usize) -> (TempFile, Vec<u8>) {
+            let path = std::env::temp_dir().join(format!("simple-http-body-{}-{}", name, std::process::id()));
+            let content: Vec<u8> = (0 .. len).map(|n| n as u8).collect();
+            std::fs::write(&path, &content).unwrap();
+
+            (TempFile(path), content)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn reads_files() {
+        // several chunks, the last one partial
+        let (file, content) = TempFile::new("read", 150 * 1024);
+        let body = RequestBody::file(&file.0).unwrap();
+
+        assert_eq!(body.len(), Some(content.len() as u64));
+        assert!(body.is_replayable());
+        assert_eq!(read(body).unwrap(), content);
+    }
+
+    #[test]
+    fn fails_on_files_removed_meanwhile() {
+        let (file, _) = TempFile::new("removed", 10);
+        let body = RequestBody::file(&file.0).unwrap();
+        drop(file);
+
+        assert_eq!(read(body).unwrap_err().kind(), io::ErrorKind::NotFound);
+        assert_eq!(RequestBody::file(std::env::temp_dir().join("simple-http-missing")).unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn clones_bodies_to_send_again() {
+        let (file, content) = TempFile::new("clone", 100 * 1024);
+
+        // a copy starts from the beginning, however much of the body was read
+        let mut body = RequestBody::file(&file.0).unwrap();
+        block_on(body.next()).unwrap().unwrap();
+        assert_eq!(read(body.try_clone().unwrap()).unwrap(), content);
+        assert_eq!(read(body).unwrap(), &content[64 * 1024 ..]);
+
+        let bytes = RequestBody::from("bytes");
+        assert_eq!(read(bytes.try_clone().unwrap()).unwrap(), b"bytes");
+        assert_eq!(read(bytes).unwrap(), b"bytes");
+
+        let streamed = stream(&["a"]);
+        assert!(!streamed.is_replayable());
+        assert!(streamed.try_clone().is_none());
+    }
+
+    #[test]
+    fn concatenates_bodies() {
+        let (file, content) = TempFile::new("concat", 10);
+
+        let body = RequestBody::concat(vec![
+            RequestBody::from("head "),
+            RequestBody::file(&file.0).unwrap(),
+            RequestBody::empty(),
+            RequestBody::from(" tail"),
+        ]);
+        assert_eq!(body.len(), Some(20));
+        assert!(body.is_replayable());
+
+        let mut expected = b"head ".to_vec();
+        expected.extend(&content);
+        expected.extend(b" tail");
+
+        assert_eq!(read(body.try_clone().unwrap()).unwrap(), expected);
+        assert_eq!(read(body).unwrap(), expected);
+
+        // a stream makes the length unknown, and the body sent once
+        let body = RequestBody::concat(vec![RequestBody::from("a"), stream(&["b", "c"])]);
+        assert_eq!(body.len(), None);
+        assert!(!body.is_replayable());
+        assert!(body.try_clone().is_none());
+        assert_eq!(read(body).unwrap(), b"abc");
+
+        let sized = RequestBody::concat(vec![RequestBody::from("a"), RequestBody::sized(stream(&["bc"]), 2)]);
+        assert_eq!(sized.len(), Some(3));
+    }
+}
@@ -1,51 +1,52 @@
 use futures::stream::Stream;
+use std::fmt;
 use std::io;
-use ::http::StatusCode;
-use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::SystemTime;
+use ::http::{Extensions, HeaderMap, StatusCode, Version};
+use ::http::header::{ETAG, LAST_MODIFIED, LOCATION};
+use hyper::Uri;
+use typed_headers::{ContentLength, ContentType, HeaderMapExt, HttpDate};
+use typed_headers::mime::Mime;
 
-
-pub struct HeaderMap {
-    map: HashMap<String, Vec<String>>,
-}
-
-impl HeaderMap {
-    pub fn new(vec: Vec<(String, String)>) -> Self {
-        let mut map: HashMap<String, Vec<String>> = HashMap::with_capacity(vec.len());
-
-        for (k, v) in vec {
-            if let Some(vec) = map.get_mut(&k)  {
-                vec.push(v);
-            } else {
-                map.insert(k, vec![v]);
-            }
-        }
-
-        Self { map }
-    }
-
-    pub fn get(&self, name: &str) -> Option<&str> {
-        self.map.get(name)
-            .and_then(|val| val.get(0))
-            .map(|val| &**val)
-    }
-}
+use crate::redirect;
+#[cfg(feature = "json")]
+use crate::error::Error;
+#[cfg(feature = "json")]
+use encoding_rs::{Encoding, UTF_8};
+#[cfg(feature = "json")]
+use futures::stream::StreamExt;
+#[cfg(feature = "json")]
+use typed_headers::mime;
 
 
 pub struct Response<B>
     where B: Stream<Item = io::Result<Vec<u8>>> + Send
 {
     pub(crate) status_code: StatusCode,
+    pub(crate) version: Version,
     pub(crate) headers: HeaderMap,
+    pub(crate) extensions: Extensions,
+    pub(crate) uri: Uri,
+    pub(crate) redirects: Vec<Uri>,
+    #[cfg(feature = "json")]
+    pub(crate) max_body_size: Option<usize>,
     pub(crate) body: B,
 }
 
 impl<B> Response<B>
     where B: Stream<Item = io::Result<Vec<u8>>> + Send
 {
-    pub fn new(status_code: u16, headers: Vec<(String, String)>, body: B) -> Self {
+    pub fn new(status_code: u16, headers: HeaderMap, body: B) -> Self {
         Self {
             status_code: StatusCode::from_u16(status_code).unwrap(),
-            headers: HeaderMap::new(headers),
+            version: Version::HTTP_11,
+            headers,
+            extensions: Extensions::new(),
+            uri: Uri::default(),
+            redirects: Vec::new(),
+            #[cfg(feature = "json")]
+            max_body_size: None,
             body,
         }
     }
@@ -54,11 +55,344 @@ impl<B> Response<B>
         &self.headers
     }
 
+    pub fn headers_mut(&mut self) -> &mut HeaderMap {
+        &mut self.headers
+    }
+
     pub fn status(&self) -> StatusCode {
         self.status_code
     }
 
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    pub fn extensions(&self) -> &Extensions {
+        &self.extensions
+    }
+
+    pub fn extensions_mut(&mut self) -> &mut Extensions {
+        &mut self.extensions
+    }
+
+    /// The URI the response was received from, after following redirects.
+    pub fn uri(&self) -> &Uri {
+        &self.uri
+    }
+
+    /// The URIs that answered with a redirect before the final one, in request order.
+    pub fn redirects(&self) -> &[Uri] {
+        &self.redirects
+    }
+
+    /// The media type of the body, from the `Content-Type` header.
+    pub fn content_type(&self) -> Option<Mime> {
+        self.headers.typed_get::<ContentType>().ok()?.map(|content_type| content_type.0)
+    }
+
+    /// The size of the body as sent by the server, from the `Content-Length` header.
+    ///
+    /// `None` when the body was decompressed, since the header no longer applies.
+    pub fn content_length(&self) -> Option<u64> {
+        self.headers.typed_get::<ContentLength>().ok()?.map(|len| len.0)
+    }
+
+    /// The `Location` header, resolved against the URI of the response.
+    pub fn location(&self) -> Option<Uri> {
+        let location = self.headers.get(LOCATION)?.to_str().ok()?;
+
+        redirect::resolve(&self.uri, location)
+            .or_else(|| location.parse().ok())
+    }
+
+    /// The entity tag of the body, from the `ETag` header.
+    pub fn etag(&self) -> Option<ETag> {
+        self.headers.get(ETAG)?.to_str().ok()?.parse().ok()
+    }
+
+    /// The `Last-Modified` header.
+    pub fn last_modified(&self) -> Option<SystemTime> {
+        let date: HttpDate = self.headers.get(LAST_MODIFIED)?.to_str().ok()?.parse().ok()?;
+        Some(date.into())
+    }
+
     pub fn into_body(self) -> B {
         self.body
     }
 }
+
+#[cfg(feature = "json")]
+impl<B> Response<B>
+    where B: Stream<Item = io::Result<Vec<u8>>> + Send + Unpin
+{
+    /// Reads the whole body.
+    ///
+    /// Fails with `Error::Body` when the body is larger than the maximum set with
+    /// `ClientBuilder::max_body_size`.
+    pub async fn bytes(self) -> Result<Vec<u8>, Error> {
+        let max = self.max_body_size;
+        let mut body = self.body;
+        let mut buf = Vec::new();
+
+        while let Some(chunk) = body.next().await {
+            buf.extend_from_slice(&chunk.map_err(Error::Body)?);
+
+            if let Some(max) = max.filter(|&max| buf.len() > max) {
+                let msg = format!("response body larger than {} bytes", max);
+                return Err(Error::Body(io::Error::new(io::ErrorKind::InvalidData, msg)));
+            }
+        }
+
+        Ok(buf)
+    }
+
+    /// Reads the whole body as text, decoded with the charset of the `Content-Type` header.
+    ///
+    /// Without a known charset the body is decoded as UTF-8, invalid sequences are
+    /// replaced with `U+FFFD`.
+    pub async fn text(self) -> Result<String, Error> {
+        let encoding = self.content_type()
+            .and_then(|mime| mime.get_param(mime::CHARSET).map(|charset| charset.as_str().to_string()))
+            .and_then(|charset| Encoding::for_label(charset.as_bytes()))
+            .unwrap_or(UTF_8);
+
+        let bytes = self.bytes().await?;
+        let (text, _, _) = encoding.decode(&bytes);
+
+        Ok(text.into_owned())
+    }
+
+    /// Reads the whole body as JSON.
+    pub async fn json<T: serde::de::DeserializeOwned>(self) -> Result<T, Error> {
+        let bytes = self.bytes().await?;
+        serde_json::from_slice(&bytes).map_err(|err| Error::Body(err.into()))
+    }
+}
+
+/// Keeps status, version, headers and extensions of the response.
+impl<B> From<Response<B>> for http::Response<B>
+    where B: Stream<Item = io::Result<Vec<u8>>> + Send
+{
+    fn from(res: Response<B>) -> Self {
+        let mut converted = http::Response::new(res.body);
+
+        *converted.status_mut() = res.status_code;
+        *converted.version_mut() = res.version;
+        *converted.headers_mut() = res.headers;
+        *converted.extensions_mut() = res.extensions;
+
+        converted
+    }
+}
+
+impl<B> From<http::Response<B>> for Response<B>
+    where B: Stream<Item = io::Result<Vec<u8>>> + Send
+{
+    fn from(res: http::Response<B>) -> Self {
+        let (parts, body) = res.into_parts();
+
+        Response {
+            status_code: parts.status,
+            version: parts.version,
+            headers: parts.headers,
+            extensions: parts.extensions,
+            uri: Uri::default(),
+            redirects: Vec::new(),
+            #[cfg(feature = "json")]
+            max_body_size: None,
+            body,
+        }
+    }
+}
+
+/// An entity tag (RFC 7232, section 2.3).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ETag {
+    weak: bool,
+    tag: String,
+}
+
+impl ETag {
+    /// Whether the tag is marked weak with `W/`.
+    pub fn is_weak(&self) -> bool {
+        self.weak
+    }
+
+    /// The opaque tag, without the quotes.
+    pub fn tag(&self) -> &str {
+        &self.tag
+    }
+}
+
+impl FromStr for ETag {
+    type Err = InvalidETag;
+
+    fn from_str(s: &str) -> Result<ETag, InvalidETag> {
+        let s = s.trim();
+        let (weak, s) = match s.strip_prefix("W/") {
+            Some(s) => (true, s),
+            None => (false, s),
+        };
+
+        let tag = s.strip_prefix('"')
+            .and_then(|s| s.strip_suffix('"'))
+            .ok_or(InvalidETag(()))?;
+
+        // etagc = %x21 / %x23-7E / obs-text
+        if !tag.bytes().all(|b| b == 0x21 || (0x23 ..= 0x7e).contains(&b) || b >= 0x80) {
+            return Err(InvalidETag(()));
+        }
+
+        Ok(ETag {
+            weak,
+            tag: tag.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for ETag {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.weak {
+            f.write_str("W/")?;
+        }
+
+        write!(f, "\"{}\"", self.tag)
+    }
+}
+
+/// The error returned when parsing an `ETag` fails.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidETag(());
+
+impl fmt::Display for InvalidETag {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("invalid entity tag")
+    }
+}
+
+impl std::error::Error for InvalidETag {}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use ::http::header::HeaderName;
+    use futures::stream;
+
+    use super::*;
+
+    type Empty = stream::Empty<io::Result<Vec<u8>>>;
+
+    #[test]
+    fn converts_to_and_from_http_responses() {
+        let mut res = http::Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .version(Version::HTTP_2)
+            .header("x-a", "1")
+            .body(stream::empty::<io::Result<Vec<u8>>>())
+            .unwrap();
+        res.extensions_mut().insert(7u32);
+
+        let res = Response::from(res);
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        assert_eq!(res.version(), Version::HTTP_2);
+        assert_eq!(res.headers()["x-a"], "1");
+        assert_eq!(res.extensions().get::<u32>(), Some(&7));
+        assert!(res.redirects().is_empty());
+
+        let res: http::Response<Empty> = res.into();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        assert_eq!(res.version(), Version::HTTP_2);
+        assert_eq!(res.headers()["x-a"], "1");
+        assert_eq!(res.extensions().get::<u32>(), Some(&7));
+    }
+
+    /// A response from `uri` with `headers`.
+    fn response(uri: &str, headers: &[(&str, &str)]) -> Response<Empty> {
+        let mut map = HeaderMap::new();
+        for &(name, value) in headers {
+            map.append(HeaderName::from_bytes(name.as_bytes()).unwrap(), value.parse().unwrap());
+        }
+
+        let mut res = Response::new(200, map, stream::empty());
+        res.uri = uri.parse().unwrap();
+        res
+    }
+
+    fn header(name: &str, value: &str) -> Response<Empty> {
+        response("http://example.com/a/b", &[(name, value)])
+    }
+
+    #[test]
+    fn reads_the_content_type() {
+        let mime = header("content-type", "text/html; charset=UTF-8").content_type().unwrap();
+        assert_eq!(mime.essence_str(), "text/html");
+        assert_eq!(mime.get_param("charset").unwrap(), "utf-8");
+
+        assert_eq!(header("content-type", "not a media type").content_type(), None);
+        assert_eq!(header("x-other", "text/html").content_type(), None);
+    }
+
+    #[test]
+    fn reads_the_content_length() {
+        assert_eq!(header("content-length", "42").content_length(), Some(42));
+        assert_eq!(header("content-length", "-1").content_length(), None);
+        assert_eq!(header("content-length", "forty-two").content_length(), None);
+        assert_eq!(response("http://example.com/", &[("content-length", "1"), ("content-length", "2")]).content_length(), None);
+        assert_eq!(header("x-other", "42").content_length(), None);
+    }
+
+    #[test]
+    fn resolves_the_location() {
+        let location = |value| header("location", value).location().map(|uri| uri.to_string());
+
+        assert_eq!(location("/c").as_deref(), Some("http://example.com/c"));
+        assert_eq!(location("c?d").as_deref(), Some("http://example.com/a/c?d"));
+        assert_eq!(location("../c").as_deref(), Some("http://example.com/c"));
+        assert_eq!(location("//example.org/c").as_deref(), Some("http://example.org/c"));
+        assert_eq!(location("https://example.org/c#d").as_deref(), Some("https://example.org/c"));
+        assert_eq!(location("a b"), None);
+        assert_eq!(header("x-other", "/c").location(), None);
+
+        // without the URI of the response, the location is taken as is
+        let mut res = header("location", "/c");
+        res.uri = Uri::default();
+        assert_eq!(res.location().map(|uri| uri.to_string()).as_deref(), Some("/c"));
+    }
+
+    #[test]
+    fn parses_entity_tags() {
+        let strong: ETag = "\"xyzzy\"".parse().unwrap();
+        assert!(!strong.is_weak());
+        assert_eq!(strong.tag(), "xyzzy");
+        assert_eq!(strong.to_string(), "\"xyzzy\"");
+
+        let weak: ETag = " W/\"xyzzy\" ".parse().unwrap();
+        assert!(weak.is_weak());
+        assert_eq!(weak.tag(), "xyzzy");
+        assert_eq!(weak.to_string(), "W/\"xyzzy\"");
+        assert_ne!(weak, strong);
+
+        assert_eq!("\"\"".parse::<ETag>().unwrap().tag(), "");
+        assert_eq!("\"caf\u{e9}!\"".parse::<ETag>().unwrap().tag(), "caf\u{e9}!");
+
+        for invalid in &["xyzzy", "\"xyzzy", "xyzzy\"", "w/\"xyzzy\"", "W/xyzzy", "\"xy\"zzy\"", "\"xy zzy\"", "\"", "*"] {
+            assert_eq!(invalid.parse::<ETag>(), Err(InvalidETag(())), "{}", invalid);
+        }
+
+        assert_eq!(header("etag", "W/\"1\"").etag().map(|etag| etag.to_string()).as_deref(), Some("W/\"1\""));
+        assert_eq!(header("etag", "1").etag(), None);
+        assert_eq!(header("x-other", "\"1\"").etag(), None);
+    }
+
+    #[test]
+    fn reads_the_last_modified_date() {
+        let date = UNIX_EPOCH + Duration::from_secs(784_111_777);
+
+        assert_eq!(header("last-modified", "Sun, 06 Nov 1994 08:49:37 GMT").last_modified(), Some(date));
+        assert_eq!(header("last-modified", "Sunday, 06-Nov-94 08:49:37 GMT").last_modified(), Some(date));
+        assert_eq!(header("last-modified", "Sun Nov  6 08:49:37 1994").last_modified(), Some(date));
+        assert_eq!(header("last-modified", "yesterday").last_modified(), None);
+        assert_eq!(header("x-other", "Sun, 06 Nov 1994 08:49:37 GMT").last_modified(), None);
+    }
+}
@@ -0,0 +1,328 @@
+//! Retrying failed requests.
+//!
+//! A `Policy` set with `ClientBuilder::retry` decides whether a failed attempt is
+//! made again and how long to wait before. By default requests are not retried.
+
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use ::http::{HeaderMap, Method, StatusCode};
+use ::http::header::RETRY_AFTER;
+use typed_headers::HttpDate;
+use hyper::Uri;
+
+use crate::error::Error;
+use crate::timeout::TimeoutPhase;
+
+type Predicate = dyn Fn(&Attempt) -> bool + Send + Sync;
+type Observer = dyn Fn(&Attempt, Option<Duration>) + Send + Sync;
+
+/// Decides whether a request is attempted again.
+#[derive(Clone)]
+pub struct Policy {
+    max_attempts: usize,
+    base_backoff: Duration,
+    max_backoff: Duration,
+    predicate: Option<Arc<Predicate>>,
+    observer: Option<Arc<Observer>>,
+}
+
+impl Policy {
+    /// Makes at most `max_attempts` attempts, the first one included.
+    ///
+    /// By default only attempts with an idempotent method that failed with
+    /// `Attempt::is_transient` are retried.
+    pub fn limited(max_attempts: usize) -> Self {
+        Policy {
+            max_attempts,
+            base_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+            predicate: None,
+            observer: None,
+        }
+    }
+
+    /// Never retries.
+    pub fn none() -> Self {
+        Self::limited(1)
+    }
+
+    /// Delays between attempts.
+    ///
+    /// The delay doubles with every attempt, starting from `base` and up to `max`, and
+    /// a random part of up to half of it is taken off. A `Retry-After` header asking
+    /// for a longer wait than `max` ends the retries. Default is 100 ms up to 10 seconds.
+    pub fn backoff(self, base: Duration, max: Duration) -> Self {
+        Self {
+            base_backoff: base,
+            max_backoff: max,
+            .. self
+        }
+    }
+
+    /// Retries the attempts `predicate` returns `true` for, instead of the default ones.
+    ///
+    /// A request with a streaming body is never retried, since the stream can't be
+    /// sent again.
+    pub fn retry_if<F>(self, predicate: F) -> Self
+        where F: Fn(&Attempt) -> bool + Send + Sync + 'static
+    {
+        Self {
+            predicate: Some(Arc::new(predicate)),
+            .. self
+        }
+    }
+
+    /// Calls `observer` after every attempt, with the delay before the next one,
+    /// or `None` when the attempt is the last.
+    pub fn observe<F>(self, observer: F) -> Self
+        where F: Fn(&Attempt, Option<Duration>) + Send + Sync + 'static
+    {
+        Self {
+            observer: Some(Arc::new(observer)),
+            .. self
+        }
+    }
+
+    /// The delay before the next attempt, or `None` when `attempt` is the last.
+    pub(crate) fn check(&self, attempt: &Attempt) -> Option<Duration> {
+        if attempt.number >= self.max_attempts || attempt.result.map(|status| status.is_success()).unwrap_or(false) {
+            return None;
+        }
+
+        let retry = match self.predicate {
+            Some(ref predicate) => predicate(attempt),
+            None => attempt.is_idempotent() && attempt.is_transient(),
+        };
+
+        if !retry {
+            return None;
+        }
+
+        match attempt.retry_after {
+            Some(delay) if delay > self.max_backoff => None,
+            Some(delay) => Some(delay),
+            None => Some(self.backoff_delay(attempt.number)),
+        }
+    }
+
+    pub(crate) fn observe_attempt(&self, attempt: &Attempt, delay: Option<Duration>) {
+        if let Some(ref observer) = self.observer {
+            observer(attempt, delay);
+        }
+    }
+
+    fn backoff_delay(&self, number: usize) -> Duration {
+        let factor = 1u32.checked_shl(number.saturating_sub(1) as u32).unwrap_or(u32::MAX);
+        let backoff = self.base_backoff.checked_mul(factor)
+            .unwrap_or(self.max_backoff)
+            .min(self.max_backoff);
+
+        let jitter = (backoff / 2).mul_f64(rand::random::<f64>());
+        backoff - jitter
+    }
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self::none()
+    }
+}
+
+impl fmt::Debug for Policy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Policy")
+            .field("max_attempts", &self.max_attempts)
+            .field("base_backoff", &self.base_backoff)
+            .field("max_backoff", &self.max_backoff)
+            .field("custom", &self.predicate.is_some())
+            .finish()
+    }
+}
+
+/// The outcome of one attempt at a request.
+#[derive(Debug)]
+pub struct Attempt<'a> {
+    pub(crate) number: usize,
+    pub(crate) method: &'a Method,
+    pub(crate) uri: &'a Uri,
+    pub(crate) result: Result<StatusCode, &'a Error>,
+    pub(crate) retry_after: Option<Duration>,
+}
+
+impl<'a> Attempt<'a> {
+    /// The number of the attempt, starting from 1.
+    pub fn number(&self) -> usize {
+        self.number
+    }
+
+    pub fn method(&self) -> &Method {
+        self.method
+    }
+
+    pub fn uri(&self) -> &Uri {
+        self.uri
+    }
+
+    /// The status code of the response, if one was received.
+    pub fn status(&self) -> Option<StatusCode> {
+        self.result.ok()
+    }
+
+    /// The error the attempt failed with.
+    pub fn error(&self) -> Option<&Error> {
+        self.result.err()
+    }
+
+    /// How long the server asked to wait with a `Retry-After` header.
+    pub fn retry_after(&self) -> Option<Duration> {
+        self.retry_after
+    }
+
+    /// Whether sending the request again has the same effect as sending it once (RFC 7231, section 4.2.2).
+    pub fn is_idempotent(&self) -> bool {
+        is_idempotent(self.method)
+    }
+
+    /// Whether the failure is likely to go away, that is a `502`, `503` or `504` response,
+    /// a failure to connect, or a connection closed by the server.
+    pub fn is_transient(&self) -> bool {
+        match self.result {
+            Ok(status) => matches!(status, StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT),
+            Err(err) if err.is_connect() => true,
+            Err(Error::Timeout(phase, _)) => *phase == TimeoutPhase::Connect,
+            Err(Error::Io(err)) => matches!(err.kind(),
+                io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionAborted | io::ErrorKind::BrokenPipe | io::ErrorKind::UnexpectedEof),
+            Err(_) => false,
+        }
+    }
+}
+
+pub(crate) fn is_idempotent(method: &Method) -> bool {
+    matches!(*method, Method::GET | Method::HEAD | Method::PUT | Method::DELETE | Method::OPTIONS | Method::TRACE)
+}
+
+/// The delay asked for by a `Retry-After` header, in seconds or as a date.
+pub(crate) fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+
+    if let Ok(secs) = value.parse() {
+        return Some(Duration::from_secs(secs));
+    }
+
+    let date: HttpDate = value.parse().ok()?;
+    Some(SystemTime::from(date).duration_since(SystemTime::now()).unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use ::http::header::HeaderValue;
+
+    use super::*;
+
+    fn attempt<'a>(number: usize, method: &'a Method, uri: &'a Uri, result: Result<StatusCode, &'a Error>) -> Attempt<'a> {
+        Attempt { number, method, uri, result, retry_after: None }
+    }
+
+    fn retry_after_of(value: &str) -> Option<Duration> {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_str(value).unwrap());
+        retry_after(&headers)
+    }
+
+    #[test]
+    fn retries_transient_failures_of_idempotent_requests() {
+        let policy = Policy::limited(3);
+        let uri = "http://a.test/".parse().unwrap();
+
+        assert!(policy.check(&attempt(1, &Method::GET, &uri, Ok(StatusCode::SERVICE_UNAVAILABLE))).is_some());
+        assert!(policy.check(&attempt(2, &Method::PUT, &uri, Ok(StatusCode::BAD_GATEWAY))).is_some());
+        assert!(policy.check(&attempt(1, &Method::GET, &uri, Ok(StatusCode::INTERNAL_SERVER_ERROR))).is_none());
+        assert!(policy.check(&attempt(1, &Method::POST, &uri, Ok(StatusCode::SERVICE_UNAVAILABLE))).is_none());
+        assert!(policy.check(&attempt(1, &Method::GET, &uri, Ok(StatusCode::OK))).is_none());
+    }
+
+    #[test]
+    fn stops_after_max_attempts() {
+        let uri = "http://a.test/".parse().unwrap();
+
+        assert!(Policy::limited(3).check(&attempt(3, &Method::GET, &uri, Ok(StatusCode::SERVICE_UNAVAILABLE))).is_none());
+        assert!(Policy::none().check(&attempt(1, &Method::GET, &uri, Ok(StatusCode::SERVICE_UNAVAILABLE))).is_none());
+    }
+
+    #[test]
+    fn tells_transient_errors() {
+        let uri = "http://a.test/".parse().unwrap();
+        let transient = [
+            Error::Connect(io::Error::from(io::ErrorKind::ConnectionRefused)),
+            TimeoutPhase::Connect.error(),
+            Error::Io(io::Error::from(io::ErrorKind::ConnectionReset)),
+        ];
+        let permanent = [
+            TimeoutPhase::Head.error(),
+            Error::Io(io::Error::from(io::ErrorKind::InvalidData)),
+            Error::Body(io::Error::from(io::ErrorKind::ConnectionReset)),
+        ];
+
+        for err in &transient {
+            assert!(attempt(1, &Method::GET, &uri, Err(err)).is_transient(), "{}", err);
+        }
+
+        for err in &permanent {
+            assert!(!attempt(1, &Method::GET, &uri, Err(err)).is_transient(), "{}", err);
+        }
+    }
+
+    #[test]
+    fn custom_predicates_replace_the_default_one() {
+        let policy = Policy::limited(3).retry_if(|attempt| attempt.status() == Some(StatusCode::TOO_MANY_REQUESTS));
+        let uri = "http://a.test/".parse().unwrap();
+
+        assert!(policy.check(&attempt(1, &Method::POST, &uri, Ok(StatusCode::TOO_MANY_REQUESTS))).is_some());
+        assert!(policy.check(&attempt(1, &Method::GET, &uri, Ok(StatusCode::SERVICE_UNAVAILABLE))).is_none());
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_maximum() {
+        let policy = Policy::limited(10).backoff(Duration::from_millis(100), Duration::from_secs(1));
+
+        for (number, full) in [(1, 100), (2, 200), (3, 400), (4, 800), (5, 1000), (64, 1000)] {
+            let full = Duration::from_millis(full);
+
+            for _ in 0 .. 20 {
+                let delay = policy.backoff_delay(number);
+                assert!(delay <= full && delay >= full / 2, "attempt {}: {:?}", number, delay);
+            }
+        }
+    }
+
+    #[test]
+    fn waits_as_asked_by_retry_after() {
+        let policy = Policy::limited(3).backoff(Duration::from_millis(100), Duration::from_secs(10));
+        let uri = "http://a.test/".parse().unwrap();
+        let mut attempt = attempt(1, &Method::GET, &uri, Ok(StatusCode::SERVICE_UNAVAILABLE));
+
+        attempt.retry_after = Some(Duration::from_secs(5));
+        assert_eq!(policy.check(&attempt), Some(Duration::from_secs(5)));
+
+        // waiting longer than the maximum backoff ends the retries
+        attempt.retry_after = Some(Duration::from_secs(11));
+        assert_eq!(policy.check(&attempt), None);
+    }
+
+    #[test]
+    fn parses_retry_after() {
+        assert_eq!(retry_after_of("120"), Some(Duration::from_secs(120)));
+        assert_eq!(retry_after_of(" 0 "), Some(Duration::from_secs(0)));
+        assert_eq!(retry_after_of("Thu, 01 Jan 1970 00:00:00 GMT"), Some(Duration::from_secs(0)));
+        assert_eq!(retry_after_of("-1"), None);
+        assert_eq!(retry_after_of("soon"), None);
+        assert_eq!(retry_after(&HeaderMap::new()), None);
+
+        let later = HttpDate::from(SystemTime::now() + Duration::from_secs(60)).to_string();
+        let delay = retry_after_of(&later).unwrap();
+        assert!(delay > Duration::from_secs(55) && delay <= Duration::from_secs(60), "{:?}", delay);
+    }
+}
@@ -0,0 +1,223 @@
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex};
+use std::task::{Context, Poll};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use futures::compat::{Compat01As03, Future01CompatExt};
+use futures::future::{self, Either};
+use lazy_static::lazy_static;
+use tokio_executor::park::ParkThread;
+use tokio_timer::timer::{Handle, Timer};
+use tokio_timer::Delay;
+
+use crate::error::Error;
+
+/// The phase of a request in which a timeout elapsed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeoutPhase {
+    /// Resolving the host, connecting, and the TLS handshake or proxy tunnel.
+    Connect,
+    /// Waiting for the response head after the request was sent.
+    Head,
+    /// Waiting for the next piece of the response body.
+    BodyIdle,
+    /// The whole request, from connecting until the end of the response body.
+    Total,
+}
+
+impl fmt::Display for TimeoutPhase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            TimeoutPhase::Connect => "connect timed out",
+            TimeoutPhase::Head => "timed out waiting for the response head",
+            TimeoutPhase::BodyIdle => "timed out waiting for the response body",
+            TimeoutPhase::Total => "request timed out",
+        })
+    }
+}
+
+impl TimeoutPhase {
+    pub(crate) fn error(self) -> Error {
+        Error::Timeout(self, io::Error::new(io::ErrorKind::TimedOut, self.to_string()))
+    }
+}
+
+/// Timeouts of a client, or the overrides of a single request.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Timeouts {
+    pub(crate) connect: Option<Duration>,
+    pub(crate) connect_attempt: Option<Duration>,
+    pub(crate) head: Option<Duration>,
+    pub(crate) body_idle: Option<Duration>,
+    pub(crate) total: Option<Duration>,
+}
+
+impl Timeouts {
+    /// Takes every timeout set in `self`, and the rest from `defaults`.
+    pub(crate) fn or(self, defaults: Timeouts) -> Timeouts {
+        Timeouts {
+            connect: self.connect.or(defaults.connect),
+            connect_attempt: self.connect_attempt.or(defaults.connect_attempt),
+            head: self.head.or(defaults.head),
+            body_idle: self.body_idle.or(defaults.body_idle),
+            total: self.total.or(defaults.total),
+        }
+    }
+}
+
+/// A point in time after which a phase is abandoned.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Deadline {
+    at: Instant,
+    phase: TimeoutPhase,
+}
+
+impl Deadline {
+    pub(crate) fn after(timeout: Option<Duration>, phase: TimeoutPhase) -> Option<Deadline> {
+        timeout.map(|timeout| Deadline {
+            at: Instant::now() + timeout,
+            phase,
+        })
+    }
+
+    /// Whichever of two deadlines comes first.
+    pub(crate) fn earliest(a: Option<Deadline>, b: Option<Deadline>) -> Option<Deadline> {
+        match (a, b) {
+            (Some(a), Some(b)) => Some(if b.at < a.at { b } else { a }),
+            (a, b) => a.or(b),
+        }
+    }
+
+    /// The time left until the deadline, zero once it passed.
+    pub(crate) fn remaining(self) -> Duration {
+        self.at.saturating_duration_since(Instant::now())
+    }
+
+    pub(crate) fn delay(self) -> Sleep {
+        Sleep {
+            wait: Some(Wait::new(self.at)),
+            phase: self.phase,
+        }
+    }
+}
+
+lazy_static! {
+    /// A timer driven by a thread of its own, for the delays polled outside of a Tokio runtime.
+    static ref FALLBACK_TIMER: Option<Handle> = {
+        // The handle is passed with a condition variable: a channel parks the waiting thread,
+        // taking the wake-up of an executor that parks it as well.
+        let started = Arc::new((Mutex::new(None), Condvar::new()));
+        let handle = started.clone();
+
+        let spawned = thread::Builder::new()
+            .name("simple-http-timer".to_string())
+            .spawn(move || {
+                let mut timer = Timer::new(ParkThread::new());
+
+                let (ref lock, ref cvar) = *handle;
+                *lock.lock().unwrap() = Some(timer.handle());
+                cvar.notify_one();
+
+                while timer.turn(None).is_ok() {}
+            });
+
+        spawned.ok().and_then(|_| {
+            let (ref lock, ref cvar) = *started;
+            let handle = cvar.wait_while(lock.lock().unwrap(), |handle| handle.is_none()).unwrap();
+            handle.clone()
+        })
+    };
+}
+
+/// Completes at a point in time.
+///
+/// Runs on the timer of the Tokio runtime polling it, and on the fallback timer
+/// outside of a runtime.
+struct Wait {
+    at: Instant,
+    delay: Compat01As03<Delay>,
+    fallback: bool,
+}
+
+impl Wait {
+    fn new(at: Instant) -> Wait {
+        Wait {
+            at,
+            delay: Delay::new(at).compat(),
+            fallback: false,
+        }
+    }
+}
+
+impl Future for Wait {
+    type Output = Result<(), tokio_timer::Error>;
+
+    fn poll(mut self: Pin<&mut Self>, ctx: &mut Context) -> Poll<Self::Output> {
+        loop {
+            match Pin::new(&mut self.delay).poll(ctx) {
+                Poll::Ready(Err(_)) if !self.fallback => {
+                    let at = self.at;
+                    self.fallback = true;
+
+                    match *FALLBACK_TIMER {
+                        Some(ref timer) => self.delay = timer.delay(at).compat(),
+                        None => return Poll::Ready(Err(tokio_timer::Error::shutdown())),
+                    }
+                },
+                res => return res,
+            }
+        }
+    }
+}
+
+/// Resolves with the timeout error once the deadline passed.
+///
+/// Never completes when no timer is available at all.
+pub(crate) struct Sleep {
+    wait: Option<Wait>,
+    phase: TimeoutPhase,
+}
+
+impl Future for Sleep {
+    type Output = Error;
+
+    fn poll(mut self: Pin<&mut Self>, ctx: &mut Context) -> Poll<Error> {
+        let res = match self.wait {
+            Some(ref mut wait) => Pin::new(wait).poll(ctx),
+            None => return Poll::Pending,
+        };
+
+        match res {
+            Poll::Ready(Ok(())) => Poll::Ready(self.phase.error()),
+            Poll::Ready(Err(_)) => {
+                self.wait = None;
+                Poll::Pending
+            },
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Waits for `duration`, failing when no timer is available.
+pub(crate) async fn sleep(duration: Duration) -> io::Result<()> {
+    Wait::new(Instant::now() + duration).await.map_err(io::Error::other)
+}
+
+/// Runs `fut`, failing with a timeout error if `deadline` passes first.
+pub(crate) async fn timed<F, T>(fut: F, deadline: Option<Deadline>) -> Result<T, Error>
+    where F: Future<Output = Result<T, Error>>
+{
+    let deadline = match deadline {
+        Some(deadline) => deadline,
+        None => return fut.await,
+    };
+
+    match future::select(Box::pin(fut), deadline.delay()).await {
+        Either::Left((res, _)) => res,
+        Either::Right((err, _)) => Err(err),
+    }
+}
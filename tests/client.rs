@@ -0,0 +1,1009 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use futures::executor::block_on;
+use futures::stream::StreamExt;
+use simple_http::auth::{Credentials, Provider};
+use simple_http::middleware::{BoxFuture, Middleware, Next};
+use simple_http::redirect::{self, RedirectError};
+use simple_http::{retry, Body, Client, Error, HeaderMap, HttpConnector, HttpsConnector, Method, ParseError, Request, RequestBody, Response, TimeoutPhase, Uri, Version};
+
+/// A request as received by the server.
+#[derive(Clone, Debug)]
+struct Received {
+    /// The connection the request came on, counted from 0.
+    conn: usize,
+    head: String,
+    body: Vec<u8>,
+    /// The body as it was sent, chunks and trailers included.
+    wire: Vec<u8>,
+}
+
+impl Received {
+    fn request_line(&self) -> &str {
+        self.head.lines().next().unwrap_or("")
+    }
+
+    fn header(&self, name: &str) -> Option<&str> {
+        self.head.lines().skip(1)
+            .filter_map(|line| line.split_once(':'))
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.trim())
+    }
+}
+
+/// The answer of the server to a request.
+enum Reply {
+    /// Sends the response and waits for the next request on the connection.
+    KeepAlive(String),
+    /// Like `KeepAlive`, for a response that isn't text.
+    #[cfg_attr(not(any(feature = "gzip", feature = "json")), allow(dead_code))]
+    Binary(Vec<u8>),
+    /// Sends the response and closes the connection.
+    Close(String),
+    /// Sends the start of a response and keeps the connection open without sending more.
+    Stall(String),
+}
+
+/// An HTTP/1.1 server answering requests with `respond`, recording what it received.
+struct Server {
+    addr: SocketAddr,
+    received: Arc<Mutex<Vec<Received>>>,
+}
+
+impl Server {
+    fn start(respond: impl Fn(&Received) -> Reply + Send + Sync + 'static) -> Server {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let log = received.clone();
+        let respond = Arc::new(respond);
+
+        thread::spawn(move || {
+            for (conn, stream) in listener.incoming().enumerate() {
+                let log = log.clone();
+                let respond = respond.clone();
+                thread::spawn(move || serve(conn, stream.unwrap(), &log, &*respond));
+            }
+        });
+
+        Server { addr, received }
+    }
+
+    fn uri(&self, path: &str) -> Uri {
+        format!("http://{}{}", self.addr, path).parse().unwrap()
+    }
+
+    fn received(&self) -> Vec<Received> {
+        self.received.lock().unwrap().clone()
+    }
+
+    fn connections(&self) -> usize {
+        self.received().iter().map(|received| received.conn + 1).max().unwrap_or(0)
+    }
+}
+
+fn serve(conn: usize, stream: TcpStream, log: &Mutex<Vec<Received>>, respond: &dyn Fn(&Received) -> Reply) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut stream = stream;
+
+    while let Some(received) = read_request(conn, &mut reader) {
+        log.lock().unwrap().push(received.clone());
+
+        match respond(&received) {
+            Reply::KeepAlive(res) => stream.write_all(res.as_bytes()).unwrap(),
+            Reply::Binary(res) => stream.write_all(&res).unwrap(),
+            Reply::Close(res) => {
+                let _ = stream.write_all(res.as_bytes());
+                return;
+            },
+            Reply::Stall(res) => {
+                let _ = stream.write_all(res.as_bytes());
+                thread::sleep(Duration::from_secs(10));
+                return;
+            },
+        }
+    }
+}
+
+fn read_request(conn: usize, reader: &mut impl BufRead) -> Option<Received> {
+    let mut head = String::new();
+
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).ok()? == 0 {
+            return None;
+        }
+
+        if line.trim_end().is_empty() {
+            break;
+        }
+
+        head.push_str(&line);
+    }
+
+    let mut received = Received { conn, head, body: Vec::new(), wire: Vec::new() };
+
+    if received.header("transfer-encoding").is_some() {
+        loop {
+            let mut size = String::new();
+            reader.read_line(&mut size).ok()?;
+            received.wire.extend_from_slice(size.as_bytes());
+            let size = usize::from_str_radix(size.trim().split(';').next()?, 16).ok()?;
+
+            if size == 0 {
+                // trailers, up to the empty line
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).ok()?;
+                    received.wire.extend_from_slice(line.as_bytes());
+                    if line.trim_end().is_empty() {
+                        break;
+                    }
+                    received.head.push_str(&line);
+                }
+
+                break;
+            }
+
+            let mut chunk = vec![0; size + 2];
+            reader.read_exact(&mut chunk).ok()?;
+            received.wire.extend_from_slice(&chunk);
+            received.body.extend_from_slice(&chunk[.. size]);
+        }
+    } else if let Some(len) = received.header("content-length") {
+        received.body = vec![0; len.parse().ok()?];
+        reader.read_exact(&mut received.body).ok()?;
+        received.wire = received.body.clone();
+    }
+
+    Some(received)
+}
+
+fn ok(body: &str) -> String {
+    format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}", body.len(), body)
+}
+
+fn client() -> Client<HttpConnector> {
+    Client::new(HttpConnector::try_new(1).unwrap())
+}
+
+fn get(client: &Client<HttpConnector>, uri: Uri) -> Response<Body> {
+    block_on(client.request(Request::builder().uri(uri).done().unwrap())).unwrap()
+}
+
+fn read(res: Response<Body>) -> Vec<u8> {
+    block_on(async {
+        let mut body = res.into_body();
+        let mut buf = Vec::new();
+
+        while let Some(chunk) = body.next().await {
+            buf.extend_from_slice(&chunk.unwrap());
+        }
+
+        buf
+    })
+}
+
+#[test]
+fn reuses_connections() {
+    let server = Server::start(|_| Reply::KeepAlive(ok("hello")));
+    let client = client();
+
+    for _ in 0 .. 3 {
+        assert_eq!(read(get(&client, server.uri("/"))), b"hello");
+    }
+
+    assert_eq!(server.received().len(), 3);
+    assert_eq!(server.connections(), 1);
+}
+
+#[test]
+fn does_not_reuse_connections_closed_by_the_server() {
+    let server = Server::start(|_| Reply::Close("HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Length: 2\r\n\r\nok".to_string()));
+    let client = client();
+
+    assert_eq!(read(get(&client, server.uri("/"))), b"ok");
+    assert_eq!(read(get(&client, server.uri("/"))), b"ok");
+    assert_eq!(server.connections(), 2);
+}
+
+#[test]
+fn replays_requests_on_stale_connections() {
+    // the server closes every connection after the response, as if it timed out while idle
+    let server = Server::start(|_| Reply::Close(ok("hello")));
+    let client = client();
+
+    assert_eq!(read(get(&client, server.uri("/a"))), b"hello");
+    assert_eq!(read(get(&client, server.uri("/b"))), b"hello");
+
+    let received = server.received();
+    assert_eq!(received.len(), 2);
+    assert_eq!(received[1].request_line(), "GET /b HTTP/1.1");
+    assert_eq!(server.connections(), 2);
+}
+
+#[test]
+fn does_not_replay_non_idempotent_requests_on_stale_connections() {
+    let server = Server::start(|_| Reply::Close(ok("hello")));
+    let client = client();
+
+    assert_eq!(read(get(&client, server.uri("/a"))), b"hello");
+
+    // the server may have acted on the request before closing the connection
+    let req = Request::builder().method(Method::POST).uri(server.uri("/b")).body(RequestBody::from("payload")).unwrap();
+    assert!(send(&client, req).is_err());
+    assert_eq!(server.received().len(), 1);
+}
+
+#[test]
+fn keeps_connections_with_unread_bodies_out_of_the_pool() {
+    let server = Server::start(|_| Reply::KeepAlive(ok("hello")));
+    let client = client();
+
+    drop(get(&client, server.uri("/")));
+    assert_eq!(read(get(&client, server.uri("/"))), b"hello");
+    assert_eq!(server.connections(), 2);
+}
+
+#[test]
+fn skips_interim_responses() {
+    let server = Server::start(|_| Reply::KeepAlive(format!(
+        "HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 103 Early Hints\r\nLink: </style.css>; rel=preload\r\n\r\n{}",
+        ok("hello"),
+    )));
+    let client = client();
+
+    for _ in 0 .. 2 {
+        let res = get(&client, server.uri("/"));
+        assert_eq!(res.status(), 200);
+        assert!(res.headers().get("link").is_none());
+        assert_eq!(read(res), b"hello");
+    }
+
+    assert_eq!(server.connections(), 1);
+}
+
+fn with_header(name: &str, len: usize) -> String {
+    format!("HTTP/1.1 200 OK\r\n{}: {}\r\nContent-Length: 5\r\n\r\nhello", name, "x".repeat(len))
+}
+
+#[test]
+fn reads_heads_larger_than_the_read_buffer() {
+    // the buffer starts at 4 KiB and doubles until the head fits
+    let server = Server::start(|_| Reply::KeepAlive(with_header("x-large", 20_000)));
+    let client = client();
+
+    for _ in 0 .. 2 {
+        let res = get(&client, server.uri("/"));
+        assert_eq!(res.headers()["x-large"].len(), 20_000);
+        assert_eq!(read(res), b"hello");
+    }
+
+    assert_eq!(server.connections(), 1);
+}
+
+#[test]
+fn fails_on_heads_larger_than_the_max_head_size() {
+    let server = Server::start(|_| Reply::KeepAlive(with_header("x-large", 2_000)));
+    let client = Client::builder()
+        .max_head_size(1024)
+        .build(HttpConnector::try_new(1).unwrap());
+
+    match block_on(client.request(Request::builder().uri(server.uri("/")).done().unwrap())) {
+        Err(Error::Parse(ParseError::HeadTooLarge(1024))) => (),
+        Err(err) => panic!("unexpected error: {:?}", err),
+        Ok(res) => panic!("unexpected response: {}", res.status()),
+    }
+
+    // the default limit lets the same head through
+    assert_eq!(read(get(&self::client(), server.uri("/"))), b"hello");
+}
+
+#[test]
+fn waits_before_retrying_outside_of_a_runtime() {
+    let server = Server::start(|_| Reply::KeepAlive("HTTP/1.1 503 Service Unavailable\r\nRetry-After: 1\r\nContent-Length: 0\r\n\r\n".to_string()));
+    let client = Client::builder()
+        .retry(retry::Policy::limited(2))
+        .build(HttpConnector::try_new(1).unwrap());
+
+    let started = Instant::now();
+    let res = get(&client, server.uri("/busy"));
+
+    assert_eq!(res.status(), 503);
+    assert_eq!(server.received().len(), 2);
+    assert!(started.elapsed() >= Duration::from_secs(1), "{:?}", started.elapsed());
+}
+
+fn read_timeout_phase(res: Response<Body>) -> Option<TimeoutPhase> {
+    block_on(async {
+        let mut body = res.into_body();
+
+        while let Some(chunk) = body.next().await {
+            if let Err(err) = chunk {
+                return Error::from(err).timeout_phase();
+            }
+        }
+
+        None
+    })
+}
+
+#[test]
+fn times_out_connecting() {
+    // accepts connections, but never answers the TLS handshake
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let _conns: Vec<_> = listener.incoming().collect();
+    });
+
+    let client = Client::builder()
+        .connect_timeout(Some(Duration::from_millis(200)))
+        .build(HttpsConnector::new(1).unwrap());
+    let uri: Uri = format!("https://{}/", addr).parse().unwrap();
+
+    let err = block_on(client.request(Request::builder().uri(uri).done().unwrap())).err().unwrap();
+    assert_eq!(err.timeout_phase(), Some(TimeoutPhase::Connect));
+}
+
+#[test]
+fn times_out_waiting_for_the_head() {
+    let server = Server::start(|_| Reply::Stall(String::new()));
+    let client = Client::builder()
+        .head_timeout(Some(Duration::from_millis(200)))
+        .build(HttpConnector::try_new(1).unwrap());
+
+    let err = block_on(client.request(Request::builder().uri(server.uri("/")).done().unwrap())).err().unwrap();
+    assert_eq!(err.timeout_phase(), Some(TimeoutPhase::Head));
+}
+
+#[test]
+fn times_out_waiting_for_the_body() {
+    let server = Server::start(|_| Reply::Stall("HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nhello".to_string()));
+    let client = Client::builder()
+        .body_idle_timeout(Some(Duration::from_millis(200)))
+        .build(HttpConnector::try_new(1).unwrap());
+
+    assert_eq!(read_timeout_phase(get(&client, server.uri("/"))), Some(TimeoutPhase::BodyIdle));
+}
+
+#[test]
+fn counts_the_body_idle_time_from_the_head() {
+    let server = Server::start(|_| Reply::Stall("HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n".to_string()));
+    let client = Client::builder()
+        .body_idle_timeout(Some(Duration::from_millis(300)))
+        .build(HttpConnector::try_new(1).unwrap());
+
+    let res = get(&client, server.uri("/"));
+    thread::sleep(Duration::from_millis(400));
+
+    let started = Instant::now();
+    assert_eq!(read_timeout_phase(res), Some(TimeoutPhase::BodyIdle));
+    assert!(started.elapsed() < Duration::from_millis(200), "{:?}", started.elapsed());
+}
+
+#[test]
+fn times_out_the_whole_request() {
+    let server = Server::start(|_| Reply::Stall("HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nhello".to_string()));
+    let client = Client::builder()
+        .timeout(Some(Duration::from_millis(300)))
+        .build(HttpConnector::try_new(1).unwrap());
+
+    let started = Instant::now();
+    let res = get(&client, server.uri("/"));
+
+    assert_eq!(read_timeout_phase(res), Some(TimeoutPhase::Total));
+    assert!(started.elapsed() >= Duration::from_millis(300), "{:?}", started.elapsed());
+}
+
+fn redirect(status: &str, location: &str) -> String {
+    format!("HTTP/1.1 {}\r\nLocation: {}\r\nContent-Length: 0\r\n\r\n", status, location)
+}
+
+/// Redirects `/<n>` to `/<n - 1>`, and answers `/0`.
+fn countdown(received: &Received) -> Reply {
+    let path = received.request_line().split(' ').nth(1).unwrap();
+    let n: usize = path[1 ..].parse().unwrap();
+
+    Reply::KeepAlive(match n {
+        0 => ok("done"),
+        n => redirect("302 Found", &format!("/{}", n - 1)),
+    })
+}
+
+fn send(client: &Client<HttpConnector>, req: Request) -> Result<Response<Body>, Error> {
+    block_on(client.request(req))
+}
+
+#[test]
+fn follows_at_most_max_hops_redirects() {
+    let server = Server::start(countdown);
+
+    for max in 1 .. 4 {
+        let client = Client::builder()
+            .redirect(redirect::Policy::limited(max))
+            .build(HttpConnector::try_new(1).unwrap());
+
+        let res = get(&client, server.uri(&format!("/{}", max)));
+        assert_eq!(res.status(), 200);
+        assert_eq!(read(res), b"done");
+
+        match send(&client, Request::builder().uri(server.uri(&format!("/{}", max + 1))).body(RequestBody::empty()).unwrap()) {
+            Err(Error::Redirect(RedirectError::TooManyHops(hops))) => assert_eq!(hops, max),
+            res => panic!("expected too many hops, got {:?}", res.map(|res| res.status())),
+        }
+    }
+}
+
+#[test]
+fn returns_redirects_when_disabled() {
+    let server = Server::start(countdown);
+    let client = Client::builder()
+        .redirect(redirect::Policy::none())
+        .build(HttpConnector::try_new(1).unwrap());
+
+    assert_eq!(get(&client, server.uri("/1")).status(), 302);
+    assert_eq!(server.received().len(), 1);
+}
+
+#[test]
+fn detects_redirect_loops() {
+    let server = Server::start(|received| Reply::KeepAlive(match received.request_line() {
+        "GET /a HTTP/1.1" => redirect("302 Found", "/b"),
+        _ => redirect("302 Found", "/a"),
+    }));
+
+    match send(&client(), Request::builder().uri(server.uri("/a")).body(RequestBody::empty()).unwrap()) {
+        Err(Error::Redirect(RedirectError::Loop(uri))) => assert_eq!(uri, server.uri("/a")),
+        res => panic!("expected a loop, got {:?}", res.map(|res| res.status())),
+    }
+
+    assert_eq!(server.received().len(), 2);
+}
+
+#[test]
+fn rewrites_methods_as_the_redirect_asks() {
+    let server = Server::start(|received| {
+        let path = received.request_line().split(' ').nth(1).unwrap();
+
+        Reply::KeepAlive(match path {
+            "/301" => redirect("301 Moved Permanently", "/target"),
+            "/302" => redirect("302 Found", "/target"),
+            "/303" => redirect("303 See Other", "/target"),
+            "/307" => redirect("307 Temporary Redirect", "/target"),
+            "/308" => redirect("308 Permanent Redirect", "/target"),
+            _ => ok(""),
+        })
+    });
+    let client = client();
+
+    for (status, method) in [(301, "GET"), (302, "GET"), (303, "GET"), (307, "POST"), (308, "POST")] {
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri(server.uri(&format!("/{}", status)))
+            .body(RequestBody::from("payload"))
+            .unwrap();
+
+        assert_eq!(send(&client, req).unwrap().status(), 200);
+
+        let received = server.received();
+        let target = received.last().unwrap();
+        assert_eq!(target.request_line(), format!("{} /target HTTP/1.1", method), "after a {}", status);
+
+        if method == "POST" {
+            assert_eq!(target.body, b"payload");
+        }
+    }
+}
+
+#[test]
+fn drops_credentials_on_cross_origin_redirects() {
+    let other = Server::start(|_| Reply::KeepAlive(ok("")));
+    let location = other.uri("/landed").to_string();
+    let server = Server::start(move |received| Reply::KeepAlive(match received.request_line() {
+        "GET /same HTTP/1.1" => redirect("302 Found", "/landed"),
+        "GET /cross HTTP/1.1" => redirect("302 Found", &location),
+        _ => ok(""),
+    }));
+    let client = client();
+
+    for path in ["/same", "/cross"] {
+        let req = Request::builder()
+            .uri(server.uri(path))
+            .header("Authorization", "Bearer secret")
+            .header("Cookie", "session=1")
+            .body(RequestBody::empty())
+            .unwrap();
+
+        assert_eq!(send(&client, req).unwrap().status(), 200);
+    }
+
+    let same = &server.received()[1];
+    assert_eq!(same.request_line(), "GET /landed HTTP/1.1");
+    assert_eq!(same.header("authorization"), Some("Bearer secret"));
+    assert_eq!(same.header("cookie"), Some("session=1"));
+
+    let cross = &other.received()[0];
+    assert_eq!(cross.header("authorization"), None);
+    assert_eq!(cross.header("cookie"), None);
+}
+
+#[test]
+fn drops_the_body_when_redirected_to_a_get() {
+    let server = Server::start(|received| Reply::KeepAlive(match received.request_line() {
+        "POST /form HTTP/1.1" => redirect("303 See Other", "/done"),
+        _ => ok(""),
+    }));
+
+    let req = Request::builder()
+        .method(Method::POST)
+        .uri(server.uri("/form"))
+        .header("Content-Type", "text/plain")
+        .body(RequestBody::from(bytes::Bytes::from_static(b"payload")))
+        .unwrap();
+
+    assert_eq!(send(&client(), req).unwrap().status(), 200);
+
+    let received = server.received();
+    assert_eq!(received[0].body, b"payload");
+    assert_eq!(received[1].request_line(), "GET /done HTTP/1.1");
+    assert_eq!(received[1].header("content-length"), None);
+    assert_eq!(received[1].header("transfer-encoding"), None);
+    assert_eq!(received[1].header("content-type"), None);
+    assert!(received[1].body.is_empty());
+}
+
+#[cfg(feature = "digest")]
+const UNAUTHORIZED: &str = "HTTP/1.1 401 Unauthorized\r\nWWW-Authenticate: Digest realm=\"test\", qop=\"auth\", nonce=\"abc\"\r\nContent-Length: 0\r\n\r\n";
+
+#[cfg(feature = "digest")]
+#[test]
+fn answers_digest_challenges() {
+    let server = Server::start(|received| Reply::KeepAlive(match received.header("authorization") {
+        Some(authorization) if authorization.starts_with("Digest ") => ok("welcome"),
+        _ => UNAUTHORIZED.to_string(),
+    }));
+    let host = server.uri("/").authority_part().unwrap().to_string();
+    let client = Client::builder()
+        .credentials(host, Credentials::digest("Mufasa", "Circle of Life"))
+        .build(HttpConnector::try_new(1).unwrap());
+
+    let res = get(&client, server.uri("/dir?a=1"));
+    assert_eq!(res.status(), 200);
+    assert_eq!(read(res), b"welcome");
+
+    let received = server.received();
+    assert_eq!(received.len(), 2);
+    assert_eq!(received[0].header("authorization"), None);
+
+    let authorization = received[1].header("authorization").unwrap();
+    assert!(authorization.contains(r#"username="Mufasa", realm="test", nonce="abc", uri="/dir?a=1""#), "{}", authorization);
+    assert!(authorization.contains("qop=auth, nc=00000001"), "{}", authorization);
+}
+
+#[cfg(feature = "digest")]
+#[test]
+fn answers_a_challenge_once() {
+    let server = Server::start(|_| Reply::KeepAlive(UNAUTHORIZED.to_string()));
+    let host = server.uri("/").authority_part().unwrap().to_string();
+    let client = Client::builder()
+        .credentials(host, Credentials::digest("Mufasa", "wrong"))
+        .build(HttpConnector::try_new(1).unwrap());
+
+    assert_eq!(get(&client, server.uri("/")).status(), 401);
+    assert_eq!(server.received().len(), 2);
+}
+
+/// Hands out a new token once the previous one was rejected.
+struct Tokens(Mutex<usize>);
+
+impl Provider for Tokens {
+    fn credentials(&self, _uri: &Uri) -> Option<Credentials> {
+        Credentials::bearer(&format!("token{}", self.0.lock().unwrap())).ok()
+    }
+
+    fn rejected(&self, _uri: &Uri, _credentials: &Credentials) {
+        *self.0.lock().unwrap() += 1;
+    }
+}
+
+#[test]
+fn resends_requests_with_renewed_credentials() {
+    let server = Server::start(|received| Reply::KeepAlive(match received.header("authorization") {
+        Some("Bearer token1") => ok("welcome"),
+        _ => "HTTP/1.1 401 Unauthorized\r\nWWW-Authenticate: Bearer\r\nContent-Length: 0\r\n\r\n".to_string(),
+    }));
+    let client = Client::builder()
+        .credential_provider(Tokens(Mutex::new(0)))
+        .build(HttpConnector::try_new(1).unwrap());
+
+    assert_eq!(get(&client, server.uri("/")).status(), 200);
+
+    let received = server.received();
+    assert_eq!(received.len(), 2);
+    assert_eq!(received[0].header("authorization"), Some("Bearer token0"));
+    assert_eq!(received[1].header("authorization"), Some("Bearer token1"));
+
+    // the renewed token is sent up front from then on
+    assert_eq!(get(&client, server.uri("/")).status(), 200);
+    assert_eq!(server.received().len(), 3);
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn decodes_compressed_bodies() {
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    let server = Server::start(|received| {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"hello, hello, hello").unwrap();
+        let body = encoder.finish().unwrap();
+
+        assert!(received.header("accept-encoding").unwrap().contains("gzip"));
+
+        let mut res = format!("HTTP/1.1 200 OK\r\nContent-Encoding: gzip\r\nContent-Length: {}\r\nX-Kept: 1\r\n\r\n", body.len()).into_bytes();
+        res.extend_from_slice(&body);
+        Reply::Binary(res)
+    });
+
+    let res = get(&client(), server.uri("/"));
+    // the headers describing the encoded body are gone with it
+    assert_eq!(res.headers().get("content-encoding"), None);
+    assert_eq!(res.headers().get("content-length"), None);
+    assert_eq!(res.headers().get("x-kept").unwrap(), "1");
+    assert_eq!(read(res), b"hello, hello, hello");
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn fails_on_corrupt_compressed_bodies() {
+    let server = Server::start(|_| Reply::KeepAlive("HTTP/1.1 200 OK\r\nContent-Encoding: gzip\r\nContent-Length: 7\r\n\r\ngarbage".to_string()));
+
+    let mut body = get(&client(), server.uri("/")).into_body();
+
+    block_on(async {
+        while let Some(res) = body.next().await {
+            if res.is_err() {
+                return;
+            }
+        }
+
+        panic!("the body ended without an error");
+    });
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn sets_the_content_type_of_json_and_forms() {
+    let server = Server::start(|_| Reply::KeepAlive(ok("")));
+    let client = client();
+
+    let req = Request::builder().method(Method::POST).uri(server.uri("/json"))
+        .json(&serde_json::json!({ "name": "a b", "n": 1 }))
+        .unwrap();
+    send(&client, req).unwrap();
+
+    let req = Request::builder().method(Method::POST).uri(server.uri("/form"))
+        .form(&[("name", "a b"), ("q", "é&")])
+        .unwrap();
+    send(&client, req).unwrap();
+
+    let received = server.received();
+    assert_eq!(received[0].header("content-type"), Some("application/json"));
+    assert_eq!(received[0].body, br#"{"n":1,"name":"a b"}"#);
+    assert_eq!(received[1].header("content-type"), Some("application/x-www-form-urlencoded"));
+    assert_eq!(received[1].body, b"name=a+b&q=%C3%A9%26");
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn keeps_the_content_type_set_by_the_caller() {
+    let server = Server::start(|_| Reply::KeepAlive(ok("")));
+    let client = client();
+
+    let req = Request::builder().method(Method::POST).uri(server.uri("/json"))
+        .header("Content-Type", "application/vnd.api+json")
+        .json(&serde_json::json!([1, 2]))
+        .unwrap();
+    send(&client, req).unwrap();
+
+    let req = Request::builder().method(Method::POST).uri(server.uri("/form"))
+        .header("content-type", "application/x-www-form-urlencoded; charset=utf-8")
+        .form(&[("a", "1")])
+        .unwrap();
+    send(&client, req).unwrap();
+
+    let received = server.received();
+    assert_eq!(received[0].header("content-type"), Some("application/vnd.api+json"));
+    assert_eq!(received[1].header("content-type"), Some("application/x-www-form-urlencoded; charset=utf-8"));
+    // the header is sent once
+    assert_eq!(received[1].head.matches("charset").count(), 1);
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn reads_text_and_json_bodies() {
+    let server = Server::start(|received| Reply::Binary(match received.request_line() {
+        line if line.starts_with("GET /latin1 ") => b"HTTP/1.1 200 OK\r\nContent-Type: text/plain; charset=ISO-8859-1\r\nContent-Length: 4\r\n\r\ncaf\xe9".to_vec(),
+        line if line.starts_with("GET /json ") => ok(r#"{"ok":true}"#).into_bytes(),
+        _ => b"HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: 4\r\n\r\nab\xffc".to_vec(),
+    }));
+    let client = client();
+
+    assert_eq!(block_on(get(&client, server.uri("/latin1")).text()).unwrap(), "café");
+    // without a charset, invalid UTF-8 is replaced
+    assert_eq!(block_on(get(&client, server.uri("/invalid")).text()).unwrap(), "ab\u{fffd}c");
+
+    let value: serde_json::Value = block_on(get(&client, server.uri("/json")).json()).unwrap();
+    assert_eq!(value, serde_json::json!({ "ok": true }));
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn enforces_the_max_body_size() {
+    let server = Server::start(|received| {
+        let len: usize = received.request_line().split(' ').nth(1).unwrap()[1 ..].parse().unwrap();
+        Reply::KeepAlive(ok(&format!("\"{}\"", "a".repeat(len - 2))))
+    });
+    let client = Client::builder().max_body_size(8).build(HttpConnector::try_new(1).unwrap());
+
+    assert_eq!(block_on(get(&client, server.uri("/8")).bytes()).unwrap(), b"\"aaaaaa\"");
+    assert_eq!(block_on(get(&client, server.uri("/8")).text()).unwrap(), "\"aaaaaa\"");
+    assert_eq!(block_on(get(&client, server.uri("/8")).json::<String>()).unwrap(), "aaaaaa");
+
+    let too_large = |res: Result<_, Error>| matches!(res, Err(Error::Body(ref err)) if err.kind() == std::io::ErrorKind::InvalidData);
+    assert!(too_large(block_on(get(&client, server.uri("/9")).bytes()).map(drop)));
+    assert!(too_large(block_on(get(&client, server.uri("/9")).text()).map(drop)));
+    assert!(too_large(block_on(get(&client, server.uri("/9")).json::<String>()).map(drop)));
+}
+
+/// Logs the request on the way in and the response on the way out, tagging both.
+struct Tag {
+    name: &'static str,
+    log: Arc<Mutex<Vec<String>>>,
+}
+
+impl Middleware for Tag {
+    fn handle<'a>(&'a self, mut req: Request, next: Next<'a>) -> BoxFuture<'a, Result<Response<Body>, Error>> {
+        Box::pin(async move {
+            self.log.lock().unwrap().push(format!("{} >", self.name));
+            req.headers_mut().append("x-tag", self.name.parse().unwrap());
+
+            let mut res = next.run(req).await?;
+
+            self.log.lock().unwrap().push(format!("{} <", self.name));
+            res.headers_mut().append("x-tag", self.name.parse().unwrap());
+            Ok(res)
+        })
+    }
+}
+
+/// Answers requests to `/cached` on its own.
+struct Cache;
+
+impl Middleware for Cache {
+    fn handle<'a>(&'a self, req: Request, next: Next<'a>) -> BoxFuture<'a, Result<Response<Body>, Error>> {
+        if req.uri().path() == "/cached" {
+            return Box::pin(async { Ok(Response::new(200, HeaderMap::new(), Body::from(b"cached".to_vec()))) });
+        }
+
+        next.run(req)
+    }
+}
+
+#[test]
+fn runs_middleware_in_order() {
+    let server = Server::start(|_| Reply::KeepAlive(ok("hello")));
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let client = Client::builder()
+        .middleware(Tag { name: "a", log: log.clone() })
+        .middleware(Tag { name: "b", log: log.clone() })
+        .build(HttpConnector::try_new(1).unwrap());
+
+    let res = get(&client, server.uri("/"));
+
+    // the first middleware sees the request first and the response last
+    assert_eq!(*log.lock().unwrap(), vec!["a >", "b >", "b <", "a <"]);
+    let tags: Vec<_> = res.headers().get_all("x-tag").iter().map(|tag| tag.to_str().unwrap()).collect();
+    assert_eq!(tags, vec!["b", "a"]);
+    assert_eq!(read(res), b"hello");
+
+    let received = server.received();
+    assert_eq!(received.len(), 1);
+    assert!(received[0].head.contains("x-tag: a\r\nx-tag: b\r\n"), "{}", received[0].head);
+}
+
+#[test]
+fn lets_middleware_answer_on_their_own() {
+    let server = Server::start(|_| Reply::KeepAlive(ok("hello")));
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let client = Client::builder()
+        .middleware(Tag { name: "a", log: log.clone() })
+        .middleware(Cache)
+        .middleware(Tag { name: "b", log: log.clone() })
+        .build(HttpConnector::try_new(1).unwrap());
+
+    assert_eq!(read(get(&client, server.uri("/cached"))), b"cached");
+    // the middleware after the one answering are skipped, with the server
+    assert_eq!(*log.lock().unwrap(), vec!["a >", "a <"]);
+    assert!(server.received().is_empty());
+
+    assert_eq!(read(get(&client, server.uri("/"))), b"hello");
+    assert_eq!(server.received().len(), 1);
+}
+
+#[cfg(feature = "tower")]
+#[test]
+fn serves_as_an_owned_tower_service() {
+    use tower_service::Service;
+
+    fn spawned<S>(mut service: S, req: Request) -> thread::JoinHandle<Result<Response<Body>, Error>>
+        where S: Service<Request, Response = Response<Body>, Error = Error> + Send + 'static,
+              S::Future: Send + 'static,
+    {
+        thread::spawn(move || block_on(service.call(req)))
+    }
+
+    let server = Server::start(|_| Reply::KeepAlive(ok("hello")));
+    let client = client();
+
+    let handles: Vec<_> = (0 .. 3)
+        .map(|_| spawned(client.clone(), Request::builder().uri(server.uri("/")).done().unwrap().map(Into::into)))
+        .collect();
+
+    for handle in handles {
+        assert_eq!(read(handle.join().unwrap().unwrap()), b"hello");
+    }
+
+    // the clones share the pool of the client
+    assert_eq!(read(get(&client, server.uri("/"))), b"hello");
+    assert!(server.connections() <= 3);
+}
+
+#[test]
+fn sends_the_version_in_the_request_line() {
+    let server = Server::start(|_| Reply::KeepAlive(ok("")));
+    let client = client();
+
+    let send_version = |version| {
+        let req = Request::builder().uri(server.uri("/")).version(version).done().unwrap();
+        read(block_on(client.request(req)).unwrap());
+    };
+
+    send_version(Version::HTTP_11);
+    send_version(Version::HTTP_10);
+
+    let received = server.received();
+    assert_eq!(received[0].request_line(), "GET / HTTP/1.1");
+    assert_eq!(received[0].header("connection"), None);
+    // an HTTP/1.0 server closes the connection unless asked to keep it
+    assert_eq!(received[1].request_line(), "GET / HTTP/1.0");
+    assert_eq!(received[1].header("connection"), Some("keep-alive"));
+
+    let req = Request::builder().uri(server.uri("/")).version(Version::HTTP_10).keep_alive(false).done().unwrap();
+    read(block_on(client.request(req)).unwrap());
+    assert_eq!(server.received()[2].header("connection"), Some("close"));
+}
+
+#[test]
+fn sends_the_port_in_the_host_header_unless_it_is_the_default() {
+    use simple_http::proxy::{Intercept, Proxy, ProxyConnector};
+
+    let server = Server::start(|_| Reply::KeepAlive(ok("")));
+
+    // the proxy is the server, which receives the requests to any host
+    let proxy = Proxy::new(Intercept::Http, server.uri("/"));
+    let client = Client::new(ProxyConnector::from_proxy_unsecured(HttpConnector::try_new(1).unwrap(), proxy));
+
+    for uri in &["http://example.com/", "http://example.com:80/", "http://example.com:8080/"] {
+        read(block_on(client.request(Request::builder().uri(uri.parse().unwrap()).done().unwrap())).unwrap());
+    }
+
+    let hosts: Vec<_> = server.received().iter().map(|received| received.header("host").unwrap().to_string()).collect();
+    assert_eq!(hosts, vec!["example.com", "example.com", "example.com:8080"]);
+
+    read(get(&self::client(), server.uri("/")));
+    assert_eq!(server.received()[3].header("host"), Some(&format!("127.0.0.1:{}", server.addr.port())[..]));
+}
+
+#[test]
+fn reads_http_1_0_responses() {
+    let server = Server::start(|received| match received.request_line() {
+        "GET /keep-alive HTTP/1.1" => Reply::KeepAlive("HTTP/1.0 200 OK\r\nConnection: keep-alive\r\nContent-Length: 5\r\n\r\nhello".to_string()),
+        _ => Reply::Close("HTTP/1.0 200 OK\r\n\r\nuntil the end".to_string()),
+    });
+    let client = client();
+
+    // without a length, the body ends with the connection
+    let res = get(&client, server.uri("/"));
+    assert_eq!(res.version(), Version::HTTP_10);
+    assert_eq!(read(res), b"until the end");
+    assert_eq!(read(get(&client, server.uri("/"))), b"until the end");
+    assert_eq!(server.connections(), 2);
+
+    // a connection the server keeps alive is reused
+    assert_eq!(read(get(&client, server.uri("/keep-alive"))), b"hello");
+    assert_eq!(read(get(&client, server.uri("/keep-alive"))), b"hello");
+    assert_eq!(server.connections(), 3);
+}
+
+fn chunks(chunks: &[&'static str]) -> RequestBody {
+    RequestBody::stream(futures::stream::iter(chunks.iter().map(|chunk| Ok(chunk.as_bytes().to_vec())).collect::<Vec<_>>()))
+}
+
+fn post(uri: Uri, body: RequestBody) -> Request {
+    Request::builder().method(Method::POST).uri(uri).body(body).unwrap()
+}
+
+#[test]
+fn sends_streams_in_chunks() {
+    let server = Server::start(|_| Reply::KeepAlive(ok("")));
+    let client = client();
+
+    // empty pieces of the stream are skipped, an empty chunk ends the body
+    send(&client, post(server.uri("/"), chunks(&["hello", "", " chunked world", "!"]))).unwrap();
+    send(&client, post(server.uri("/"), chunks(&[]))).unwrap();
+
+    let received = server.received();
+    assert_eq!(received[0].header("transfer-encoding"), Some("chunked"));
+    assert_eq!(received[0].header("content-length"), None);
+    assert_eq!(received[0].wire, &b"5\r\nhello\r\nE\r\n chunked world\r\n1\r\n!\r\n0\r\n\r\n"[..]);
+    assert_eq!(received[0].body, b"hello chunked world!");
+    assert_eq!(received[1].wire, b"0\r\n\r\n");
+}
+
+#[test]
+fn sends_trailers_after_the_last_chunk() {
+    let server = Server::start(|_| Reply::KeepAlive(ok("")));
+    let client = client();
+
+    let req = Request::builder().method(Method::POST).uri(server.uri("/"))
+        .trailer("x-checksum", "abc")
+        .trailer("x-count", "2")
+        .body(chunks(&["a", "b"]))
+        .unwrap();
+    send(&client, req).unwrap();
+
+    // ignored when the length is known
+    let req = Request::builder().method(Method::POST).uri(server.uri("/"))
+        .trailer("x-checksum", "abc")
+        .body(RequestBody::from("ab"))
+        .unwrap();
+    send(&client, req).unwrap();
+
+    let received = server.received();
+    assert_eq!(received[0].header("trailer"), Some("x-checksum, x-count"));
+    assert_eq!(received[0].wire, &b"1\r\na\r\n1\r\nb\r\n0\r\nx-checksum: abc\r\nx-count: 2\r\n\r\n"[..]);
+    assert_eq!(received[1].header("trailer"), None);
+    assert_eq!(received[1].header("content-length"), Some("2"));
+    assert_eq!(received[1].wire, b"ab");
+}
+
+#[test]
+fn fails_on_bodies_not_matching_their_length() {
+    let server = Server::start(|_| Reply::KeepAlive(ok("")));
+    let client = client();
+
+    let mismatch = |res: Result<Response<Body>, Error>| match res {
+        Err(Error::Body(err)) => err.kind() == std::io::ErrorKind::InvalidInput,
+        _ => false,
+    };
+
+    let short = RequestBody::sized(futures::stream::iter(vec![Ok(b"abc".to_vec())]), 5);
+    assert!(mismatch(send(&client, post(server.uri("/"), short))));
+
+    let long = RequestBody::sized(futures::stream::iter(vec![Ok(b"abc".to_vec()), Ok(b"def".to_vec())]), 5);
+    assert!(mismatch(send(&client, post(server.uri("/"), long))));
+
+    // with a Content-Length header set by the caller
+    let req = Request::builder().method(Method::POST).uri(server.uri("/"))
+        .header("content-length", "2")
+        .body(chunks(&["abc"]))
+        .unwrap();
+    assert!(mismatch(send(&client, req)));
+}
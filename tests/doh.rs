@@ -0,0 +1,284 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{IpAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use futures::compat::Future01CompatExt;
+use futures::executor::block_on;
+use futures::stream::StreamExt;
+use simple_http::http::ares::LookupFamily;
+use simple_http::http::dns::Resolve;
+use simple_http::http::doh::DohResolver;
+use simple_http::{Client, HttpsConnector, Request, Uri};
+
+const A: u16 = 1;
+const AAAA: u16 = 28;
+const CNAME: u16 = 5;
+
+/// The addresses and TTL of the records of a name, `None` for names that don't exist.
+fn zone(name: &str, qtype: u16) -> Option<(Vec<IpAddr>, u32)> {
+    let addrs = |addrs: &[&str]| addrs.iter().map(|addr| addr.parse().unwrap()).collect();
+
+    match (name, qtype) {
+        ("dual.test", A) => Some((addrs(&["192.0.2.1"]), 300)),
+        ("dual.test", AAAA) => Some((addrs(&["2001:db8::1"]), 300)),
+        ("short.test", A) => Some((addrs(&["192.0.2.3"]), 1)),
+        ("app.test", A) => Some((addrs(&["127.0.0.1"]), 300)),
+        ("dual.test", _) | ("short.test", _) | ("app.test", _) => Some((Vec::new(), 300)),
+        _ => None,
+    }
+}
+
+/// A DNS over HTTPS server answering from `zone`, which also serves `/hello`.
+struct Stub {
+    port: u16,
+    queries: Arc<Mutex<Vec<(String, u16)>>>,
+}
+
+impl Stub {
+    fn start() -> Stub {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let queries = Arc::new(Mutex::new(Vec::new()));
+        let log = queries.clone();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let log = log.clone();
+                thread::spawn(move || serve(stream.unwrap(), log));
+            }
+        });
+
+        Stub { port, queries }
+    }
+
+    /// A resolver whose endpoint name is only known from its bootstrap address.
+    fn resolver(&self) -> DohResolver {
+        let endpoint: Uri = format!("http://doh.test:{}/dns-query", self.port).parse().unwrap();
+        DohResolver::new(endpoint, vec!["127.0.0.1".parse().unwrap()]).unwrap()
+    }
+
+    fn queries(&self, name: &str) -> Vec<u16> {
+        self.queries.lock().unwrap().iter()
+            .filter(|(queried, _)| queried == name)
+            .map(|&(_, qtype)| qtype)
+            .collect()
+    }
+}
+
+fn serve(stream: TcpStream, log: Arc<Mutex<Vec<(String, u16)>>>) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut stream = stream;
+
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).unwrap_or(0) == 0 {
+            return;
+        }
+
+        let target = line.split(' ').nth(1).unwrap().to_string();
+        let mut length = 0;
+
+        loop {
+            let mut header = String::new();
+            reader.read_line(&mut header).unwrap();
+
+            match header.trim_end().split_once(':') {
+                Some((name, value)) if name.eq_ignore_ascii_case("content-length") => length = value.trim().parse().unwrap(),
+                Some(_) => {},
+                None => break,
+            }
+        }
+
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).unwrap();
+
+        let (content_type, response) = if target == "/dns-query" {
+            let (name, qtype) = question(&body);
+            log.lock().unwrap().push((name.clone(), qtype));
+            ("application/dns-message", wire_answer(&body, &name, qtype))
+        } else if let Some(query) = target.strip_prefix("/dns-query?") {
+            let params: Vec<_> = query.split('&').filter_map(|param| param.split_once('=')).collect();
+            let name = params.iter().find(|(key, _)| *key == "name").unwrap().1.to_string();
+            let qtype = params.iter().find(|(key, _)| *key == "type").unwrap().1.parse().unwrap();
+            log.lock().unwrap().push((name.clone(), qtype));
+            ("application/dns-json", json_answer(&name, qtype).into_bytes())
+        } else {
+            ("text/plain", b"hello".to_vec())
+        };
+
+        write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n", content_type, response.len()).unwrap();
+        stream.write_all(&response).unwrap();
+    }
+}
+
+fn question(query: &[u8]) -> (String, u16) {
+    let mut labels = Vec::new();
+    let mut pos = 12;
+
+    while query[pos] != 0 {
+        let len = usize::from(query[pos]);
+        labels.push(String::from_utf8(query[pos + 1 .. pos + 1 + len].to_vec()).unwrap());
+        pos += 1 + len;
+    }
+
+    (labels.join("."), u16::from_be_bytes([query[pos + 1], query[pos + 2]]))
+}
+
+fn record(message: &mut Vec<u8>, name: &[u8], rtype: u16, ttl: u32, data: &[u8]) {
+    message.extend_from_slice(name);
+    message.extend_from_slice(&rtype.to_be_bytes());
+    message.extend_from_slice(&[0, 1]);
+    message.extend_from_slice(&ttl.to_be_bytes());
+    message.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    message.extend_from_slice(data);
+}
+
+fn wire_answer(query: &[u8], name: &str, qtype: u16) -> Vec<u8> {
+    // `alias.test` is a CNAME of `dual.test`, answered with the records of both
+    let (alias, target) = if name == "alias.test" { (true, "dual.test") } else { (false, name) };
+    let (rcode, addrs, ttl) = match zone(target, qtype) {
+        Some((addrs, ttl)) => (0u16, addrs, ttl),
+        None => (3, Vec::new(), 0),
+    };
+
+    let question_end = 12 + name.len() + 2 + 4;
+    let mut message = query[.. 2].to_vec();
+    message.extend_from_slice(&(0x8180 | rcode).to_be_bytes());
+    message.extend_from_slice(&[0, 1]);
+    message.extend_from_slice(&((addrs.len() + alias as usize) as u16).to_be_bytes());
+    message.extend_from_slice(&[0, 0, 0, 0]);
+    message.extend_from_slice(&query[12 .. question_end]);
+
+    // the name of the records, a pointer to the question or to the target of the alias
+    let mut owner = vec![0xc0, 12];
+
+    if alias {
+        let target_at = message.len() + 12;
+        record(&mut message, &owner, CNAME, 300, b"\x04dual\x04test\x00");
+        owner = vec![0xc0, target_at as u8];
+    }
+
+    for addr in addrs {
+        let data = match addr {
+            IpAddr::V4(addr) => addr.octets().to_vec(),
+            IpAddr::V6(addr) => addr.octets().to_vec(),
+        };
+
+        record(&mut message, &owner, qtype, ttl, &data);
+    }
+
+    message
+}
+
+fn json_answer(name: &str, qtype: u16) -> String {
+    match zone(name, qtype) {
+        Some((addrs, ttl)) => {
+            let answers: Vec<_> = addrs.iter()
+                .map(|addr| format!(r#"{{"name":"{}.","type":{},"TTL":{},"data":"{}"}}"#, name, qtype, ttl, addr))
+                .collect();
+
+            format!(r#"{{"Status":0,"Answer":[{}]}}"#, answers.join(","))
+        },
+        None => r#"{"Status":3}"#.to_string(),
+    }
+}
+
+fn resolve(resolver: &DohResolver, name: &str) -> io::Result<Vec<IpAddr>> {
+    block_on(resolver.resolve(name.parse().unwrap()).compat()).map(Iterator::collect)
+}
+
+fn addrs(addrs: &[&str]) -> Vec<IpAddr> {
+    addrs.iter().map(|addr| addr.parse().unwrap()).collect()
+}
+
+#[test]
+fn resolves_both_families() {
+    let stub = Stub::start();
+    let resolver = stub.resolver();
+
+    assert_eq!(resolve(&resolver, "dual.test").unwrap(), addrs(&["2001:db8::1", "192.0.2.1"]));
+    assert_eq!(resolve(&resolver, "short.test").unwrap(), addrs(&["192.0.2.3"]));
+
+    let mut queries = stub.queries("dual.test");
+    queries.sort();
+    assert_eq!(queries, vec![A, AAAA]);
+}
+
+#[test]
+fn follows_aliases() {
+    let stub = Stub::start();
+
+    assert_eq!(resolve(&stub.resolver(), "alias.test").unwrap(), addrs(&["2001:db8::1", "192.0.2.1"]));
+}
+
+#[test]
+fn looks_up_one_family() {
+    let stub = Stub::start();
+    let resolver = stub.resolver().lookup_family(LookupFamily::V4Only);
+
+    assert_eq!(resolve(&resolver, "dual.test").unwrap(), addrs(&["192.0.2.1"]));
+    assert_eq!(stub.queries("dual.test"), vec![A]);
+}
+
+#[test]
+fn caches_answers_for_their_ttl() {
+    let stub = Stub::start();
+    let resolver = stub.resolver();
+
+    resolve(&resolver, "dual.test").unwrap();
+    resolve(&resolver, "dual.test").unwrap();
+    assert_eq!(stub.queries("dual.test").len(), 2);
+
+    resolve(&resolver, "short.test").unwrap();
+    resolve(&resolver, "short.test").unwrap();
+    assert_eq!(stub.queries("short.test").len(), 2);
+
+    thread::sleep(Duration::from_millis(1100));
+    resolve(&resolver, "short.test").unwrap();
+    assert_eq!(stub.queries("short.test").len(), 4);
+}
+
+#[test]
+fn caches_unknown_names() {
+    let stub = Stub::start();
+    let resolver = stub.resolver();
+
+    assert!(resolve(&resolver, "missing.test").is_err());
+    assert!(resolve(&resolver, "missing.test").is_err());
+    assert_eq!(stub.queries("missing.test").len(), 2);
+}
+
+#[test]
+fn client_connects_to_resolved_names() {
+    let stub = Stub::start();
+    let client = Client::builder().build(HttpsConnector::new_with_resolver(stub.resolver()).unwrap());
+    let uri = format!("http://app.test:{}/hello", stub.port).parse().unwrap();
+
+    let body = block_on(async {
+        let res = client.request(Request::builder().uri(uri).done().unwrap()).await.unwrap();
+        let mut body = res.into_body();
+        let mut buf = Vec::new();
+
+        while let Some(chunk) = body.next().await {
+            buf.extend_from_slice(&chunk.unwrap());
+        }
+
+        buf
+    });
+
+    assert_eq!(body, b"hello");
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn resolves_with_json() {
+    use simple_http::http::doh::DohFormat;
+
+    let stub = Stub::start();
+    let resolver = stub.resolver().format(DohFormat::Json);
+
+    assert_eq!(resolve(&resolver, "dual.test").unwrap(), addrs(&["2001:db8::1", "192.0.2.1"]));
+    assert!(resolve(&resolver, "missing.test").is_err());
+}
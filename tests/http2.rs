@@ -0,0 +1,292 @@
+#![cfg(feature = "http2")]
+
+use std::mem;
+use std::net::{SocketAddr, TcpListener as StdListener};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures::executor::block_on;
+use futures::future::{FutureExt, TryFutureExt};
+use futures::stream::StreamExt;
+use futures_legacy::{Future, Stream};
+use native_tls::{Certificate, Identity};
+use simple_http::{Body, Client, HttpConnector, HttpsConnector, Method, Request, RequestBody, Response, TimeoutPhase, Uri, Version};
+use tokio::net::TcpListener;
+use tokio_reactor::Handle;
+
+const CERT: &[u8] = include_bytes!("certs/localhost.crt");
+const KEY: &[u8] = include_bytes!("certs/localhost.key");
+
+/// A TLS server offering `protocols` with ALPN, which answers every request with the
+/// protocol it came with, the path, and the request body.
+struct Server {
+    addr: SocketAddr,
+    connections: Arc<AtomicUsize>,
+    open: Arc<AtomicUsize>,
+    requests: Arc<AtomicUsize>,
+}
+
+impl Server {
+    fn start(protocols: &'static [&'static str]) -> Server {
+        Server::with_max_streams(protocols, None)
+    }
+
+    /// A server answering requests to `/stall` with nothing, keeping their streams open,
+    /// and serving at most `max_streams` streams at once on a connection.
+    fn with_max_streams(protocols: &'static [&'static str], max_streams: Option<u32>) -> Server {
+        let listener = StdListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let connections = Arc::new(AtomicUsize::new(0));
+        let open = Arc::new(AtomicUsize::new(0));
+        let requests = Arc::new(AtomicUsize::new(0));
+
+        let mut acceptor = native_tls::TlsAcceptor::builder(Identity::from_pkcs8(CERT, KEY).unwrap());
+        acceptor.accept_alpn(protocols);
+        let acceptor = tokio_tls::TlsAcceptor::from(acceptor.build().unwrap());
+
+        let (counted, opened, served) = (connections.clone(), open.clone(), requests.clone());
+
+        thread::spawn(move || {
+            let listener = TcpListener::from_std(listener, &Handle::default()).unwrap();
+
+            tokio::run(listener.incoming().map_err(|_| ()).for_each(move |tcp| {
+                counted.fetch_add(1, Ordering::SeqCst);
+                opened.fetch_add(1, Ordering::SeqCst);
+                let (opened, served) = (opened.clone(), served.clone());
+
+                tokio::spawn(acceptor.accept(tcp).map_err(|_| ()).and_then(move |tls| {
+                    let h2 = tls.get_ref().negotiated_alpn().unwrap().as_deref() == Some(&b"h2"[..]);
+
+                    if h2 {
+                        serve_h2(tls, max_streams, served)
+                    } else {
+                        serve_http1(tls, served)
+                    }
+                }).then(move |_| {
+                    opened.fetch_sub(1, Ordering::SeqCst);
+                    Ok(())
+                }));
+
+                Ok(())
+            }));
+        });
+
+        Server { addr, connections, open, requests }
+    }
+
+    fn uri(&self, path: &str) -> Uri {
+        format!("https://{}{}", self.addr, path).parse().unwrap()
+    }
+
+    fn connections(&self) -> usize {
+        self.connections.load(Ordering::SeqCst)
+    }
+
+    /// Waits up to a second for the client to close all connections but `n`.
+    fn wait_for_open(&self, n: usize) -> usize {
+        for _ in 0 .. 100 {
+            if self.open.load(Ordering::SeqCst) <= n {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        self.open.load(Ordering::SeqCst)
+    }
+
+    fn requests(&self) -> usize {
+        self.requests.load(Ordering::SeqCst)
+    }
+}
+
+type Served = Box<dyn Future<Item = (), Error = ()> + Send>;
+
+fn serve_h2(tls: tokio_tls::TlsStream<tokio::net::TcpStream>, max_streams: Option<u32>, served: Arc<AtomicUsize>) -> Served {
+    let mut builder = h2::server::Builder::new();
+    if let Some(max) = max_streams {
+        builder.max_concurrent_streams(max);
+    }
+
+    Box::new(builder.handshake(tls).and_then(move |conn| conn.for_each(move |(req, mut respond)| {
+        served.fetch_add(1, Ordering::SeqCst);
+        let path = req.uri().path().to_string();
+
+        if path == "/stall" {
+            mem::forget(respond);
+            return Ok(());
+        }
+
+        tokio::spawn(req.into_body().concat2().map_err(|_| ()).map(move |body| {
+            let res = http::Response::builder().body(()).unwrap();
+            let mut send = respond.send_response(res, false).unwrap();
+            let mut answer = format!("h2 {} ", path).into_bytes();
+            answer.extend_from_slice(&body);
+            send.send_data(Bytes::from(answer), true).unwrap();
+        }));
+
+        Ok(())
+    })).map_err(|_| ()))
+}
+
+/// Answers one `GET` and closes the connection.
+fn serve_http1(tls: tokio_tls::TlsStream<tokio::net::TcpStream>, served: Arc<AtomicUsize>) -> Served {
+    Box::new(tokio_io::io::read(tls, vec![0; 4096]).map_err(|_| ()).and_then(move |(tls, buf, len)| {
+        served.fetch_add(1, Ordering::SeqCst);
+
+        let head = String::from_utf8_lossy(&buf[.. len]);
+        let path = head.split(' ').nth(1).unwrap_or("").to_string();
+        let answer = format!("http/1.1 {} ", path);
+        let res = format!("HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{}", answer.len(), answer);
+
+        tokio_io::io::write_all(tls, res).map(|_| ()).map_err(|_| ())
+    }))
+}
+
+/// A client trusting the certificate of the server.
+fn client() -> Client<HttpsConnector<HttpConnector>> {
+    let mut tls = native_tls::TlsConnector::builder();
+    tls.add_root_certificate(Certificate::from_pem(CERT).unwrap());
+    tls.request_alpns(&["h2", "http/1.1"]);
+
+    let mut http = HttpConnector::try_new(1).unwrap();
+    http.enforce_http(false);
+
+    Client::builder().build(HttpsConnector::from((http, tls.build().unwrap())))
+}
+
+async fn fetch(client: &Client<HttpsConnector<HttpConnector>>, req: Request) -> (Version, String) {
+    let res: Response<Body> = client.request(req).await.unwrap();
+    let version = res.version();
+    let mut body = res.into_body();
+    let mut buf = Vec::new();
+
+    while let Some(chunk) = body.next().await {
+        buf.extend_from_slice(&chunk.unwrap());
+    }
+
+    (version, String::from_utf8(buf).unwrap())
+}
+
+fn get(client: &Client<HttpsConnector<HttpConnector>>, uri: Uri) -> (Version, String) {
+    block_on(fetch(client, Request::builder().uri(uri).body(RequestBody::empty()).unwrap()))
+}
+
+#[test]
+fn negotiates_http2() {
+    let server = Server::start(&["h2", "http/1.1"]);
+
+    assert_eq!(get(&client(), server.uri("/a")), (Version::HTTP_2, "h2 /a ".to_string()));
+}
+
+#[test]
+fn falls_back_to_http1() {
+    let server = Server::start(&["http/1.1"]);
+    let client = client();
+
+    assert_eq!(get(&client, server.uri("/a")), (Version::HTTP_11, "http/1.1 /a ".to_string()));
+
+    // nor does a server without ALPN speak HTTP/2
+    let server = Server::start(&[]);
+    assert_eq!(get(&client, server.uri("/b")), (Version::HTTP_11, "http/1.1 /b ".to_string()));
+}
+
+#[test]
+fn shares_one_connection_between_streams() {
+    let server = Server::start(&["h2"]);
+    let client = client();
+
+    for path in &["/a", "/b", "/c"] {
+        assert_eq!(get(&client, server.uri(path)).1, format!("h2 {} ", path));
+    }
+
+    // concurrent requests, each on a stream of its own
+    let answers = block_on(futures::future::join_all((0 .. 5).map(|n| {
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri(server.uri(&format!("/{}", n)))
+            .body(RequestBody::from(format!("body {}", n)))
+            .unwrap();
+
+        fetch(&client, req)
+    })));
+
+    for (n, (version, answer)) in answers.into_iter().enumerate() {
+        assert_eq!(version, Version::HTTP_2);
+        assert_eq!(answer, format!("h2 /{} body {}", n, n));
+    }
+
+    assert_eq!(server.requests(), 8);
+    assert_eq!(server.connections(), 1);
+}
+
+#[test]
+fn drives_connections_on_the_runtime_making_them() {
+    let server = Server::start(&["h2"]);
+    let client = client();
+
+    let mut runtime = tokio::runtime::current_thread::Runtime::new().unwrap();
+    let answer = runtime.block_on(async {
+        let req = Request::builder().uri(server.uri("/a")).body(RequestBody::empty()).unwrap();
+        Ok::<_, ()>(fetch(&client, req).await)
+    }.boxed().compat()).unwrap();
+
+    assert_eq!(answer, (Version::HTTP_2, "h2 /a ".to_string()));
+
+    // the connection ends with the runtime, the next request opens another one
+    drop(runtime);
+    assert_eq!(get(&client, server.uri("/b")), (Version::HTTP_2, "h2 /b ".to_string()));
+    assert_eq!(server.connections(), 2);
+}
+
+#[test]
+fn keeps_one_of_the_connections_opened_at_once() {
+    let server = Server::start(&["h2"]);
+    let client = client();
+
+    // the first requests to the origin all connect, one connection is kept
+    let answers = block_on(futures::future::join_all((0 .. 5).map(|n| {
+        let req = Request::builder().uri(server.uri(&format!("/{}", n))).body(RequestBody::empty()).unwrap();
+        fetch(&client, req)
+    })));
+
+    for (n, (version, answer)) in answers.into_iter().enumerate() {
+        assert_eq!(version, Version::HTTP_2);
+        assert_eq!(answer, format!("h2 /{} ", n));
+    }
+
+    let connections = server.connections();
+    assert_eq!(server.wait_for_open(1), 1);
+
+    for path in &["/a", "/b"] {
+        assert_eq!(get(&client, server.uri(path)).1, format!("h2 {} ", path));
+    }
+
+    assert_eq!(server.connections(), connections);
+    assert_eq!(server.open.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn times_out_waiting_for_a_stream() {
+    let server = Server::with_max_streams(&["h2"], Some(1));
+    let client = Arc::new(client());
+
+    let stall = |client: Arc<Client<HttpsConnector<HttpConnector>>>, uri: Uri| thread::spawn(move || {
+        let _ = block_on(client.request(Request::builder().uri(uri).body(RequestBody::empty()).unwrap()));
+    });
+
+    // one stream is open, another one waits for it to end
+    stall(client.clone(), server.uri("/stall"));
+    while server.requests() == 0 {
+        thread::sleep(Duration::from_millis(10));
+    }
+    stall(client.clone(), server.uri("/stall"));
+    thread::sleep(Duration::from_millis(200));
+
+    let req = Request::builder().uri(server.uri("/a")).timeout(Duration::from_millis(300)).body(RequestBody::empty()).unwrap();
+    let err = block_on(client.request(req)).err().unwrap();
+    assert_eq!(err.timeout_phase(), Some(TimeoutPhase::Total));
+    assert_eq!(server.connections(), 1);
+}